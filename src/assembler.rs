@@ -0,0 +1,750 @@
+use crate::encoder::*;
+use crate::instruction::*;
+use crate::prelude::*;
+
+use anyhow::Context;
+
+use std::collections::HashMap;
+
+//* Alternate names nasm accepts for the same conditional jump opcodes
+const JUMP_ALIASES: [(&str, &str); 16] = [
+    ("jz", "je"),
+    ("jnz", "jne"),
+    ("jnge", "jl"),
+    ("jng", "jle"),
+    ("jge", "jnl"),
+    ("jg", "jnle"),
+    ("jc", "jb"),
+    ("jnae", "jb"),
+    ("jna", "jbe"),
+    ("jnc", "jnb"),
+    ("jae", "jnb"),
+    ("ja", "jnbe"),
+    ("jpe", "jp"),
+    ("jpo", "jnp"),
+    ("loopz", "loope"),
+    ("loopnz", "loopne"),
+];
+
+fn canonical_mnemonic(mnemonic: &str) -> Option<&'static str> {
+    if let Some(name) = [
+        "mov", "int", "int3", "jmp", "call", "ret", "retf", "push", "pop", "inc", "dec",
+    ]
+    .into_iter()
+    .find(|name| *name == mnemonic)
+    {
+        return Some(name);
+    }
+
+    if let Some((name, ..)) = ARITHMETIC_OPCODE_MAPPING
+        .iter()
+        .find(|(name, ..)| *name == mnemonic)
+    {
+        return Some(name);
+    }
+
+    if let Some((_, name)) = FLAG_INSTRUCTION_MAPPING
+        .iter()
+        .find(|(_, name)| *name == mnemonic)
+    {
+        return Some(name);
+    }
+
+    if let Some((_, name)) = CONDITIONAL_JUMP_MAPPING
+        .iter()
+        .find(|(_, name)| *name == mnemonic)
+    {
+        return Some(name);
+    }
+
+    JUMP_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == mnemonic)
+        .map(|(_, name)| *name)
+}
+
+pub fn parse_register(text: &str) -> Option<Register> {
+    (0..16u8)
+        .find(|i| get_register_name(i / 2, i % 2 == 1) == text)
+        .map(|i| Register {
+            reg: i / 2,
+            word: i % 2 == 1,
+        })
+}
+
+pub fn parse_number(text: &str) -> Result<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits.trim()),
+        None => (false, text.trim_start_matches('+').trim()),
+    };
+
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i32::from_str_radix(hex, 16)
+    } else if let Some(hex) = digits
+        .strip_suffix('h')
+        .or_else(|| digits.strip_suffix('H'))
+    {
+        i32::from_str_radix(hex, 16)
+    } else {
+        digits.parse::<i32>()
+    }
+    .with_context(|| format!("Invalid number `{}`", text))?;
+
+    Ok(if negative { -value } else { value })
+}
+
+//* Parses the inside of `[...]`, e.g. `bx + si + -4`, `word bx + 4` or `2555`
+fn parse_address(text: &str) -> Result<EffectiveAddress> {
+    //* `byte`/`word` inside the brackets forces the displacement size
+    let mut explicit_displacement_size = None;
+    let mut text = text.trim();
+    for (keyword, size) in [("byte", 1), ("word", 2)] {
+        if let Some(rest) = text.strip_prefix(keyword) {
+            if rest.starts_with(char::is_whitespace) {
+                explicit_displacement_size = Some(size);
+                text = rest.trim();
+            }
+        }
+    }
+
+    //* Segment override, e.g. `es:bx + si`
+    let mut segment = None;
+    if let Some((prefix, rest)) = text.split_once(':') {
+        let Some(sr) = SEGMENT_REGISTER_NAME_MAPPING
+            .iter()
+            .position(|name| *name == prefix.trim())
+        else {
+            bail!("Invalid segment override `{}`", prefix);
+        };
+        segment = Some(sr as u8);
+        text = rest.trim();
+    }
+
+    let mut registers = Vec::new();
+    let mut displacement: i32 = 0;
+
+    //* Split on + and -, keeping the sign with the term that follows
+    let mut terms = Vec::new();
+    let mut term = String::new();
+    for c in text.chars() {
+        let is_sign = |c: char| c == '+' || c == '-';
+        if is_sign(c) && !term.trim_matches(is_sign).is_empty() {
+            terms.push(term.trim().to_owned());
+            term.clear();
+        }
+        if !c.is_whitespace() {
+            term.push(c);
+        }
+    }
+    if !term.is_empty() {
+        terms.push(term);
+    }
+
+    for term in terms {
+        let unsigned_term = term.trim_start_matches('+');
+        if ["bx", "bp", "si", "di"].contains(&unsigned_term) {
+            registers.push(unsigned_term.to_owned());
+        } else {
+            displacement += parse_number(&term)?;
+        }
+    }
+
+    if registers.is_empty() {
+        return Ok(EffectiveAddress {
+            rm: None,
+            displacement: displacement as i16,
+            displacement_size: 2,
+            segment,
+        });
+    }
+
+    let registers_str = registers.join(" + ");
+    let Some(rm) = MEM_ADDR_MODE_MAPPING
+        .iter()
+        .position(|mode| *mode == registers_str)
+    else {
+        bail!("Invalid effective address `{}`", text);
+    };
+
+    let mut address = EffectiveAddress {
+        rm: Some(rm as u8),
+        displacement: displacement as i16,
+        displacement_size: 0,
+        segment,
+    };
+    address.displacement_size = explicit_displacement_size
+        .unwrap_or_default()
+        .max(address.minimal_displacement_size());
+
+    Ok(address)
+}
+
+fn parse_memory_label(text: &str) -> Option<ParsedOperand> {
+    let (segment, name) = match text.split_once(':') {
+        Some((prefix, name)) => {
+            let sr = SEGMENT_REGISTER_NAME_MAPPING
+                .iter()
+                .position(|sr_name| *sr_name == prefix.trim())?;
+            (Some(sr as u8), name.trim())
+        }
+        None => (None, text.trim()),
+    };
+
+    let is_name = is_label_name(name)
+        && !name.starts_with(|c: char| c.is_ascii_digit() || c == '$')
+        && !["bx", "bp", "si", "di"].contains(&name);
+
+    is_name.then(|| ParsedOperand::MemoryLabel(segment, name.to_owned()))
+}
+
+fn is_label_name(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+enum ParsedOperand {
+    Operand(Operand),
+    Immediate(i32),
+    Label(String),
+    //* `[name]` or `[es:name]`, a direct address given by a label
+    MemoryLabel(Option<u8>, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Instruction(Instruction),
+    Data(Vec<u8>),
+}
+
+//* Byte index in the data of each `dw label` word
+type DataLabels = Vec<(usize, String)>;
+
+struct ParsedLine {
+    statement: Statement,
+    jump_label: Option<String>,
+    memory_label: Option<(usize, String)>,
+    data_labels: DataLabels,
+    line_number: usize,
+    //* A jmp to a label without a size hint, its size is picked once the labels are placed
+    relaxable: bool,
+}
+
+fn parse_operand(
+    text: &str,
+    size_hint: &mut Option<bool>,
+    is_strict: &mut bool,
+    is_short: &mut bool,
+    is_near: &mut bool,
+    is_far: &mut bool,
+) -> Result<ParsedOperand> {
+    let mut text = text.trim();
+
+    if let Some(rest) = text.strip_prefix("strict ") {
+        *is_strict = true;
+        text = rest.trim();
+    }
+
+    //* `jmp short label` picks the two byte encoding, `jmp near label` the three byte one
+    if let Some(rest) = text.strip_prefix("short ") {
+        *is_short = true;
+        text = rest.trim();
+    }
+    if let Some(rest) = text.strip_prefix("near ") {
+        *is_near = true;
+        text = rest.trim();
+    }
+
+    //* `call far [bx]` goes through a segment:offset pointer
+    if let Some(rest) = text.strip_prefix("far") {
+        if rest.starts_with(char::is_whitespace) || rest.starts_with('[') {
+            *is_far = true;
+            text = rest.trim();
+        }
+    }
+
+    //* `byte`/`word` may come before either the memory operand or the immediate
+    for (keyword, word) in [("byte", false), ("word", true)] {
+        if let Some(rest) = text.strip_prefix(keyword) {
+            if rest.starts_with(char::is_whitespace) || rest.starts_with('[') {
+                *size_hint = Some(word);
+                text = rest.trim();
+            }
+        }
+    }
+
+    if let Some(register) = parse_register(text) {
+        return Ok(ParsedOperand::Operand(Operand::Register(register)));
+    }
+
+    if let Some(sr) = SEGMENT_REGISTER_NAME_MAPPING
+        .iter()
+        .position(|name| *name == text)
+    {
+        return Ok(ParsedOperand::Operand(Operand::SegmentRegister(sr as u8)));
+    }
+
+    if let Some(inner) = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+    {
+        if let Some(label) = parse_memory_label(inner) {
+            return Ok(label);
+        }
+
+        return Ok(ParsedOperand::Operand(Operand::Memory(parse_address(
+            inner,
+        )?)));
+    }
+
+    if text.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+') {
+        return Ok(ParsedOperand::Immediate(parse_number(text)?));
+    }
+
+    Ok(ParsedOperand::Label(text.to_owned()))
+}
+
+fn parse_instruction(text: &str, line_number: usize) -> Result<ParsedLine> {
+    let (mnemonic, operands_str) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, rest)) => (mnemonic, rest.trim()),
+        None => (text, ""),
+    };
+
+    let mnemonic = mnemonic.to_lowercase();
+    let Some(mnemonic) = canonical_mnemonic(&mnemonic) else {
+        bail!("Unsupported instruction `{}`", mnemonic);
+    };
+
+    let mut size_hint = None;
+    let mut is_strict = false;
+    let mut is_short = false;
+    let mut is_near = false;
+    let mut is_far = false;
+    let mut parsed_operands = Vec::new();
+    if !operands_str.is_empty() {
+        for operand_str in operands_str.split(',') {
+            parsed_operands.push(parse_operand(
+                operand_str,
+                &mut size_hint,
+                &mut is_strict,
+                &mut is_short,
+                &mut is_near,
+                &mut is_far,
+            )?);
+        }
+    }
+    if is_short && mnemonic != "jmp" {
+        bail!("Only `jmp` has a short form");
+    }
+    if is_near && !matches!(mnemonic, "jmp" | "call") {
+        bail!("Only `call` and `jmp` have a near form");
+    }
+    if is_near && (is_short || is_far) {
+        bail!("`near` can't be combined with `short` or `far`");
+    }
+
+    //* Like nasm, word immediates shrink to a sign extended byte unless `strict`
+    let has_sign_extended_form = ARITHMETIC_OPCODE_MAPPING
+        .iter()
+        .any(|(opname, ..)| *opname == mnemonic);
+
+    let mut instruction = Instruction {
+        mnemonic,
+        //* The encoder tells short jumps apart by their size
+        size: if is_short { 2 } else { 0 },
+        ..Default::default()
+    };
+    let mut jump_label = None;
+    let mut memory_label = None;
+
+    //* The width comes from a register operand, otherwise from the explicit size hint
+    let register_width = parsed_operands.iter().find_map(|operand| match operand {
+        ParsedOperand::Operand(Operand::Register(register)) => Some(register.word),
+        ParsedOperand::Operand(Operand::SegmentRegister(_)) => Some(true),
+        _ => None,
+    });
+    //* Interrupt types are always a byte, the bytes ret pops always a word
+    let word = register_width.or(size_hint).or(match mnemonic {
+        "int" => Some(false),
+        "ret" | "retf" => Some(true),
+        _ => None,
+    });
+
+    for (i, parsed_operand) in parsed_operands.into_iter().enumerate() {
+        if i >= 2 {
+            bail!("Too many operands for `{}`", mnemonic);
+        }
+
+        instruction.operands[i] = Some(match parsed_operand {
+            ParsedOperand::Operand(operand) => operand,
+            ParsedOperand::Immediate(value) => {
+                let Some(word) = word else {
+                    bail!("Operation size not specified for `{}`", text);
+                };
+                let value = if word {
+                    value as u16
+                } else {
+                    value as u8 as u16
+                };
+                let fits_in_i8 = (i8::MIN as i32..=i8::MAX as i32).contains(&(value as i16 as i32));
+                Operand::Immediate(Immediate {
+                    value,
+                    word,
+                    sign_extended: has_sign_extended_form && word && fits_in_i8 && !is_strict,
+                })
+            }
+            ParsedOperand::Label(label) => {
+                jump_label = Some(label);
+                //* Resolved once every label's offset is known
+                Operand::JumpTarget(0)
+            }
+            ParsedOperand::MemoryLabel(segment, label) => {
+                memory_label = Some((i, label));
+                //* Direct addresses are always two bytes, so the size is known already
+                Operand::Memory(EffectiveAddress {
+                    rm: None,
+                    displacement: 0,
+                    displacement_size: 2,
+                    segment,
+                })
+            }
+        });
+    }
+
+    //* A lone memory operand has nothing else to take its size from
+    let is_transfer = matches!(mnemonic, "call" | "jmp");
+    if let [Some(Operand::Memory(_)), None] = instruction.operands {
+        instruction.memory_size = match (is_far, size_hint) {
+            (true, _) if is_transfer => Some(MemorySize::Far),
+            (true, _) => bail!("Only `call` and `jmp` have a far form"),
+            (false, Some(false)) if is_transfer || mnemonic == "push" => {
+                bail!("`{}` operates on words", mnemonic)
+            }
+            (false, Some(word)) => Some(if word {
+                MemorySize::Word
+            } else {
+                MemorySize::Byte
+            }),
+            //* Like nasm, call and jmp through memory are near unless told otherwise
+            (false, None) if is_transfer => Some(MemorySize::Word),
+            (false, None) => None,
+        };
+    } else if is_far {
+        bail!("`far` needs a memory operand");
+    }
+
+    //* Like nasm, a jmp starts short and only grows to near if its target is out of range
+    let relaxable = mnemonic == "jmp" && jump_label.is_some() && !is_short && !is_near;
+    if relaxable {
+        instruction.size = 2;
+    }
+
+    Ok(ParsedLine {
+        statement: Statement::Instruction(instruction),
+        jump_label,
+        memory_label,
+        data_labels: Vec::new(),
+        line_number,
+        relaxable,
+    })
+}
+
+//* Splits on commas that are not inside a quoted string
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut operand = String::new();
+    let mut quote = None;
+
+    for c in text.chars() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (',', None) => {
+                operands.push(operand.trim().to_owned());
+                operand.clear();
+                continue;
+            }
+            _ => {}
+        }
+        operand.push(c);
+    }
+    if !operand.trim().is_empty() {
+        operands.push(operand.trim().to_owned());
+    }
+
+    operands
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (';', None) => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+//* `db` with numbers and quoted strings, `dw` with numbers and labels.
+//* Labels are zero until resolved, the returned indices say where they go
+fn parse_data(directive: &str, operands_str: &str) -> Result<(Vec<u8>, DataLabels)> {
+    let mut bytes = Vec::new();
+    let mut labels = Vec::new();
+
+    for operand in split_operands(operands_str) {
+        let quoted = operand
+            .strip_prefix('\'')
+            .and_then(|operand| operand.strip_suffix('\''))
+            .or_else(|| {
+                operand
+                    .strip_prefix('"')
+                    .and_then(|operand| operand.strip_suffix('"'))
+            });
+
+        match (directive, quoted) {
+            ("db", Some(string)) => bytes.extend_from_slice(string.as_bytes()),
+            ("db", None) => bytes.push(parse_number(&operand)? as u8),
+            ("dw", None) if operand.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+                labels.push((bytes.len(), operand));
+                bytes.extend_from_slice(&[0, 0]);
+            }
+            ("dw", None) => {
+                bytes.extend_from_slice(&(parse_number(&operand)? as u16).to_le_bytes())
+            }
+            _ => bail!("Invalid {} operand `{}`", directive, operand),
+        }
+    }
+
+    Ok((bytes, labels))
+}
+
+//* Encodes already structured instructions back to back,
+//* jump targets are the absolute offsets the decoder produced
+pub fn assemble_instructions(instructions: &[Instruction]) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    for instruction in instructions {
+        bytes.extend(encode_instruction(instruction)?);
+    }
+
+    Ok(bytes)
+}
+
+//* Parses the nasm subset the formatter emits
+fn jump_target(
+    label: &str,
+    instruction: &Instruction,
+    label_offsets: &HashMap<String, usize>,
+    line_number: usize,
+) -> Result<usize> {
+    match label.strip_prefix('$') {
+        //* `$` is the start of the current instruction
+        Some(relative) => Ok((instruction.offset as i32 + parse_number(relative)?) as usize),
+        None => match label_offsets.get(label) {
+            Some(target) => Ok(*target),
+            None => bail!("line {}: undefined label `{}`", line_number, label),
+        },
+    }
+}
+
+pub fn parse_program(source: &str) -> Result<(Vec<Statement>, HashMap<usize, String>)> {
+    let mut parsed_lines = Vec::new();
+    let mut label_offsets: HashMap<String, usize> = HashMap::new();
+    //* Labels in the program and the index of the line they are placed before
+    let mut label_lines = Vec::new();
+
+    //* First pass: apart from jmps to labels every supported encoding has a fixed size,
+    //* so offsets are known before the labels are resolved
+    let mut offset = 0;
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let mut line = strip_comment(line).trim();
+
+        if line.is_empty() || line.starts_with("bits") {
+            continue;
+        }
+
+        //* `name equ 1000` names an address that isn't in the program, e.g. a variable
+        if let [label, "equ", value] = line.split_whitespace().collect::<Vec<_>>()[..] {
+            if !is_label_name(label) {
+                bail!("line {}: invalid label `{}`", line_number, label);
+            }
+            let address = parse_number(value)? as u16 as usize;
+            if label_offsets.insert(label.to_owned(), address).is_some() {
+                bail!("line {}: label `{}` defined twice", line_number, label);
+            }
+            continue;
+        }
+
+        if let Some((label, rest)) = line
+            .split_once(':')
+            .filter(|(label, _)| is_label_name(label.trim()))
+        {
+            if label_offsets
+                .insert(label.trim().to_owned(), offset)
+                .is_some()
+            {
+                bail!(
+                    "line {}: label `{}` defined twice",
+                    line_number,
+                    label.trim()
+                );
+            }
+            label_lines.push((label.trim().to_owned(), parsed_lines.len()));
+            line = rest.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+        }
+
+        if let Some((directive @ ("db" | "dw"), operands_str)) = line
+            .split_once(char::is_whitespace)
+            .map(|(directive, rest)| (directive, rest.trim()))
+        {
+            let (data, data_labels) = parse_data(directive, operands_str)
+                .with_context(|| format!("line {}: `{}`", line_number, line))?;
+            offset += data.len();
+            parsed_lines.push(ParsedLine {
+                statement: Statement::Data(data),
+                jump_label: None,
+                memory_label: None,
+                data_labels,
+                line_number,
+                relaxable: false,
+            });
+            continue;
+        }
+
+        let mut parsed_line = parse_instruction(line, line_number)
+            .with_context(|| format!("line {}: `{}`", line_number, line))?;
+
+        if let Statement::Instruction(instruction) = &mut parsed_line.statement {
+            instruction.offset = offset;
+
+            if parsed_line.jump_label.is_some() {
+                instruction.operands[0] = Some(Operand::JumpTarget(offset + 2));
+            }
+            instruction.size = encode_instruction(instruction)
+                .with_context(|| format!("line {}: `{}`", line_number, line))?
+                .len();
+
+            offset += instruction.size;
+        }
+        parsed_lines.push(parsed_line);
+    }
+
+    //* Grow the short jmps whose target is out of range until every jump fits, growing one
+    //* only moves the code after it so the sizes settle
+    while parsed_lines.iter().any(|parsed_line| parsed_line.relaxable) {
+        let mut offset = 0;
+        let mut offsets = Vec::with_capacity(parsed_lines.len() + 1);
+        for parsed_line in &mut parsed_lines {
+            offsets.push(offset);
+            offset += match &mut parsed_line.statement {
+                Statement::Instruction(instruction) => {
+                    instruction.offset = offsets[offsets.len() - 1];
+                    instruction.size
+                }
+                Statement::Data(data) => data.len(),
+            };
+        }
+        offsets.push(offset);
+        for (label, line) in &label_lines {
+            label_offsets.insert(label.clone(), offsets[*line]);
+        }
+
+        let mut grown = false;
+        for parsed_line in &mut parsed_lines {
+            let (Some(label), Statement::Instruction(instruction)) =
+                (&parsed_line.jump_label, &mut parsed_line.statement)
+            else {
+                continue;
+            };
+            if !parsed_line.relaxable || instruction.size != 2 {
+                continue;
+            }
+            let target = jump_target(label, instruction, &label_offsets, parsed_line.line_number)?;
+            let increment = target as i32 - (instruction.offset + 2) as i32;
+            if !(i8::MIN as i32..=i8::MAX as i32).contains(&increment) {
+                instruction.size = 3;
+                grown = true;
+            }
+        }
+        if !grown {
+            break;
+        }
+    }
+
+    //* Second pass: resolve labels
+    let mut statements = Vec::new();
+    for mut parsed_line in parsed_lines {
+        if let (Some(label), Statement::Instruction(instruction)) =
+            (&parsed_line.jump_label, &mut parsed_line.statement)
+        {
+            let target = jump_target(label, instruction, &label_offsets, parsed_line.line_number)?;
+            instruction.operands[0] = Some(Operand::JumpTarget(target));
+        }
+
+        if let (Some((i, label)), Statement::Instruction(instruction)) =
+            (&parsed_line.memory_label, &mut parsed_line.statement)
+        {
+            let Some(address) = label_offsets.get(label) else {
+                bail!(
+                    "line {}: undefined label `{}`",
+                    parsed_line.line_number,
+                    label
+                );
+            };
+            if let Some(Operand::Memory(memory)) = &mut instruction.operands[*i] {
+                memory.displacement = *address as u16 as i16;
+            }
+        }
+
+        if let Statement::Data(data) = &mut parsed_line.statement {
+            for (index, label) in &parsed_line.data_labels {
+                let Some(address) = label_offsets.get(label) else {
+                    bail!(
+                        "line {}: undefined label `{}`",
+                        parsed_line.line_number,
+                        label
+                    );
+                };
+                data[*index..*index + 2].copy_from_slice(&(*address as u16).to_le_bytes());
+            }
+        }
+
+        statements.push(parsed_line.statement);
+    }
+
+    let labels = label_offsets
+        .into_iter()
+        .map(|(label, offset)| (offset, label))
+        .collect();
+
+    Ok((statements, labels))
+}
+
+//* Parses and assembles the nasm subset the formatter emits
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    let (statements, _) = parse_program(source)?;
+
+    let mut bytes = Vec::new();
+    for statement in statements {
+        match statement {
+            Statement::Instruction(instruction) => bytes.extend(encode_instruction(&instruction)?),
+            Statement::Data(data) => bytes.extend(data),
+        }
+    }
+
+    Ok(bytes)
+}
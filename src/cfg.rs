@@ -0,0 +1,325 @@
+use crate::instruction::*;
+use crate::jumptables::*;
+use crate::prelude::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EdgeKind {
+    //* Into the next block without a transfer, including the return site after a call
+    Fallthrough,
+    //* Taken branch of a conditional jump
+    Conditional,
+    Unconditional,
+    Call,
+    //* From a ret back to the instruction after each call into its procedure
+    Return,
+    //* Taken branch of loop, loope, loopne and jcxz
+    Loop,
+    //* From an indirect jmp to each entry of its jump table
+    JumpTable,
+}
+
+//* Blocks are identified by the offset of their first instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    //* Offset past the last instruction
+    pub end: usize,
+    pub instructions: Vec<Instruction>,
+    pub successors: Vec<Edge>,
+    pub predecessors: Vec<Edge>,
+}
+
+impl BasicBlock {
+    pub fn last_instruction(&self) -> &Instruction {
+        self.instructions
+            .last()
+            .expect("basic blocks have at least one instruction")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    //* Transfers to offsets that don't start a decoded instruction, e.g. into data
+    pub unresolved: Vec<Edge>,
+}
+
+//* How an instruction leaves its block, None when execution just continues
+pub fn transfer_kind(instruction: &Instruction) -> Option<EdgeKind> {
+    match instruction.mnemonic {
+        "jmp" => Some(EdgeKind::Unconditional),
+        "call" => Some(EdgeKind::Call),
+        "ret" | "retf" => Some(EdgeKind::Return),
+        "loop" | "loope" | "loopne" | "jcxz" => Some(EdgeKind::Loop),
+        mnemonic
+            if CONDITIONAL_JUMP_MAPPING
+                .iter()
+                .any(|(_, jump_str)| *jump_str == mnemonic) =>
+        {
+            Some(EdgeKind::Conditional)
+        }
+        _ => None,
+    }
+}
+
+//* `push bp` followed by `mov bp, sp`, the standard frame setup at the start of a procedure
+pub fn is_prologue(first: &Instruction, second: &Instruction) -> bool {
+    const BP: Register = Register { reg: 5, word: true };
+    const SP: Register = Register { reg: 4, word: true };
+
+    first.mnemonic == "push"
+        && first.operands == [Some(Operand::Register(BP)), None]
+        && second.mnemonic == "mov"
+        && second.operands == [Some(Operand::Register(BP)), Some(Operand::Register(SP))]
+}
+
+impl ControlFlowGraph {
+    //* Block whose instructions cover an offset
+    pub fn block_containing(&self, offset: usize) -> Option<&BasicBlock> {
+        self.blocks
+            .range(..=offset)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| offset < block.end)
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.blocks
+            .values()
+            .flat_map(|block| block.successors.iter())
+    }
+
+    fn add_edge(&mut self, edge: Edge) {
+        let Some(to) = self.blocks.get_mut(&edge.to) else {
+            self.unresolved.push(edge);
+            return;
+        };
+        if to.predecessors.contains(&edge) {
+            return;
+        }
+        to.predecessors.push(edge);
+
+        self.blocks
+            .get_mut(&edge.from)
+            .expect("edges start at a block")
+            .successors
+            .push(edge);
+    }
+
+    //* Call targets and blocks starting with a prologue
+    pub fn detected_procedures(&self) -> BTreeSet<usize> {
+        let mut entries: BTreeSet<usize> = self
+            .edges()
+            .filter(|edge| edge.kind == EdgeKind::Call)
+            .map(|edge| edge.to)
+            .collect();
+
+        entries.extend(self.blocks.values().filter_map(
+            |block| match block.instructions.as_slice() {
+                [first, second, ..] if is_prologue(first, second) => Some(block.start),
+                _ => None,
+            },
+        ));
+
+        entries
+    }
+
+    //* The program entry and every detected procedure
+    pub fn procedure_entries(&self) -> BTreeSet<usize> {
+        let mut entries = self.detected_procedures();
+        entries.extend(self.blocks.keys().next());
+
+        entries
+    }
+
+    //* (caller, callee) pairs of procedure entries
+    pub fn call_graph(&self) -> BTreeSet<(usize, usize)> {
+        let mut calls = BTreeSet::new();
+
+        for entry in self.procedure_entries() {
+            for start in self.procedure_blocks(entry) {
+                calls.extend(
+                    self.blocks[&start]
+                        .successors
+                        .iter()
+                        .filter(|edge| edge.kind == EdgeKind::Call)
+                        .map(|edge| (entry, edge.to)),
+                );
+            }
+        }
+
+        calls
+    }
+
+    //* Blocks a procedure entered at `entry` can reach without following its calls
+    pub fn procedure_blocks(&self, entry: usize) -> BTreeSet<usize> {
+        let mut reached = BTreeSet::new();
+        let mut pending = vec![entry];
+
+        while let Some(start) = pending.pop() {
+            if !reached.insert(start) {
+                continue;
+            }
+
+            for edge in &self.blocks[&start].successors {
+                if !matches!(edge.kind, EdgeKind::Call | EdgeKind::Return) {
+                    pending.push(edge.to);
+                }
+            }
+        }
+
+        reached
+    }
+}
+
+//* Splits linearly decoded instructions into basic blocks. A block starts at the first
+//* instruction, at every transfer target, after every transfer and at every prologue
+pub fn build_cfg(instructions: &[Instruction]) -> Result<ControlFlowGraph> {
+    build_cfg_with_jump_tables(instructions, &[])
+}
+
+//* build_cfg with the targets of recognised jump tables as blocks too
+pub fn build_cfg_with_jump_tables(
+    instructions: &[Instruction],
+    jump_tables: &[JumpTable],
+) -> Result<ControlFlowGraph> {
+    let starts: BTreeSet<usize> = instructions.iter().map(|ins| ins.offset).collect();
+
+    let mut leaders = BTreeSet::new();
+    for table in jump_tables {
+        leaders.extend(
+            table
+                .targets
+                .iter()
+                .filter(|target| starts.contains(target)),
+        );
+    }
+    leaders.extend(instructions.first().map(|ins| ins.offset));
+    for pair in instructions.windows(2) {
+        if is_prologue(&pair[0], &pair[1]) {
+            leaders.insert(pair[0].offset);
+        }
+    }
+    for ins in instructions {
+        if transfer_kind(ins).is_none() {
+            continue;
+        }
+
+        leaders.insert(ins.offset + ins.size);
+        if let Some(target) = ins.jump_target().filter(|target| starts.contains(target)) {
+            leaders.insert(target);
+        }
+    }
+
+    let mut cfg = ControlFlowGraph::default();
+    let mut current: Option<BasicBlock> = None;
+    for ins in instructions {
+        //* Data ends the block before it and is in none
+        if ins.data.is_some() {
+            if let Some(block) = current.take() {
+                cfg.blocks.insert(block.start, block);
+            }
+            continue;
+        }
+
+        if let Some(block) = current.as_ref().filter(|block| block.end != ins.offset) {
+            bail!(
+                "Instruction at {:#x} does not follow the one ending at {:#x}",
+                ins.offset,
+                block.end
+            );
+        }
+
+        if leaders.contains(&ins.offset) {
+            if let Some(block) = current.take() {
+                cfg.blocks.insert(block.start, block);
+            }
+        }
+
+        let block = current.get_or_insert_with(|| BasicBlock {
+            start: ins.offset,
+            end: ins.offset,
+            instructions: Vec::new(),
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+        });
+        block.end = ins.offset + ins.size;
+        block.instructions.push(ins.clone());
+    }
+    if let Some(block) = current {
+        cfg.blocks.insert(block.start, block);
+    }
+
+    let block_ends: Vec<(usize, usize, Instruction)> = cfg
+        .blocks
+        .values()
+        .map(|block| (block.start, block.end, block.last_instruction().clone()))
+        .collect();
+
+    for (start, end, last) in &block_ends {
+        let edge = |to, kind| Edge {
+            from: *start,
+            to,
+            kind,
+        };
+
+        let kind = transfer_kind(last);
+        if let (Some(kind), Some(target)) = (kind, last.jump_target()) {
+            cfg.add_edge(edge(target, kind));
+        }
+
+        //* Everything but jmp and ret can continue with the next instruction
+        let falls_through = !matches!(kind, Some(EdgeKind::Unconditional) | Some(EdgeKind::Return));
+        if falls_through && cfg.blocks.contains_key(end) {
+            cfg.add_edge(edge(*end, EdgeKind::Fallthrough));
+        }
+    }
+
+    for table in jump_tables {
+        let Some(from) = cfg.block_containing(table.jump).map(|block| block.start) else {
+            continue;
+        };
+        for target in &table.targets {
+            cfg.add_edge(Edge {
+                from,
+                to: *target,
+                kind: EdgeKind::JumpTable,
+            });
+        }
+    }
+
+    //* Returns go back to the return site of every call that reaches the ret
+    let calls: Vec<Edge> = cfg
+        .edges()
+        .filter(|edge| edge.kind == EdgeKind::Call)
+        .copied()
+        .collect();
+
+    for call in calls {
+        let return_site = cfg.blocks[&call.from].end;
+        if !cfg.blocks.contains_key(&return_site) {
+            continue;
+        }
+
+        for start in cfg.procedure_blocks(call.to) {
+            if transfer_kind(cfg.blocks[&start].last_instruction()) == Some(EdgeKind::Return) {
+                cfg.add_edge(Edge {
+                    from: start,
+                    to: return_site,
+                    kind: EdgeKind::Return,
+                });
+            }
+        }
+    }
+
+    Ok(cfg)
+}
@@ -0,0 +1,277 @@
+use crate::instruction::*;
+use crate::prelude::*;
+
+use std::str::FromStr;
+
+//* Clocks a word transfer costs on top of the instruction when it needs two bus cycles
+const WORD_TRANSFER_PENALTY: u32 = 4;
+
+//* The 8088 has an 8 bit data bus, so every word transfer takes two bus cycles.
+//* The 8086 only pays that for words at odd addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuModel {
+    #[default]
+    I8086,
+    I8088,
+}
+
+impl FromStr for CpuModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "8086" => Ok(CpuModel::I8086),
+            "8088" => Ok(CpuModel::I8088),
+            _ => bail!("Invalid cpu model `{}`, expected 8086 or 8088", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Clocks {
+    pub base: u32,
+    //* Effective address calculation
+    pub ea: u32,
+    //* Extra bus cycles for word transfers
+    pub penalty: u32,
+}
+
+impl Clocks {
+    pub fn total(&self) -> u32 {
+        self.base + self.ea + self.penalty
+    }
+}
+
+//* Effective address calculation time from the 8086 manual
+pub fn effective_address_clocks(address: &EffectiveAddress) -> u32 {
+    let has_displacement = address.displacement_size > 0;
+
+    let clocks = match address.rm {
+        None => 6,
+        //* si, di, bp, bx
+        Some(0b100..=0b111) => {
+            if has_displacement {
+                9
+            } else {
+                5
+            }
+        }
+        //* bx + si, bp + di
+        Some(0b000) | Some(0b011) => {
+            if has_displacement {
+                11
+            } else {
+                7
+            }
+        }
+        //* bx + di, bp + si
+        Some(_) => {
+            if has_displacement {
+                12
+            } else {
+                8
+            }
+        }
+    };
+
+    if address.segment.is_some() {
+        clocks + 2
+    } else {
+        clocks
+    }
+}
+
+fn is_accumulator(operand: &Operand) -> bool {
+    matches!(operand, Operand::Register(register) if register.reg == 0)
+}
+
+fn is_direct_address(operand: &Operand) -> bool {
+    matches!(operand, Operand::Memory(address) if address.rm.is_none())
+}
+
+struct Timing {
+    base: u32,
+    transfers: u32,
+    //* The accumulator forms encode their address directly, without an EA calculation
+    has_ea: bool,
+}
+
+fn timing(base: u32, transfers: u32) -> Timing {
+    Timing {
+        base,
+        transfers,
+        has_ea: transfers > 0,
+    }
+}
+
+//* Base clocks and the number of memory transfers, per the 8086 manual's instruction timings
+fn base_clocks(instruction: &Instruction, jump_taken: bool) -> Result<Timing> {
+    let mnemonic = instruction.mnemonic;
+
+    //* Indirect transfers read their target, far ones a segment as well
+    let far = instruction.memory_size == Some(MemorySize::Far);
+    match (mnemonic, instruction.operands[0]) {
+        ("jmp", Some(Operand::Register(_))) => return Ok(timing(11, 0)),
+        ("jmp", Some(Operand::Memory(_))) if far => return Ok(timing(24, 2)),
+        ("jmp", Some(Operand::Memory(_))) => return Ok(timing(18, 1)),
+        ("call", Some(Operand::Register(_))) => {
+            return Ok(Timing {
+                base: 16,
+                transfers: 1,
+                has_ea: false,
+            })
+        }
+        ("call", Some(Operand::Memory(_))) if far => return Ok(timing(37, 4)),
+        ("call", Some(Operand::Memory(_))) => return Ok(timing(21, 2)),
+        ("push", Some(Operand::Memory(_))) => return Ok(timing(16, 2)),
+        ("inc" | "dec", Some(Operand::Memory(_))) => return Ok(timing(15, 2)),
+        //* The one byte word register form is faster than the 0xFE/0xFF one
+        ("inc" | "dec", Some(Operand::Register(register))) if register.word => {
+            return Ok(timing(2, 0))
+        }
+        ("inc" | "dec", _) => return Ok(timing(3, 0)),
+        _ => {}
+    }
+
+    match mnemonic {
+        //* Direct transfers always go to their target, call pushes the return address
+        "jmp" => return Ok(timing(15, 0)),
+        "call" => return Ok(timing(19, 1)),
+        "ret" if instruction.operands[0].is_some() => return Ok(timing(12, 1)),
+        "ret" => return Ok(timing(8, 1)),
+        //* Pops cs as well as ip
+        "retf" if instruction.operands[0].is_some() => return Ok(timing(17, 2)),
+        "retf" => return Ok(timing(18, 2)),
+        "push" => return Ok(timing(11, 1)),
+        "pop" => return Ok(timing(8, 1)),
+        _ => {}
+    }
+
+    if instruction.jump_target().is_some() {
+        let (taken, not_taken) = match mnemonic {
+            "jcxz" | "loope" => (18, 6),
+            "loop" => (17, 5),
+            "loopne" => (19, 5),
+            _ => (16, 4),
+        };
+
+        return Ok(timing(if jump_taken { taken } else { not_taken }, 0));
+    }
+
+    if FLAG_INSTRUCTION_MAPPING
+        .iter()
+        .any(|(_, flag_mnemonic)| *flag_mnemonic == mnemonic)
+    {
+        return Ok(timing(2, 0));
+    }
+
+    match mnemonic {
+        "int" => return Ok(timing(51, 0)),
+        "int3" => return Ok(timing(52, 0)),
+        _ => {}
+    }
+
+    let [Some(destination), Some(source)] = &instruction.operands else {
+        bail!("No clock timings for `{}`", mnemonic);
+    };
+
+    let is_arithmetic = ARITHMETIC_OPCODE_MAPPING
+        .iter()
+        .any(|(opname, ..)| *opname == mnemonic);
+
+    let accumulator_form = mnemonic == "mov"
+        && ((is_accumulator(destination) && is_direct_address(source))
+            || (is_direct_address(destination) && is_accumulator(source)));
+    if accumulator_form {
+        return Ok(Timing {
+            base: 10,
+            transfers: 1,
+            has_ea: false,
+        });
+    }
+
+    let (base, transfers) = match (destination, source) {
+        (Operand::Memory(_), Operand::Immediate(_)) if mnemonic == "mov" => (10, 1),
+        (Operand::Memory(_), _) if mnemonic == "mov" => (9, 1),
+        (_, Operand::Memory(_)) if mnemonic == "mov" => (8, 1),
+        (_, Operand::Immediate(_)) if mnemonic == "mov" => (4, 0),
+        (..) if mnemonic == "mov" => (2, 0),
+        (Operand::Register(_), Operand::Register(_)) if is_arithmetic => (3, 0),
+        (Operand::Register(_), Operand::Memory(_)) if is_arithmetic => (9, 1),
+        (Operand::Register(_), Operand::Immediate(_)) if is_arithmetic => (4, 0),
+        //* cmp only reads its destination
+        (Operand::Memory(_), Operand::Register(_)) if mnemonic == "cmp" => (9, 1),
+        (Operand::Memory(_), Operand::Immediate(_)) if mnemonic == "cmp" => (10, 1),
+        (Operand::Memory(_), Operand::Register(_)) if is_arithmetic => (16, 2),
+        (Operand::Memory(_), Operand::Immediate(_)) if is_arithmetic => (17, 2),
+        _ => bail!(
+            "No clock timings for `{}`",
+            format_instruction(instruction, &Default::default())
+        ),
+    };
+
+    Ok(timing(base, transfers))
+}
+
+//* `address` is the offset the memory operand resolved to, when it is known.
+//* Without it a word at a register based address is assumed to be aligned on the 8086
+pub fn estimate_clocks(
+    instruction: &Instruction,
+    model: CpuModel,
+    address: Option<u16>,
+    jump_taken: bool,
+) -> Result<Clocks> {
+    let timing = base_clocks(instruction, jump_taken)?;
+
+    let memory_operand = instruction.operands().find_map(|operand| match operand {
+        Operand::Memory(address) => Some(address),
+        _ => None,
+    });
+
+    let ea = match memory_operand {
+        Some(memory) if timing.has_ea => effective_address_clocks(memory),
+        _ => 0,
+    };
+
+    let address = address.or(memory_operand
+        .filter(|memory| memory.rm.is_none())
+        .map(|memory| memory.displacement as u16));
+
+    let is_word = instruction.is_word().unwrap_or(true);
+    let is_split_transfer = match model {
+        CpuModel::I8088 => is_word,
+        CpuModel::I8086 => is_word && address.is_some_and(|address| address % 2 == 1),
+    };
+
+    let penalty = if is_split_transfer {
+        timing.transfers * WORD_TRANSFER_PENALTY
+    } else {
+        0
+    };
+
+    Ok(Clocks {
+        base: timing.base,
+        ea,
+        penalty,
+    })
+}
+
+//* `Clocks: +13 = 13 (8 + 5ea)`, with the running total after this instruction
+pub fn format_clocks(clocks: &Clocks, total: u64) -> String {
+    let mut output = format!("Clocks: +{} = {}", clocks.total(), total);
+
+    if clocks.ea != 0 || clocks.penalty != 0 {
+        output.push_str(&format!(" ({}", clocks.base));
+
+        if clocks.ea != 0 {
+            output.push_str(&format!(" + {}ea", clocks.ea));
+        }
+        if clocks.penalty != 0 {
+            output.push_str(&format!(" + {}p", clocks.penalty));
+        }
+
+        output.push(')');
+    }
+
+    output
+}
@@ -0,0 +1,84 @@
+use crate::instruction::*;
+use crate::prelude::*;
+
+use std::{io::IsTerminal, str::FromStr};
+
+const RESET: &str = "\x1b[0m";
+
+//* Highlights each token kind of the listing with ANSI escape codes
+pub struct AnsiStyle;
+
+impl AnsiStyle {
+    fn color(kind: TokenKind) -> Option<&'static str> {
+        match kind {
+            TokenKind::Mnemonic => Some("\x1b[1;34m"),
+            TokenKind::Register => Some("\x1b[32m"),
+            TokenKind::Immediate => Some("\x1b[33m"),
+            TokenKind::Memory => Some("\x1b[36m"),
+            TokenKind::SizeHint => Some("\x1b[2;37m"),
+            TokenKind::JumpTarget => Some("\x1b[4;35m"),
+            TokenKind::Label => Some("\x1b[1;35m"),
+            TokenKind::Punctuation => None,
+        }
+    }
+}
+
+impl Style for AnsiStyle {
+    fn write_token(&self, output: &mut String, kind: TokenKind, text: &str) -> Result<()> {
+        match Self::color(kind) {
+            Some(color) => {
+                output.push_str(color);
+                output.push_str(text);
+                output.push_str(RESET);
+            }
+            None => output.push_str(text),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    //* Auto only colors when stdout is a terminal and NO_COLOR is not set
+    pub fn should_colorize(&self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+            }
+        }
+    }
+
+    pub fn style(&self) -> &'static dyn Style {
+        if self.should_colorize() {
+            &AnsiStyle
+        } else {
+            &PlainStyle
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => bail!(
+                "Invalid color choice `{}`, expected auto, always or never",
+                s
+            ),
+        }
+    }
+}
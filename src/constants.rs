@@ -10,10 +10,60 @@ pub fn get_register_name(reg_val: u8, word: bool) -> &'static str {
     REGISTER_NAME_MAPPING[(reg_val * 2 + word_val) as usize]
 }
 
+pub const SEGMENT_REGISTER_NAME_MAPPING: [&str; 4] = ["es", "cs", "ss", "ds"];
+
 pub const MEM_ADDR_MODE_MAPPING: [&str; 8] = [
     "bx + si", "bx + di", "bp + si", "bp + di", "si", "di", "bp", "bx",
 ];
 
+//* Short jumps with an 8 bit signed increment, opcode to mnemonic
+pub const CONDITIONAL_JUMP_MAPPING: [(u8, &str); 20] = [
+    (0b01110100, "je"),
+    (0b01111100, "jl"),
+    (0b01111110, "jle"),
+    (0b01110010, "jb"),
+    (0b01110110, "jbe"),
+    (0b01111010, "jp"),
+    (0b01110000, "jo"),
+    (0b01111000, "js"),
+    (0b01110101, "jne"),
+    (0b01111101, "jnl"),
+    (0b01111111, "jnle"),
+    (0b01110011, "jnb"),
+    (0b01110111, "jnbe"),
+    (0b01111011, "jnp"),
+    (0b01110001, "jno"),
+    (0b01111001, "jns"),
+    (0b11100010, "loop"),
+    (0b11100001, "loope"),
+    (0b11100000, "loopne"),
+    (0b11100011, "jcxz"),
+];
+
+//* (opname, reg/memory with register opcode, immediate to accumulator opcode, group reg field)
+//* mirrors the arguments decode_add, decode_sub etc. pass to decode_add_sub_cmp
+pub const ARITHMETIC_OPCODE_MAPPING: [(&str, u8, u8, u8); 8] = [
+    ("add", 0b000000, 0b0000010, 0b000),
+    ("or", 0b000010, 0b0000110, 0b001),
+    ("adc", 0b000100, 0b0001010, 0b010),
+    ("sbb", 0b000110, 0b0001110, 0b011),
+    ("and", 0b001000, 0b0010010, 0b100),
+    ("sub", 0b001010, 0b0010110, 0b101),
+    ("xor", 0b001100, 0b0011010, 0b110),
+    ("cmp", 0b001110, 0b0011110, 0b111),
+];
+
+//* Single byte processor control instructions, opcode to mnemonic
+pub const FLAG_INSTRUCTION_MAPPING: [(u8, &str); 7] = [
+    (0xF5, "cmc"),
+    (0xF8, "clc"),
+    (0xF9, "stc"),
+    (0xFA, "cli"),
+    (0xFB, "sti"),
+    (0xFC, "cld"),
+    (0xFD, "std"),
+];
+
 //* increments num_bytes_in_instruction by one or two depending on boolean flag word */
 pub fn get_byte_or_word(
     instructions: &[u8],
@@ -36,3 +86,11 @@ pub fn get_byte_or_word(
 }
 
 pub type NumBytesInInstruction = usize;
+
+pub fn format_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
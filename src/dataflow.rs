@@ -0,0 +1,463 @@
+use crate::cfg::*;
+use crate::instruction::*;
+use crate::memory::*;
+use crate::prelude::*;
+use crate::simulator::WORD_REGISTER_COUNT;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+//* Registers by index: ax = 0, cx = 1, bx = 3, sp = 4, bp = 5, si = 6, di = 7
+const AX: u8 = 0;
+const CX: u8 = 1;
+const BX: u8 = 3;
+const SP: u8 = 4;
+const BP: u8 = 5;
+const SI: u8 = 6;
+const DI: u8 = 7;
+
+//* What a call or an interrupt may return something in, bp and the other segments are
+//* assumed to survive it
+const CLOBBERED_BY_CALLS: [Location; 7] = [
+    Location::Register(AX),
+    Location::Register(CX),
+    Location::Register(2),
+    Location::Register(BX),
+    Location::Register(SI),
+    Location::Register(DI),
+    Location::Segment(ES),
+];
+
+//* A register the analysis tracks. Byte registers are part of their word register, so
+//* writing al or ah defines ax
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Location {
+    //* Indexed by the reg field of the word register
+    Register(u8),
+    //* Indexes SEGMENT_REGISTER_NAME_MAPPING
+    Segment(u8),
+}
+
+impl Location {
+    pub fn name(self) -> &'static str {
+        match self {
+            Location::Register(reg) => get_register_name(reg, true),
+            Location::Segment(sr) => SEGMENT_REGISTER_NAME_MAPPING[sr as usize],
+        }
+    }
+
+    fn of_register(register: Register) -> Self {
+        match register.word {
+            true => Location::Register(register.reg),
+            false => Location::Register(register.reg & 0b11),
+        }
+    }
+
+    fn of_operand(operand: &Option<Operand>) -> Option<Self> {
+        match operand {
+            Some(Operand::Register(register)) => Some(Self::of_register(*register)),
+            Some(Operand::SegmentRegister(sr)) => Some(Location::Segment(*sr)),
+            _ => None,
+        }
+    }
+}
+
+//* The word registers an addressing mode adds up, indexed by rm
+const ADDRESS_REGISTERS: [&[u8]; 8] = [
+    &[BX, SI],
+    &[BX, DI],
+    &[BP, SI],
+    &[BP, DI],
+    &[SI],
+    &[DI],
+    &[BP],
+    &[BX],
+];
+
+fn address_registers(address: &EffectiveAddress) -> &'static [u8] {
+    match address.rm {
+        Some(rm) => ADDRESS_REGISTERS[rm as usize],
+        None => &[],
+    }
+}
+
+//* Registers an instruction reads and writes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Access {
+    pub uses: BTreeSet<Location>,
+    pub defs: BTreeSet<Location>,
+}
+
+//* `xor ax, ax` and `sub ah, ah` give zero whatever the register held
+fn is_zeroing(instruction: &Instruction) -> bool {
+    matches!(instruction.mnemonic, "xor" | "sub")
+        && matches!(
+            instruction.operands,
+            [Some(Operand::Register(left)), Some(Operand::Register(right))] if left == right
+        )
+}
+
+pub fn access(instruction: &Instruction) -> Access {
+    let mut access = Access::default();
+    let [destination, source] = &instruction.operands;
+
+    //* Addresses read their base and index registers and the segment, whichever side they're on
+    for operand in instruction.operands() {
+        if let Operand::Memory(address) = operand {
+            access.uses.extend(
+                address_registers(address)
+                    .iter()
+                    .map(|reg| Location::Register(*reg)),
+            );
+            access
+                .uses
+                .insert(Location::Segment(effective_segment(address)));
+        }
+    }
+
+    let (uses_destination, defines_destination) = match instruction.mnemonic {
+        "mov" | "pop" => (false, true),
+        _ if is_zeroing(instruction) => (false, true),
+        "add" | "adc" | "sub" | "sbb" | "and" | "or" | "xor" | "inc" | "dec" => (true, true),
+        _ => (true, false),
+    };
+    if let Some(location) = Location::of_operand(destination) {
+        if uses_destination {
+            access.uses.insert(location);
+        }
+        if defines_destination {
+            access.defs.insert(location);
+        }
+    }
+    if let Some(location) = Location::of_operand(source) {
+        if !is_zeroing(instruction) {
+            access.uses.insert(location);
+        }
+    }
+
+    match instruction.mnemonic {
+        "push" | "pop" | "call" | "ret" | "retf" => {
+            access.uses.insert(Location::Register(SP));
+            access.defs.insert(Location::Register(SP));
+        }
+        "loop" | "loope" | "loopne" => {
+            access.uses.insert(Location::Register(CX));
+            access.defs.insert(Location::Register(CX));
+        }
+        "jcxz" => {
+            access.uses.insert(Location::Register(CX));
+        }
+        _ => {}
+    }
+
+    match instruction.mnemonic {
+        "call" => access.defs.extend(CLOBBERED_BY_CALLS),
+        //* AH, and sometimes AL, select the service
+        "int" | "int3" => {
+            access.uses.insert(Location::Register(AX));
+            access.defs.extend(CLOBBERED_BY_CALLS);
+        }
+        _ => {}
+    }
+
+    access
+}
+
+//* What is known about the registers at one point of a block
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterState {
+    //* Low and high byte of each word register, None when unknown
+    bytes: [[Option<u8>; 2]; WORD_REGISTER_COUNT],
+    segments: [Option<u16>; 4],
+    //* Values pushed in the block and not popped yet, the last one on top
+    stack: Vec<Option<u16>>,
+}
+
+impl RegisterState {
+    pub fn get(&self, register: Register) -> Option<u16> {
+        match (register.word, register.reg) {
+            (true, reg) => {
+                let [low, high] = self.bytes[reg as usize];
+                Some(u16::from_le_bytes([low?, high?]))
+            }
+            (false, reg) => self.bytes[(reg & 0b11) as usize][(reg >> 2) as usize].map(u16::from),
+        }
+    }
+
+    pub fn set(&mut self, register: Register, value: Option<u16>) {
+        match (register.word, register.reg) {
+            (true, reg) => {
+                self.bytes[reg as usize] = match value {
+                    Some(value) => value.to_le_bytes().map(Some),
+                    None => [None, None],
+                };
+            }
+            (false, reg) => {
+                self.bytes[(reg & 0b11) as usize][(reg >> 2) as usize] =
+                    value.map(|value| value as u8);
+            }
+        }
+    }
+
+    pub fn segment(&self, sr: u8) -> Option<u16> {
+        self.segments[sr as usize]
+    }
+
+    fn forget(&mut self, location: Location) {
+        match location {
+            Location::Register(reg) => self.set(Register { reg, word: true }, None),
+            Location::Segment(sr) => self.segments[sr as usize] = None,
+        }
+    }
+
+    fn value_of(&self, operand: &Option<Operand>) -> Option<u16> {
+        match operand {
+            Some(Operand::Register(register)) => self.get(*register),
+            Some(Operand::SegmentRegister(sr)) => self.segment(*sr),
+            Some(Operand::Immediate(immediate)) => Some(immediate.value),
+            _ => None,
+        }
+    }
+
+    //* Offset an effective address refers to, when all its registers are known
+    pub fn address_offset(&self, address: &EffectiveAddress) -> Option<u16> {
+        address_registers(address)
+            .iter()
+            .try_fold(address.displacement as u16, |offset, reg| {
+                let value = self.get(Register {
+                    reg: *reg,
+                    word: true,
+                })?;
+                Some(offset.wrapping_add(value))
+            })
+    }
+
+    //* The state after the instruction runs
+    pub fn step(&mut self, instruction: &Instruction) {
+        let [destination, source] = &instruction.operands;
+        let word = instruction.is_word().unwrap_or(true);
+        let mask = if word { 0xFFFF } else { 0xFF };
+        let sp_register = Register {
+            reg: SP,
+            word: true,
+        };
+        let sp = self.get(sp_register);
+
+        let value = match instruction.mnemonic {
+            _ if is_zeroing(instruction) => Some(0),
+            "mov" => self.value_of(source),
+            "pop" => self.stack.pop().flatten(),
+            "inc" => self
+                .value_of(destination)
+                .map(|value| value.wrapping_add(1)),
+            "dec" => self
+                .value_of(destination)
+                .map(|value| value.wrapping_sub(1)),
+            //* adc and sbb depend on the carry, which isn't tracked
+            "add" | "sub" | "and" | "or" | "xor" => {
+                let (left, right) = (self.value_of(destination), self.value_of(source));
+                left.zip(right)
+                    .map(|(left, right)| match instruction.mnemonic {
+                        "add" => left.wrapping_add(right),
+                        "sub" => left.wrapping_sub(right),
+                        "and" => left & right,
+                        "or" => left | right,
+                        _ => left ^ right,
+                    })
+            }
+            _ => None,
+        }
+        .map(|value| value & mask);
+
+        match instruction.mnemonic {
+            "push" => self.stack.push(self.value_of(destination)),
+            //* The values a callee pushes and pops are its own
+            "call" | "int" | "int3" => self.stack.clear(),
+            _ => {}
+        }
+
+        //* The destination is set below, so a byte write keeps the other half of its register
+        let written = Location::of_operand(destination);
+        let defs = access(instruction).defs;
+        for location in defs.iter().filter(|location| Some(**location) != written) {
+            self.forget(*location);
+        }
+
+        match destination {
+            _ if !written.is_some_and(|location| defs.contains(&location)) => {}
+            Some(Operand::Register(register)) => self.set(*register, value),
+            Some(Operand::SegmentRegister(sr)) => self.segments[*sr as usize] = value,
+            _ => {}
+        }
+
+        //* sp moves by a word for every push and pop, whatever was pushed
+        match instruction.mnemonic {
+            "push" => self.set(sp_register, sp.map(|sp| sp.wrapping_sub(2))),
+            "pop" if written != Some(Location::Register(SP)) => {
+                self.set(sp_register, sp.map(|sp| sp.wrapping_add(2)))
+            }
+            _ => {}
+        }
+    }
+}
+
+//* A register an instruction uses, with the instruction in the same block that last
+//* defined it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DefUse {
+    pub location: Location,
+    pub used_at: usize,
+    //* None when the value comes from before the block
+    pub defined_at: Option<usize>,
+}
+
+//* Dataflow within one basic block. Each block starts with nothing known, what reaches it
+//* from its predecessors depends on the path taken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockFlow {
+    pub start: usize,
+    //* Offsets of the block's instructions, in order
+    pub offsets: Vec<usize>,
+    //* Register state before each instruction, followed by the state after the last one
+    pub states: Vec<RegisterState>,
+    pub chains: Vec<DefUse>,
+}
+
+impl BlockFlow {
+    pub fn state_before(&self, offset: usize) -> Option<&RegisterState> {
+        let i = self.offsets.iter().position(|start| *start == offset)?;
+        Some(&self.states[i])
+    }
+
+    //* Offset of the instruction in the block that defined what `used_at` reads from `location`
+    pub fn definition_of(&self, used_at: usize, location: Location) -> Option<usize> {
+        self.chains
+            .iter()
+            .find(|chain| chain.used_at == used_at && chain.location == location)?
+            .defined_at
+    }
+}
+
+pub fn analyse_block(start: usize, instructions: &[Instruction]) -> BlockFlow {
+    let mut state = RegisterState::default();
+    let mut states = vec![state.clone()];
+    let mut chains = Vec::new();
+    let mut definitions: HashMap<Location, usize> = HashMap::new();
+
+    for instruction in instructions {
+        let access = access(instruction);
+
+        chains.extend(access.uses.iter().map(|location| DefUse {
+            location: *location,
+            used_at: instruction.offset,
+            defined_at: definitions.get(location).copied(),
+        }));
+        for location in access.defs {
+            definitions.insert(location, instruction.offset);
+        }
+
+        state.step(instruction);
+        states.push(state.clone());
+    }
+
+    BlockFlow {
+        start,
+        offsets: instructions
+            .iter()
+            .map(|instruction| instruction.offset)
+            .collect(),
+        states,
+        chains,
+    }
+}
+
+//* Dataflow of every block of a control flow graph
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dataflow {
+    pub blocks: BTreeMap<usize, BlockFlow>,
+}
+
+impl Dataflow {
+    pub fn build(cfg: &ControlFlowGraph) -> Self {
+        Self {
+            blocks: cfg
+                .blocks
+                .values()
+                .map(|block| (block.start, analyse_block(block.start, &block.instructions)))
+                .collect(),
+        }
+    }
+
+    //* Register state before the instruction at `offset`
+    pub fn state_before(&self, offset: usize) -> Option<&RegisterState> {
+        let (_, block) = self.blocks.range(..=offset).next_back()?;
+        block.state_before(offset)
+    }
+}
+
+//* Where an indirect call or jmp goes, when the registers it reads are known. Memory
+//* operands are read from the image, as the table of a `call [bx]` is part of the program
+pub fn resolve_indirect_target(
+    instruction: &Instruction,
+    state: &RegisterState,
+    image: &[u8],
+) -> Option<usize> {
+    if !instruction.is_indirect_transfer() || instruction.memory_size == Some(MemorySize::Far) {
+        return None;
+    }
+
+    match instruction.operands[0]? {
+        Operand::Register(register) => state.get(register).map(usize::from),
+        Operand::Memory(address) => {
+            let offset = state.address_offset(&address)? as usize;
+            let bytes = image.get(offset..offset + 2)?;
+            Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+        }
+        _ => None,
+    }
+}
+
+//* `; ds = 0xb800` after segment registers are loaded with a known value and
+//* `; target: proc_0010` before indirect calls and jumps that can be resolved
+pub fn dataflow_comments(
+    dataflow: &Dataflow,
+    cfg: &ControlFlowGraph,
+    image: &[u8],
+    labels: &HashMap<usize, String>,
+) -> HashMap<usize, Vec<String>> {
+    let mut comments: HashMap<usize, Vec<String>> = HashMap::new();
+
+    for block in cfg.blocks.values() {
+        let flow = &dataflow.blocks[&block.start];
+
+        for (i, instruction) in block.instructions.iter().enumerate() {
+            let (before, after) = (&flow.states[i], &flow.states[i + 1]);
+
+            if let (Some(Operand::SegmentRegister(sr)), Some(Operand::Register(_))) =
+                (instruction.operands[0], instruction.operands[1])
+            {
+                if let Some(value) = after.segment(sr) {
+                    comments
+                        .entry(instruction.offset)
+                        .or_default()
+                        .push(format!(
+                            "; {} = {:#06x}",
+                            SEGMENT_REGISTER_NAME_MAPPING[sr as usize], value
+                        ));
+                }
+            }
+
+            if let Some(target) = resolve_indirect_target(instruction, before, image) {
+                let name = match labels.get(&target) {
+                    Some(label) => label.to_owned(),
+                    None => format!("{:#06x}", target),
+                };
+                comments
+                    .entry(instruction.offset)
+                    .or_default()
+                    .push(format!("; target: {}", name));
+            }
+        }
+    }
+
+    comments
+}
@@ -0,0 +1,428 @@
+use crate::assembler::{parse_number, parse_register};
+use crate::flags::*;
+use crate::history::*;
+use crate::instruction::*;
+use crate::memory::*;
+use crate::prelude::*;
+use crate::simulator::*;
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    io::{BufRead, Write as IoWrite},
+};
+
+const PROMPT: &str = "(dbg) ";
+
+const HELP: &str = "\
+step [n]            execute n instructions (default 1)
+continue            run until a breakpoint, a watched word changes or the program ends
+break <ip>          stop before executing the instruction at ip
+delete <ip>         remove a breakpoint
+watch <seg:off>     stop when the word at the address changes
+regs                show every register
+mem <seg:off> <n>   hex dump n bytes
+disasm [ip] [n]     decode n instructions (default 5) starting at ip
+set <reg> <value>   set a register, ip or flags, which starts a new history
+back [n]            undo the last n instructions (default 1)
+rewind <ip> <n>     go back to before the nth execution of the instruction at ip
+last-write <seg:off>
+                    go back to the last instruction that wrote the byte
+quit
+";
+
+struct Watchpoint {
+    segment: u16,
+    offset: u16,
+    value: u16,
+}
+
+pub struct Debugger {
+    pub cpu: Cpu,
+    pub history: History,
+    pub breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+//* Whether the debugger keeps reading commands after one ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Self {
+        Self {
+            history: History::new(&cpu),
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    //* `ds:0x100`, `0x1000:4` or a plain offset into DS
+    fn parse_address(&self, text: &str) -> Result<(u16, u16)> {
+        let (segment, offset) = match text.split_once(':') {
+            Some((segment, offset)) => {
+                let segment = match SEGMENT_REGISTER_NAME_MAPPING
+                    .iter()
+                    .position(|name| *name == segment)
+                {
+                    Some(sr) => self.cpu.state.segment_registers[sr],
+                    None => parse_word(segment)?,
+                };
+                (segment, offset)
+            }
+            None => (self.cpu.state.segment_registers[DS as usize], text),
+        };
+
+        Ok((segment, parse_word(offset)?))
+    }
+
+    //* `=> 0x0003: mov bx, cx`, or that the program ended
+    fn format_next_instruction(&mut self) -> String {
+        if self.cpu.is_halted() {
+            return "Program halted\n".to_owned();
+        }
+
+        match self.cpu.decode_at_ip() {
+            Ok(instruction) => format!(
+                "=> {:#06x}: {}\n",
+                self.cpu.state.ip,
+                format_instruction(&instruction, &Default::default())
+            ),
+            Err(error) => format!("=> {:#06x}: {}\n", self.cpu.state.ip, error),
+        }
+    }
+
+    //* Executes one instruction, reporting any watched word it changed
+    fn step(&mut self, output: &mut String) -> Result<bool> {
+        output.push_str(&self.history.step(&mut self.cpu)?);
+
+        let mut watch_hit = false;
+        for watchpoint in &mut self.watchpoints {
+            let value = self
+                .cpu
+                .memory
+                .read_word(watchpoint.segment, watchpoint.offset);
+
+            if value != watchpoint.value {
+                writeln!(
+                    output,
+                    "watch {:04x}:{:04x}: {:#x}->{:#x}",
+                    watchpoint.segment, watchpoint.offset, watchpoint.value, value
+                )?;
+                watchpoint.value = value;
+                watch_hit = true;
+            }
+        }
+
+        Ok(watch_hit)
+    }
+
+    //* After moving through time the watched words hold whatever they held back then
+    fn refresh_watchpoints(&mut self) {
+        for watchpoint in &mut self.watchpoints {
+            watchpoint.value = self
+                .cpu
+                .memory
+                .read_word(watchpoint.segment, watchpoint.offset);
+        }
+    }
+
+    //* `Step 4`, then the instruction about to execute
+    fn format_position(&mut self) -> String {
+        let step = self.history.step;
+        format!("Step {}\n{}", step, self.format_next_instruction())
+    }
+
+    fn format_registers(&self) -> String {
+        let state = &self.cpu.state;
+        let mut output = String::new();
+
+        for reg in 0..WORD_REGISTER_COUNT {
+            let value = state.registers[reg];
+            let name = get_register_name(reg as u8, true);
+            let _ = writeln!(output, "{:>8}: {:#06x} ({})", name, value, value);
+        }
+
+        for (sr, name) in SEGMENT_REGISTER_NAME_MAPPING.iter().enumerate() {
+            let value = state.segment_registers[sr];
+            let _ = writeln!(output, "{:>8}: {:#06x} ({})", name, value, value);
+        }
+
+        let _ = writeln!(output, "{:>8}: {:#06x} ({})", "ip", state.ip, state.ip);
+        let _ = writeln!(output, "{:>8}: {}", "flags", format_flags(state.flags));
+
+        output
+    }
+
+    //* 16 bytes per line, prefixed with the segment:offset of the first one
+    fn format_memory(&self, segment: u16, offset: u16, length: usize) -> String {
+        let mut output = String::new();
+
+        for line_start in (0..length).step_by(16) {
+            let line_offset = offset.wrapping_add(line_start as u16);
+            let _ = write!(output, "{:04x}:{:04x}:", segment, line_offset);
+
+            for i in line_start..length.min(line_start + 16) {
+                let byte = self
+                    .cpu
+                    .memory
+                    .read_byte(segment, offset.wrapping_add(i as u16));
+                let _ = write!(output, " {:02x}", byte);
+            }
+            output.push('\n');
+        }
+
+        output
+    }
+
+    fn format_disassembly(&mut self, start: u16, count: usize) -> Result<String> {
+        let mut output = String::new();
+
+        let mut ip = start;
+        for _ in 0..count {
+            let instruction = self.cpu.decode_at(ip)?;
+
+            let marker = if ip == self.cpu.state.ip {
+                "=>"
+            } else if self.breakpoints.contains(&ip) {
+                " *"
+            } else {
+                "  "
+            };
+            writeln!(
+                output,
+                "{} {:#06x}: {}",
+                marker,
+                ip,
+                format_instruction(&instruction, &Default::default())
+            )?;
+
+            ip = ip.wrapping_add(instruction.size as u16);
+        }
+
+        Ok(output)
+    }
+
+    fn set(&mut self, name: &str, value: u16) -> Result<()> {
+        if let Some(register) = parse_register(name) {
+            self.cpu.set_register(register, value);
+        } else if let Some(sr) = SEGMENT_REGISTER_NAME_MAPPING
+            .iter()
+            .position(|sr_name| *sr_name == name)
+        {
+            self.cpu.state.segment_registers[sr] = value;
+        } else if name == "ip" {
+            self.cpu.state.ip = value;
+        } else if name == "flags" {
+            self.cpu.state.flags = value;
+        } else {
+            bail!("Unknown register `{}`", name);
+        }
+
+        //* Replaying from a snapshot wouldn't redo the change, so the history starts over here
+        self.history = History::new(&self.cpu);
+
+        Ok(())
+    }
+
+    //* Runs one command line, returning what it printed
+    pub fn execute_command(&mut self, line: &str) -> Result<(Flow, String)> {
+        let mut output = String::new();
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = words.split_first() else {
+            return Ok((Flow::Continue, output));
+        };
+
+        match (*command, args) {
+            ("step" | "s", _) => {
+                let count = match args.first() {
+                    Some(count) => parse_word(count)? as usize,
+                    None => 1,
+                };
+
+                for _ in 0..count {
+                    if self.cpu.is_halted() || self.step(&mut output)? {
+                        break;
+                    }
+                }
+                output.push_str(&self.format_next_instruction());
+            }
+            ("continue" | "c", []) => {
+                //* Always move past the breakpoint we are stopped at
+                while !self.cpu.is_halted() {
+                    if self.step(&mut output)? {
+                        break;
+                    }
+                    if self.breakpoints.contains(&self.cpu.state.ip) {
+                        writeln!(output, "Breakpoint at {:#06x}", self.cpu.state.ip)?;
+                        break;
+                    }
+                }
+                output.push_str(&self.format_next_instruction());
+            }
+            ("break" | "b", [ip]) => {
+                let ip = parse_word(ip)?;
+                self.breakpoints.insert(ip);
+                writeln!(output, "Breakpoint set at {:#06x}", ip)?;
+            }
+            ("delete" | "d", [ip]) => {
+                let ip = parse_word(ip)?;
+                if !self.breakpoints.remove(&ip) {
+                    bail!("No breakpoint at {:#06x}", ip);
+                }
+            }
+            ("watch" | "w", [address]) => {
+                let (segment, offset) = self.parse_address(address)?;
+                let value = self.cpu.memory.read_word(segment, offset);
+
+                self.watchpoints.push(Watchpoint {
+                    segment,
+                    offset,
+                    value,
+                });
+                writeln!(
+                    output,
+                    "Watching {:04x}:{:04x} ({:#x})",
+                    segment, offset, value
+                )?;
+            }
+            ("regs" | "r", []) => output.push_str(&self.format_registers()),
+            ("mem" | "m", [address, length]) => {
+                let (segment, offset) = self.parse_address(address)?;
+                output.push_str(&self.format_memory(segment, offset, parse_word(length)? as usize));
+            }
+            ("disasm" | "u", _) if args.len() <= 2 => {
+                let start = match args.first() {
+                    Some(ip) => parse_word(ip)?,
+                    None => self.cpu.state.ip,
+                };
+                let count = match args.get(1) {
+                    Some(count) => parse_word(count)? as usize,
+                    None => 5,
+                };
+                output.push_str(&self.format_disassembly(start, count)?);
+            }
+            ("set", [name, value]) => self.set(name, parse_word(value)?)?,
+            ("back" | "bs", _) if args.len() <= 1 => {
+                let count = match args.first() {
+                    Some(count) => parse_word(count)? as u64,
+                    None => 1,
+                };
+
+                self.history.back(&mut self.cpu, count)?;
+                self.refresh_watchpoints();
+                output.push_str(&self.format_position());
+            }
+            ("rewind", [ip, count]) => {
+                let (ip, count) = (parse_word(ip)?, parse_word(count)? as u64);
+
+                let result = self.history.rewind_to_hit(&mut self.cpu, ip, count);
+                self.refresh_watchpoints();
+                result?;
+
+                output.push_str(&self.format_position());
+            }
+            ("last-write", [address]) => {
+                let (segment, offset) = self.parse_address(address)?;
+                let physical_address = Memory::physical_address(segment, offset);
+
+                let Some(record) = self.history.last_write(physical_address) else {
+                    bail!(
+                        "No recorded instruction wrote {:04x}:{:04x}",
+                        segment,
+                        offset
+                    );
+                };
+
+                let write = record
+                    .writes
+                    .iter()
+                    .rev()
+                    .find(|write| write.address == physical_address)
+                    .expect("the record wrote the address");
+                writeln!(
+                    output,
+                    "{:04x}:{:04x} was written by `{}` at step {} ({:#x}->{:#x})",
+                    segment,
+                    offset,
+                    format_instruction(&record.instruction, &Default::default()),
+                    record.step,
+                    write.old,
+                    write.new
+                )?;
+
+                let step = record.step;
+                self.history.goto(&mut self.cpu, step)?;
+                self.refresh_watchpoints();
+                output.push_str(&self.format_position());
+            }
+            ("help" | "h", []) => output.push_str(HELP),
+            ("quit" | "q", []) => return Ok((Flow::Quit, output)),
+            _ => bail!("Invalid command `{}`, try `help`", line.trim()),
+        }
+
+        Ok((Flow::Continue, output))
+    }
+}
+
+//* Accepts anything parse_number does, negative values wrap to their two's complement
+fn parse_word(text: &str) -> Result<u16> {
+    let value = parse_number(text)?;
+    if !(i16::MIN as i32..=u16::MAX as i32).contains(&value) {
+        bail!("`{}` does not fit in 16 bits", text);
+    }
+
+    Ok(value as u16)
+}
+
+//* Reads commands until `quit` or the end of the input. Scripts echo each command
+//* after the prompt so their output reads like an interactive session
+pub fn run_debugger(
+    debugger: &mut Debugger,
+    input: &mut dyn BufRead,
+    output: &mut dyn IoWrite,
+    interactive: bool,
+) -> Result<()> {
+    write!(output, "{}", debugger.format_next_instruction())?;
+
+    loop {
+        if interactive {
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+        }
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+
+        //* `#` starts a comment in command files
+        let command = line.split('#').next().unwrap_or_default().trim();
+        if command.is_empty() {
+            continue;
+        }
+
+        if !interactive {
+            writeln!(output, "{}{}", PROMPT, command)?;
+        }
+
+        match debugger.execute_command(command) {
+            Ok((flow, text)) => {
+                write!(output, "{}", text)?;
+                if flow == Flow::Quit {
+                    break;
+                }
+            }
+            //* A bad command shouldn't end an interactive session, but should fail a script
+            Err(error) if interactive => writeln!(output, "error: {}", error)?,
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
@@ -2,12 +2,19 @@ use anyhow::Ok;
 
 use crate::prelude::*;
 
-use std::{collections::HashMap, fmt::Write};
+use crate::cfg::{transfer_kind, EdgeKind};
+use crate::clocks::*;
+use crate::encoder::*;
+use crate::instruction::*;
+use crate::jumptables::*;
+use crate::stack::WARNING_COMMENT;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 pub type DecodeFunc = fn(
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
     decoder: &mut Decoder,
 ) -> Result<NumBytesInInstruction>;
 
@@ -19,7 +26,7 @@ pub struct InstructionWithOffset {
 
 impl PartialOrd for InstructionWithOffset {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.offset.partial_cmp(&other.offset)
+        Some(self.cmp(other))
     }
 }
 
@@ -37,11 +44,38 @@ impl PartialEq for InstructionWithOffset {
 
 impl Eq for InstructionWithOffset {}
 
+//* Bytes decode_all writes out as data instead of decoding them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataRange {
+    pub start: usize,
+    pub length: usize,
+    pub kind: DataKind,
+}
+
+impl DataRange {
+    pub fn contains(&self, offset: usize) -> bool {
+        (self.start..self.start + self.length).contains(&offset)
+    }
+}
+
+//* A segment override prefix, opcode, mod reg r/m, displacement and immediate
+pub const MAX_INSTRUCTION_LENGTH: usize = 7;
+
+//* Bytes per `db`/`dw` line, strings get longer lines
+const DATA_LINE_LENGTH: usize = 16;
+const STRING_LINE_LENGTH: usize = 64;
+
 pub struct Decoder {
-    pub funcs: [DecodeFunc; 0xFF],
+    pub funcs: [DecodeFunc; 0x100],
     pub groups: [DecodeFunc; 8 * 4],
     pub enqued_labels: Vec<InstructionWithOffset>,
     pub labels: HashMap<usize, String>,
+    pub data_ranges: Vec<DataRange>,
+    //* Found by decode_all, their tables are added to data_ranges
+    pub jump_tables: Vec<JumpTable>,
+    //* Where the image is loaded in its segment, 0x100 for a .COM program. Addresses the
+    //* code holds, like jump table entries, are relative to it
+    pub origin: usize,
 }
 
 impl Decoder {
@@ -49,11 +83,11 @@ impl Decoder {
         let mut groups = [decode_stub as DecodeFunc; 8 * 4];
 
         //*Set all funcs to stub
-        let mut funcs = [decode_stub as DecodeFunc; 0xFF];
+        let mut funcs = [decode_stub as DecodeFunc; 0x100];
 
         //* Set indices to MOV as per the machine instruction encoding table
-        //TODO: 8C, 8E Segment register movs
-        funcs[0x88..=0x8B].fill(decode_mov);
+        funcs[0x88..=0x8C].fill(decode_mov);
+        funcs[0x8E] = decode_mov;
         funcs[0xA0..=0xA3].fill(decode_mov);
         funcs[0xB0..=0xBF].fill(decode_mov);
         funcs[0xc6..=0xC7].fill(decode_mov);
@@ -71,22 +105,379 @@ impl Decoder {
         funcs[0x38..=0x3D].fill(decode_cmp);
         groups[0b111] = decode_cmp;
 
+        //* ADC, SBB, AND, OR, XOR share the same encodings
+        funcs[0x10..=0x15].fill(decode_adc);
+        groups[0b010] = decode_adc;
+        funcs[0x18..=0x1D].fill(decode_sbb);
+        groups[0b011] = decode_sbb;
+        funcs[0x20..=0x25].fill(decode_and);
+        groups[0b100] = decode_and;
+        funcs[0x08..=0x0D].fill(decode_or);
+        groups[0b001] = decode_or;
+        funcs[0x30..=0x35].fill(decode_xor);
+        groups[0b110] = decode_xor;
+
+        //* Processor control instructions that only touch flags
+        funcs[0xF5] = decode_flag_instruction;
+        funcs[0xF8..=0xFD].fill(decode_flag_instruction);
+
+        //* int 3 and int imm8
+        funcs[0xCC..=0xCD].fill(decode_interrupt);
+
+        //* es:, cs:, ss:, ds: prefixes
+        for sr in 0..4 {
+            funcs[0x26 | (sr << 3)] = decode_segment_override;
+        }
+
         funcs[0x70..=0x7F].fill(decode_conditional_jump);
         funcs[0xE0..=0xE3].fill(decode_conditional_jump);
 
+        //* call near, jmp near, jmp short
+        funcs[0xE8..=0xE9].fill(decode_direct_transfer);
+        funcs[0xEB] = decode_direct_transfer;
+        //* ret imm16, ret, retf imm16, retf
+        funcs[0xC2..=0xC3].fill(decode_return);
+        funcs[0xCA..=0xCB].fill(decode_return);
+
+        //* push and pop of a word register
+        funcs[0x50..=0x5F].fill(decode_push_pop);
+
+        //* inc and dec of a word register
+        funcs[0x40..=0x4F].fill(decode_inc_dec_register);
+
+        //* inc, dec, call, call far, jmp, jmp far and push of a register or memory
+        funcs[0xFE..=0xFF].fill(decode_from_group);
+        groups[8 * 3..8 * 4].fill(decode_group_fe_ff);
+
         Self {
             funcs,
             groups,
             enqued_labels: Vec::new(),
             labels: HashMap::new(),
+            data_ranges: Vec::new(),
+            jump_tables: Vec::new(),
+            origin: 0,
+        }
+    }
+
+    pub fn decode_single_instruction(
+        &mut self,
+        instructions: &[u8],
+        offset: usize,
+    ) -> Result<Instruction> {
+        let mut instruction = Instruction {
+            offset,
+            ..Default::default()
+        };
+
+        let first_byte = instructions[offset];
+        instruction.size =
+            self.funcs[first_byte as usize](instructions, offset, &mut instruction, self)?;
+
+        Ok(instruction)
+    }
+
+    //* Decodes front to back. Jump tables are recognised as their jmp is decoded, their
+    //* targets get labels and the table becomes data. A table behind bytes already decoded
+    //* as code means starting over with it in place
+    pub fn decode_all(&mut self, instructions: &[u8]) -> Result<Vec<Instruction>> {
+        let initial_labels = self.labels.clone();
+        let initial_enqued_labels = self.enqued_labels.len();
+
+        'decode: loop {
+            let mut outputs = Vec::new();
+
+            let mut bytes_processed = 0;
+            while bytes_processed < instructions.len() {
+                let instruction = match self.data_range_at(bytes_processed) {
+                    Some(range) => decode_data(instructions, bytes_processed, &range),
+                    None => self.decode_single_instruction(instructions, bytes_processed)?,
+                };
+                bytes_processed += instruction.size;
+                let is_indirect_transfer = instruction.is_indirect_transfer();
+                outputs.push(instruction);
+
+                if !is_indirect_transfer {
+                    continue;
+                }
+                let Some(table) = recognise_jump_table(&outputs, instructions, self.origin) else {
+                    continue;
+                };
+
+                if !self.jump_tables.contains(&table) {
+                    self.data_ranges.push(DataRange {
+                        start: table.table,
+                        length: table.length(),
+                        kind: DataKind::Address,
+                    });
+                    let is_behind = table.table < bytes_processed;
+                    self.jump_tables.push(table.clone());
+
+                    if is_behind {
+                        self.labels = initial_labels.clone();
+                        self.enqued_labels.truncate(initial_enqued_labels);
+                        continue 'decode;
+                    }
+                }
+
+                for target in &table.targets {
+                    add_label(self, *target);
+                }
+            }
+
+            return Ok(outputs);
+        }
+    }
+
+    //* Recursive descent from the first byte: follows fallthroughs, jump and call targets
+    //* and jump tables, stopping at anything that doesn't decode. Unlike decode_all this
+    //* leaves the labels alone
+    pub fn decode_reachable(&self, instructions: &[u8]) -> Vec<Instruction> {
+        let mut tracer = Decoder::new();
+        tracer.data_ranges = self.data_ranges.clone();
+        tracer.origin = self.origin;
+
+        //* Padded so an instruction cut off by the end of the image decodes instead of
+        //* reading out of bounds, it is then dropped for running past the end
+        let mut padded = instructions.to_vec();
+        padded.resize(instructions.len() + MAX_INSTRUCTION_LENGTH, 0);
+
+        let mut reached = BTreeMap::new();
+        let mut pending = vec![0];
+        while let Some(mut offset) = pending.pop() {
+            //* Straight line run from the pending offset, what jump tables are recognised in
+            let mut run = Vec::new();
+
+            while offset < instructions.len()
+                && !reached.contains_key(&offset)
+                && tracer.data_range_at(offset).is_none()
+            {
+                let Some(instruction) = tracer
+                    .decode_single_instruction(&padded, offset)
+                    .ok()
+                    .filter(|instruction| offset + instruction.size <= instructions.len())
+                else {
+                    break;
+                };
+                offset += instruction.size;
+                pending.extend(instruction.jump_target());
+                run.push(instruction.clone());
+
+                if instruction.is_indirect_transfer() {
+                    if let Some(table) = recognise_jump_table(&run, instructions, tracer.origin) {
+                        tracer.data_ranges.push(DataRange {
+                            start: table.table,
+                            length: table.length(),
+                            kind: DataKind::Address,
+                        });
+                        pending.extend(table.targets);
+                    }
+                }
+
+                let ends_run = matches!(
+                    transfer_kind(&instruction),
+                    Some(EdgeKind::Unconditional) | Some(EdgeKind::Return)
+                ) || ends_program(&run);
+                reached.insert(instruction.offset, instruction);
+
+                if ends_run {
+                    break;
+                }
+            }
+        }
+
+        reached.into_values().collect()
+    }
+
+    pub fn data_range_at(&self, offset: usize) -> Option<DataRange> {
+        self.data_ranges
+            .iter()
+            .find(|range| range.contains(offset))
+            .copied()
+    }
+}
+
+//* `int 20h`, or `int 21h` after setting ah to 4Ch, doesn't come back
+pub fn ends_program(run: &[Instruction]) -> bool {
+    let Some((last, before)) = run.split_last() else {
+        return false;
+    };
+    let Some(Operand::Immediate(vector)) = last.operands[0].filter(|_| last.mnemonic == "int")
+    else {
+        return false;
+    };
+
+    match vector.value {
+        0x20 => true,
+        0x21 => {
+            //* The last mov into ah, or ax with ah in its high byte
+            let service = before.iter().rev().find_map(|instruction| {
+                match (instruction.mnemonic, instruction.operands) {
+                    (
+                        "mov",
+                        [Some(Operand::Register(register)), Some(Operand::Immediate(value))],
+                    ) => match (register.reg, register.word) {
+                        (4, false) => Some(Some(value.value as u8)),
+                        (0, true) => Some(Some((value.value >> 8) as u8)),
+                        _ => None,
+                    },
+                    //* Anything else writing ah, ax, or a register we can't tell from
+                    (_, [Some(Operand::Register(register)), _])
+                        if matches!((register.reg, register.word), (0, true) | (4, false)) =>
+                    {
+                        Some(None)
+                    }
+                    _ => None,
+                }
+            });
+
+            service.flatten() == Some(0x4C)
+        }
+        _ => false,
+    }
+}
+
+//* One line of data from `offset` up to the end of its range
+fn decode_data(instructions: &[u8], offset: usize, range: &DataRange) -> Instruction {
+    let line_length = match range.kind {
+        DataKind::String => STRING_LINE_LENGTH,
+        _ => DATA_LINE_LENGTH,
+    };
+    let end = (range.start + range.length)
+        .min(offset + line_length)
+        .min(instructions.len());
+
+    //* A word range with an odd length ends in a single byte
+    let kind = match range.kind {
+        DataKind::Word | DataKind::Address if end - offset == 1 => DataKind::Byte,
+        kind => kind,
+    };
+    let end = match kind {
+        DataKind::Word | DataKind::Address => end - (end - offset) % 2,
+        _ => end,
+    };
+
+    Instruction {
+        offset,
+        size: end - offset,
+        mnemonic: match kind {
+            DataKind::Word | DataKind::Address => "dw",
+            DataKind::Byte | DataKind::String => "db",
+        },
+        data: Some(Data {
+            kind,
+            bytes: instructions[offset..end].to_vec(),
+        }),
+        ..Default::default()
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//* Writes the listing line by line, with comment lines and labels placed before the
+//* instruction they point at.
+//* Instructions whose exact bytes can't be written as assembly are emitted as `db`
+pub fn write_listing(
+    instructions: &[u8],
+    decoded: &[Instruction],
+    labels: &HashMap<usize, String>,
+    comments: &HashMap<usize, Vec<String>>,
+    style: &dyn Style,
+    clock_model: Option<CpuModel>,
+) -> Result<Vec<String>> {
+    let mut output_str_vec = Vec::new();
+    output_str_vec.push("bits 16\n\n".to_owned());
+
+    //* Names for addresses no line starts at can only be defined with equ
+    let starts: HashSet<usize> = decoded.iter().map(|ins| ins.offset).collect();
+    let mut unplaced: Vec<(&usize, &String)> = labels
+        .iter()
+        .filter(|(address, _)| !starts.contains(address))
+        .collect();
+    unplaced.sort();
+    if !unplaced.is_empty() {
+        let mut output = String::new();
+        for (address, label) in unplaced {
+            style.write_token(&mut output, TokenKind::Label, label)?;
+            output.push_str(&format!(" equ {:#06x}\n", address));
         }
+        output.push('\n');
+        output_str_vec.push(output);
     }
+
+    let mut total_clocks = 0;
+
+    for ins in decoded {
+        let mut output = String::new();
+
+        //* Warnings are about the instruction, the label goes between them and the rest
+        let (warnings, notes): (Vec<&String>, Vec<&String>) = comments
+            .get(&ins.offset)
+            .into_iter()
+            .flatten()
+            .partition(|comment| comment.starts_with(WARNING_COMMENT));
+
+        for comment in notes {
+            output.push_str(comment);
+            output.push('\n');
+        }
+
+        if let Some(label) = labels.get(&ins.offset) {
+            style.write_token(&mut output, TokenKind::Label, label)?;
+            output.push_str(":\n");
+        }
+
+        for comment in warnings {
+            output.push_str(comment);
+            output.push('\n');
+        }
+
+        //* Anything the encoder can't reproduce, or rejects, is written as raw bytes
+        let original = &instructions[ins.offset..ins.offset + ins.size];
+        if ins.data.is_some() || encode_instruction(ins).ok().as_deref() == Some(original) {
+            write_instruction(&mut output, ins, labels, style)?;
+        } else {
+            write_raw_instruction(&mut output, original, ins, labels, style)?;
+        }
+
+        //* Straight line estimate, the listing can't tell whether a jump is taken
+        if let Some(model) = clock_model.filter(|_| ins.data.is_none()) {
+            let clocks = estimate_clocks(ins, model, None, false)?;
+            total_clocks += clocks.total() as u64;
+
+            output.push_str(" ; ");
+            output.push_str(&format_clocks(&clocks, total_clocks));
+        }
+        output.push('\n');
+
+        output_str_vec.push(output);
+    }
+
+    Ok(output_str_vec)
+}
+
+pub fn decode_instructions(instructions: &[u8]) -> Result<Vec<String>> {
+    let mut decoder = Decoder::new();
+    let outputs = decoder.decode_all(instructions)?;
+
+    write_listing(
+        instructions,
+        &outputs,
+        &decoder.labels,
+        &HashMap::new(),
+        &PlainStyle,
+        None,
+    )
 }
 
 pub fn decode_from_group(
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
     decoder: &mut Decoder,
 ) -> Result<NumBytesInInstruction> {
     let first_byte = instructions[offset];
@@ -113,20 +504,51 @@ pub fn decode_from_group(
     Ok(num_bytes_in_instruction)
 }
 
+//* Decodes the instruction following the prefix and applies the segment to its memory operand
+pub fn decode_segment_override(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    decoder: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    let first_byte = instructions[offset];
+    let sr = (first_byte & 0b00011000) >> 3;
+
+    let next_byte = instructions[offset + 1];
+    if next_byte & 0b11100111 == 0b00100110 {
+        bail!("Repeated segment override prefix at offset {:#x}", offset);
+    }
+
+    let num_bytes_in_instruction =
+        decoder.funcs[next_byte as usize](instructions, offset + 1, output, decoder)?;
+
+    for operand in output.operands.iter_mut().flatten() {
+        if let Operand::Memory(address) = operand {
+            address.segment = Some(sr);
+        }
+    }
+
+    Ok(num_bytes_in_instruction + 1)
+}
+
 #[allow(unused_variables, clippy::ptr_arg)]
 pub fn decode_stub(
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
     _: &mut Decoder,
 ) -> Result<NumBytesInInstruction> {
-    Ok(0)
+    bail!(
+        "Unsupported opcode {:#04x} at offset {:#x}",
+        instructions[offset],
+        offset
+    );
 }
 
 pub fn decode_mov(
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
     _: &mut Decoder,
 ) -> Result<NumBytesInInstruction> {
     let first_byte = instructions[offset];
@@ -142,6 +564,44 @@ pub fn decode_mov(
         }
     }
 
+    //* Register/Memory to/from segment register
+    {
+        if first_byte == 0x8C || first_byte == 0x8E {
+            let second_byte = instructions[offset + num_bytes_in_instruction];
+            num_bytes_in_instruction += 1;
+
+            let to_segment_register = (first_byte & 0b00000010) > 0;
+            let sr = (second_byte & 0b00011000) >> 3;
+            let rm = second_byte & 0b00000111;
+            let mode = (second_byte & 0b11000000) >> 6;
+
+            let reg_mem = if mode == 0b11 {
+                Operand::Register(Register {
+                    reg: rm,
+                    word: true,
+                })
+            } else {
+                Operand::Memory(construct_address(
+                    instructions,
+                    offset,
+                    &mut num_bytes_in_instruction,
+                    mode,
+                    rm,
+                )?)
+            };
+            let segment_register = Operand::SegmentRegister(sr);
+
+            output.mnemonic = "mov";
+            if to_segment_register {
+                output.operands = [Some(segment_register), Some(reg_mem)];
+            } else {
+                output.operands = [Some(reg_mem), Some(segment_register)];
+            }
+
+            return Ok(num_bytes_in_instruction);
+        }
+    }
+
     //*Immediate to register
     {
         let opcode = 0b1011;
@@ -151,7 +611,15 @@ pub fn decode_mov(
 
             let data = get_byte_or_word(instructions, offset, &mut num_bytes_in_instruction, word);
 
-            writeln!(output, "mov {}, {}", get_register_name(reg, word), data)?;
+            output.mnemonic = "mov";
+            output.operands = [
+                Some(Operand::Register(Register { reg, word })),
+                Some(Operand::Immediate(Immediate {
+                    value: data,
+                    word,
+                    sign_extended: false,
+                })),
+            ];
 
             return Ok(num_bytes_in_instruction);
         }
@@ -195,24 +663,24 @@ pub fn decode_mov(
             let word = (first_byte & 0b00000001) > 0;
             let is_acc_to_mem = direction;
 
+            //* The address is always 16 bits, w only selects al or ax
             let direct_address =
-                get_byte_or_word(instructions, offset, &mut num_bytes_in_instruction, word) as i16;
+                get_byte_or_word(instructions, offset, &mut num_bytes_in_instruction, true) as i16;
+
+            let memory = Operand::Memory(EffectiveAddress {
+                rm: None,
+                displacement: direct_address,
+                displacement_size: 2,
+                segment: None,
+            });
+            let accumulator = Operand::Register(Register { reg: 0, word });
 
+            output.mnemonic = "mov";
             if is_acc_to_mem {
-                writeln!(
-                    output,
-                    "mov [{}], {}",
-                    direct_address,
-                    get_register_name(0, word)
-                )?;
+                output.operands = [Some(memory), Some(accumulator)];
             } else {
                 //* memory to accumulator case
-                writeln!(
-                    output,
-                    "mov {}, [{}]",
-                    get_register_name(0, word),
-                    direct_address,
-                )?;
+                output.operands = [Some(accumulator), Some(memory)];
             }
 
             return Ok(num_bytes_in_instruction);
@@ -222,11 +690,10 @@ pub fn decode_mov(
     unreachable!()
 }
 
-//TODO: Not thread safe because label generation uses a static mut
 pub fn decode_conditional_jump(
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
     decoder: &mut Decoder,
 ) -> Result<NumBytesInInstruction> {
     let first_byte = instructions[offset];
@@ -239,61 +706,103 @@ pub fn decode_conditional_jump(
         current_byte.wrapping_add((second_byte as i8) as usize)
     };
 
-    let jump_str = match first_byte {
-        0b01110100 => "je",
-        0b01111100 => "jl",
-        0b01111110 => "jle",
-        0b01110010 => "jb",
-        0b01110110 => "jbe",
-        0b01111010 => "jp",
-        0b01110000 => "jo",
-        0b01111000 => "js",
-        0b01110101 => "jne",
-        0b01111101 => "jnl",
-        0b01111111 => "jnle",
-        0b01110011 => "jnb",
-        0b01110111 => "jnbe",
-        0b01111011 => "jnp",
-        0b01110001 => "jno",
-        0b01111001 => "jns",
-        0b11100010 => "loop",
-        0b11100001 => "loope",
-        0b11100000 => "loopne",
-        0b11100011 => "jcxz",
-        _ => {
+    let jump_str = match CONDITIONAL_JUMP_MAPPING
+        .iter()
+        .find(|(opcode, _)| *opcode == first_byte)
+    {
+        Some((_, jump_str)) => *jump_str,
+        None => {
             bail!("Invalid Conditional Jump opcode");
         }
     };
 
-    //*If label already exists, write the jump instruction and return
-    if let Some(label) = decoder.labels.get(&byte_to_jump_to) {
-        writeln!(output, "{} {}", jump_str, label)?;
-        return Ok(num_bytes_in_instruction);
-    }
+    output.mnemonic = jump_str;
+    output.operands = [Some(Operand::JumpTarget(byte_to_jump_to)), None];
 
-    //* Generate label
-    static mut LABEL_NUM: usize = 0;
-    let label = unsafe {
-        let string = format!("label{}", LABEL_NUM);
-        LABEL_NUM += 1;
-        string
-    };
+    add_label(decoder, byte_to_jump_to);
 
-    writeln!(output, "{} {}", jump_str, label)?;
+    Ok(num_bytes_in_instruction)
+}
+
+fn add_label(decoder: &mut Decoder, target: usize) {
+    //*If label already exists, the jump instruction refers to it
+    if decoder.labels.contains_key(&target) {
+        return;
+    }
+
+    //* Generate label, numbered per decoder
+    let label = format!("label{}", decoder.labels.len());
 
-    decoder.labels.insert(byte_to_jump_to, label.clone());
+    decoder.labels.insert(target, label.clone());
     decoder.enqued_labels.push(InstructionWithOffset {
-        offset: byte_to_jump_to,
+        offset: target,
         output: label,
     });
+}
+
+//* call and jmp with a relative target, near ones wrap around within the segment
+pub fn decode_direct_transfer(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    decoder: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    let first_byte = instructions[offset];
+    let num_bytes_in_instruction = if first_byte == 0xEB { 2 } else { 3 };
+
+    let Some(increment) = instructions.get(offset + 1..offset + num_bytes_in_instruction) else {
+        bail!("Jump at {:#x} is missing its increment", offset);
+    };
+    let increment = match increment {
+        [byte] => *byte as i8 as i16,
+        _ => i16::from_le_bytes([increment[0], increment[1]]),
+    };
+
+    let next_instruction = (offset + num_bytes_in_instruction) as u16;
+    let target = next_instruction.wrapping_add(increment as u16) as usize;
+
+    output.mnemonic = if first_byte == 0xE8 { "call" } else { "jmp" };
+    output.operands = [Some(Operand::JumpTarget(target)), None];
+
+    add_label(decoder, target);
 
     Ok(num_bytes_in_instruction)
 }
 
+pub fn decode_return(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    let first_byte = instructions[offset];
+    output.mnemonic = if first_byte & 0b1000 == 0 {
+        "ret"
+    } else {
+        "retf"
+    };
+
+    if first_byte & 1 == 1 {
+        return Ok(1);
+    }
+
+    //* Bytes of arguments to pop after the return address
+    let Some(count) = instructions.get(offset + 1..offset + 3) else {
+        bail!("Return at {:#x} is missing its pop count", offset);
+    };
+    output.operands[0] = Some(Operand::Immediate(Immediate {
+        value: u16::from_le_bytes([count[0], count[1]]),
+        word: true,
+        sign_extended: false,
+    }));
+
+    Ok(3)
+}
+
 pub fn decode_add(
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
     _: &mut Decoder,
 ) -> Result<NumBytesInInstruction> {
     decode_add_sub_cmp(
@@ -311,7 +820,7 @@ pub fn decode_add(
 pub fn decode_sub(
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
     _: &mut Decoder,
 ) -> Result<NumBytesInInstruction> {
     decode_add_sub_cmp(
@@ -329,7 +838,7 @@ pub fn decode_sub(
 pub fn decode_cmp(
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
     _: &mut Decoder,
 ) -> Result<NumBytesInInstruction> {
     decode_add_sub_cmp(
@@ -344,15 +853,268 @@ pub fn decode_cmp(
     )
 }
 
+pub fn decode_adc(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    decode_add_sub_cmp(
+        "adc",
+        0b000100,
+        0b100000,
+        0b0001010,
+        0b010,
+        instructions,
+        offset,
+        output,
+    )
+}
+
+pub fn decode_sbb(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    decode_add_sub_cmp(
+        "sbb",
+        0b000110,
+        0b100000,
+        0b0001110,
+        0b011,
+        instructions,
+        offset,
+        output,
+    )
+}
+
+pub fn decode_and(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    decode_add_sub_cmp(
+        "and",
+        0b001000,
+        0b100000,
+        0b0010010,
+        0b100,
+        instructions,
+        offset,
+        output,
+    )
+}
+
+pub fn decode_or(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    decode_add_sub_cmp(
+        "or",
+        0b000010,
+        0b100000,
+        0b0000110,
+        0b001,
+        instructions,
+        offset,
+        output,
+    )
+}
+
+pub fn decode_xor(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    decode_add_sub_cmp(
+        "xor",
+        0b001100,
+        0b100000,
+        0b0011010,
+        0b110,
+        instructions,
+        offset,
+        output,
+    )
+}
+
+pub fn decode_flag_instruction(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    let first_byte = instructions[offset];
+
+    output.mnemonic = match FLAG_INSTRUCTION_MAPPING
+        .iter()
+        .find(|(opcode, _)| *opcode == first_byte)
+    {
+        Some((_, mnemonic)) => mnemonic,
+        None => bail!("Invalid flag instruction opcode"),
+    };
+
+    Ok(1)
+}
+
+pub fn decode_interrupt(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    //* The breakpoint interrupt has its own one byte encoding
+    if instructions[offset] == 0xCC {
+        output.mnemonic = "int3";
+        return Ok(1);
+    }
+
+    let Some(vector) = instructions.get(offset + 1) else {
+        bail!("Interrupt at {:#x} is missing its type", offset);
+    };
+
+    output.mnemonic = "int";
+    output.operands[0] = Some(Operand::Immediate(Immediate {
+        value: *vector as u16,
+        word: false,
+        sign_extended: false,
+    }));
+
+    Ok(2)
+}
+
+pub fn decode_push_pop(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    let first_byte = instructions[offset];
+
+    output.mnemonic = if first_byte & 0b1000 == 0 {
+        "push"
+    } else {
+        "pop"
+    };
+    output.operands[0] = Some(Operand::Register(Register {
+        reg: first_byte & 0b111,
+        word: true,
+    }));
+
+    Ok(1)
+}
+
+pub fn decode_inc_dec_register(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    let first_byte = instructions[offset];
+
+    output.mnemonic = if first_byte & 0b1000 == 0 {
+        "inc"
+    } else {
+        "dec"
+    };
+    output.operands[0] = Some(Operand::Register(Register {
+        reg: first_byte & 0b111,
+        word: true,
+    }));
+
+    Ok(1)
+}
+
+//* 0xFE only has inc and dec of a byte, 0xFF the word forms of all of them
+pub fn decode_group_fe_ff(
+    instructions: &[u8],
+    offset: usize,
+    output: &mut Instruction,
+    _: &mut Decoder,
+) -> Result<NumBytesInInstruction> {
+    let first_byte = instructions[offset];
+    let Some(&second_byte) = instructions.get(offset + 1) else {
+        bail!(
+            "Instruction at {:#x} is missing its mod reg r/m byte",
+            offset
+        );
+    };
+    let mut num_bytes_in_instruction = 2;
+
+    let word = first_byte == 0xFF;
+    let reg = (second_byte & 0b00111000) >> 3;
+    let rm = second_byte & 0b00000111;
+    let mode = (second_byte & 0b11000000) >> 6;
+
+    let (mnemonic, size) = match (word, reg) {
+        (_, 0b000) => (
+            "inc",
+            if word {
+                MemorySize::Word
+            } else {
+                MemorySize::Byte
+            },
+        ),
+        (_, 0b001) => (
+            "dec",
+            if word {
+                MemorySize::Word
+            } else {
+                MemorySize::Byte
+            },
+        ),
+        (true, 0b010) => ("call", MemorySize::Word),
+        (true, 0b011) => ("call", MemorySize::Far),
+        (true, 0b100) => ("jmp", MemorySize::Word),
+        (true, 0b101) => ("jmp", MemorySize::Far),
+        (true, 0b110) => ("push", MemorySize::Word),
+        _ => bail!(
+            "Invalid opcode extension {} for {:#04x} at offset {:#x}",
+            reg,
+            first_byte,
+            offset
+        ),
+    };
+
+    output.mnemonic = mnemonic;
+    output.operands[0] = Some(if mode == 0b11 {
+        //* A far pointer can't be held in a register
+        if size == MemorySize::Far {
+            bail!(
+                "Far `{}` through a register at offset {:#x}",
+                mnemonic,
+                offset
+            );
+        }
+        Operand::Register(Register { reg: rm, word })
+    } else {
+        output.memory_size = Some(size);
+        Operand::Memory(construct_address(
+            instructions,
+            offset,
+            &mut num_bytes_in_instruction,
+            mode,
+            rm,
+        )?)
+    });
+
+    Ok(num_bytes_in_instruction)
+}
+
 pub fn decode_add_sub_cmp(
-    opname: &str,
+    opname: &'static str,
     reg_mem_to_reg_mem_opcode: u8,
     imm_to_reg_mem_opcode: u8,
     imm_to_acc_opcode: u8,
     group_reg: u8,
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
 ) -> Result<NumBytesInInstruction> {
     let first_byte = instructions[offset];
     let mut num_bytes_in_instruction = 1;
@@ -392,13 +1154,11 @@ pub fn decode_add_sub_cmp(
                     is_data_16bit,
                 );
 
-                writeln!(
-                    output,
-                    "{} {}, {}",
-                    opname,
-                    get_register_name(rm, word),
-                    data
-                )?;
+                output.mnemonic = opname;
+                output.operands = [
+                    Some(Operand::Register(Register { reg: rm, word })),
+                    Some(Operand::Immediate(sign_extend_immediate(data, sign, word))),
+                ];
             } else {
                 let is_data_16bit = !sign && word;
 
@@ -427,13 +1187,15 @@ pub fn decode_add_sub_cmp(
 
             let data = get_byte_or_word(instructions, offset, &mut num_bytes_in_instruction, word);
 
-            writeln!(
-                output,
-                "{} {}, {}",
-                opname,
-                get_register_name(0, word),
-                data
-            )?;
+            output.mnemonic = opname;
+            output.operands = [
+                Some(Operand::Register(Register { reg: 0, word })),
+                Some(Operand::Immediate(Immediate {
+                    value: data,
+                    word,
+                    sign_extended: false,
+                })),
+            ];
 
             return Ok(num_bytes_in_instruction);
         }
@@ -441,32 +1203,46 @@ pub fn decode_add_sub_cmp(
     unreachable!()
 }
 
+//* An 8 bit immediate with the sign bit set is extended to the full word
+fn sign_extend_immediate(data: u16, sign: bool, word: bool) -> Immediate {
+    let sign_extended = sign && word;
+    let value = if sign_extended {
+        (data as u8 as i8) as i16 as u16
+    } else {
+        data
+    };
+
+    Immediate {
+        value,
+        word,
+        sign_extended,
+    }
+}
+
 fn register_to_register(
-    op_name: &str,
+    op_name: &'static str,
     reg: u8,
     rm: u8,
     direction: bool,
     word: bool,
-    output: &mut String,
+    output: &mut Instruction,
 ) -> Result<()> {
     let (src, dest) = if direction { (rm, reg) } else { (reg, rm) };
 
-    writeln!(
-        output,
-        "{} {}, {}",
-        op_name,
-        get_register_name(dest, word),
-        get_register_name(src, word)
-    )?;
+    output.mnemonic = op_name;
+    output.operands = [
+        Some(Operand::Register(Register { reg: dest, word })),
+        Some(Operand::Register(Register { reg: src, word })),
+    ];
 
     Ok(())
 }
 
 fn reg_mem_to_reg_mem(
-    opname: &str,
+    opname: &'static str,
     instructions: &[u8],
     offset: usize,
-    output: &mut String,
+    output: &mut Instruction,
 ) -> Result<NumBytesInInstruction> {
     let first_byte = instructions[offset];
     let mut num_bytes_in_instruction = 1;
@@ -486,44 +1262,22 @@ fn reg_mem_to_reg_mem(
         register_to_register(opname, reg, rm, direction, word, output)?;
     } else {
         //* Memory to register mode, where mode = 00 | 01 | 10
-        if mode == 0b00 && rm == 0b110 {
-            //* Direct address case
-            let direct_address =
-                get_byte_or_word(instructions, offset, &mut num_bytes_in_instruction, true) as i16;
-
-            writeln!(
-                output,
-                "{} {}, [{}]",
-                opname,
-                get_register_name(reg, word),
-                direct_address
-            )?;
+        let address = construct_address(
+            instructions,
+            offset,
+            &mut num_bytes_in_instruction,
+            mode,
+            rm,
+        )?;
+
+        let register = Operand::Register(Register { reg, word });
+        let memory = Operand::Memory(address);
+
+        output.mnemonic = opname;
+        if direction {
+            output.operands = [Some(register), Some(memory)];
         } else {
-            let source_addr_str = construct_address(
-                instructions,
-                offset,
-                &mut num_bytes_in_instruction,
-                mode,
-                rm,
-            )?;
-
-            if direction {
-                writeln!(
-                    output,
-                    "{} {}, [{}]",
-                    opname,
-                    get_register_name(reg, word),
-                    source_addr_str
-                )?;
-            } else {
-                writeln!(
-                    output,
-                    "{} [{}], {}",
-                    opname,
-                    source_addr_str,
-                    get_register_name(reg, word),
-                )?;
-            }
+            output.operands = [Some(memory), Some(register)];
         }
     }
 
@@ -536,44 +1290,46 @@ fn construct_address(
     num_bytes_in_instruction: &mut usize,
     mode: u8,
     rm: u8,
-) -> Result<String> {
-    let mut source_addr_str = String::new();
-
+) -> Result<EffectiveAddress> {
     if mode == 0b00 && rm == 0b110 {
         let direct_address =
             get_byte_or_word(instructions, offset, num_bytes_in_instruction, true) as i16;
-        write!(source_addr_str, "{}", direct_address)?;
-    } else {
-        write!(source_addr_str, "{}", MEM_ADDR_MODE_MAPPING[rm as usize])?;
+
+        return Ok(EffectiveAddress {
+            rm: None,
+            displacement: direct_address,
+            displacement_size: 2,
+            segment: None,
+        });
     }
 
     //* Check if addr should have displacement
     //* by checking the mode != 0b00
     //? Displacements are signed even though the manual says unsigned?
-    if mode != 0b00 {
-        let signed_disp = if mode == 0b10 {
-            get_byte_or_word(instructions, offset, num_bytes_in_instruction, true) as i16
-        } else {
-            get_byte_or_word(instructions, offset, num_bytes_in_instruction, false) as i16
-        };
-
-        //* No need to print displacement if it's 0
-        if signed_disp != 0 {
-            if mode == 0b01 {
-                //* 8 bit displacement
-                write!(source_addr_str, " + {}", signed_disp as i8)?;
-            } else {
-                //* 16 bit displacement
-                write!(source_addr_str, " + {}", signed_disp)?;
-            }
+    let (displacement, displacement_size) = match mode {
+        0b01 => {
+            //* 8 bit displacement
+            let disp = get_byte_or_word(instructions, offset, num_bytes_in_instruction, false);
+            ((disp as u8 as i8) as i16, 1)
         }
-    }
+        0b10 => {
+            //* 16 bit displacement
+            let disp = get_byte_or_word(instructions, offset, num_bytes_in_instruction, true);
+            (disp as i16, 2)
+        }
+        _ => (0, 0),
+    };
 
-    Ok(source_addr_str)
+    Ok(EffectiveAddress {
+        rm: Some(rm),
+        displacement,
+        displacement_size,
+        segment: None,
+    })
 }
 
 fn imm_to_mem(
-    opname: &str,
+    opname: &'static str,
     instructions: &[u8],
     offset: usize,
     num_bytes_in_instruction: &mut usize,
@@ -581,10 +1337,9 @@ fn imm_to_mem(
     rm: u8,
     word: bool,
     is_data_16bit: bool,
-    output: &mut String,
+    output: &mut Instruction,
 ) -> Result<()> {
-    let source_addr_str =
-        construct_address(instructions, offset, num_bytes_in_instruction, mode, rm)?;
+    let address = construct_address(instructions, offset, num_bytes_in_instruction, mode, rm)?;
 
     let data = get_byte_or_word(
         instructions,
@@ -593,13 +1348,16 @@ fn imm_to_mem(
         is_data_16bit,
     );
 
-    if word {
-        writeln!(output, "{} [{}], word {}", opname, source_addr_str, data)?;
-    } else {
-        writeln!(output, "{} [{}], byte {}", opname, source_addr_str, data)?;
-    }
-
-    // println!("{}", output.lines().last().unwrap());
+    //* A byte immediate on a word destination means the sign bit was set
+    output.mnemonic = opname;
+    output.operands = [
+        Some(Operand::Memory(address)),
+        Some(Operand::Immediate(sign_extend_immediate(
+            data,
+            word && !is_data_16bit,
+            word,
+        ))),
+    ];
 
     Ok(())
 }
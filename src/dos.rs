@@ -0,0 +1,407 @@
+use crate::flags::*;
+use crate::instruction::*;
+use crate::memory::*;
+use crate::prelude::*;
+use crate::simulator::*;
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+//* Where .COM programs are loaded, the PSP takes the first 0x100 bytes of the segment
+pub const COM_SEGMENT: u16 = 0x1000;
+pub const COM_OFFSET: u16 = 0x100;
+
+//* stdin, stdout, stderr, aux and prn are always open
+const FIRST_FILE_HANDLE: u16 = 5;
+
+//* DOS error codes returned in ax with the carry flag set
+const ERROR_FILE_NOT_FOUND: u16 = 2;
+const ERROR_PATH_NOT_FOUND: u16 = 3;
+const ERROR_ACCESS_DENIED: u16 = 5;
+const ERROR_INVALID_HANDLE: u16 = 6;
+const ERROR_INVALID_ACCESS: u16 = 12;
+
+const AX: Register = Register { reg: 0, word: true };
+const AL: Register = Register {
+    reg: 0,
+    word: false,
+};
+const AH: Register = Register {
+    reg: 4,
+    word: false,
+};
+const CX: Register = Register { reg: 1, word: true };
+const DX: Register = Register { reg: 2, word: true };
+const DL: Register = Register {
+    reg: 2,
+    word: false,
+};
+const BX: Register = Register { reg: 3, word: true };
+
+//* High level emulation of the DOS and BIOS services simple .COM programs use.
+//* Program output is collected instead of written straight to the terminal
+pub struct DosServices {
+    pub output: Vec<u8>,
+    //* Keys INT 16h and reads from stdin return, in order
+    pub keyboard: VecDeque<u8>,
+    //* Files can only be opened below this directory
+    sandbox: PathBuf,
+    files: HashMap<u16, OpenFile>,
+}
+
+struct OpenFile {
+    file: File,
+    //* What it was opened with, so undoing a close can open it again
+    path: PathBuf,
+    access: u8,
+    //* Bytes read and written so far, there is no seek service
+    position: u64,
+}
+
+//* What DosServices looked like before a step, enough to take back its output, the keys
+//* it read and the files it opened, closed or moved through. File contents aren't put
+//* back, executing the step again writes the same bytes at the same position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServicesMark {
+    output_length: usize,
+    keyboard: VecDeque<u8>,
+    //* Path, access mode and position of each open handle
+    files: BTreeMap<u16, (PathBuf, u8, u64)>,
+}
+
+fn open_options(access: u8) -> Option<OpenOptions> {
+    let mut options = OpenOptions::new();
+    match access & 0b111 {
+        0 => options.read(true),
+        1 => options.write(true),
+        2 => options.read(true).write(true),
+        _ => return None,
+    };
+
+    Some(options)
+}
+
+impl DosServices {
+    pub fn new(sandbox: PathBuf, keyboard: &[u8]) -> Self {
+        Self {
+            output: Vec::new(),
+            keyboard: keyboard.iter().copied().collect(),
+            sandbox,
+            files: HashMap::new(),
+        }
+    }
+
+    //* Only plain relative names, `..` or a drive or root would leave the sandbox
+    fn sandboxed_path(&self, name: &str) -> Option<PathBuf> {
+        let name = name.replace('\\', "/");
+        let path = Path::new(&name);
+
+        let is_contained = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+            && !name.contains(':');
+
+        is_contained.then(|| self.sandbox.join(path))
+    }
+
+    fn open(&mut self, name: &str, access: u8) -> std::result::Result<u16, u16> {
+        let Some(path) = self.sandboxed_path(name) else {
+            return Err(ERROR_PATH_NOT_FOUND);
+        };
+
+        let options = open_options(access).ok_or(ERROR_INVALID_ACCESS)?;
+
+        let file = options.open(&path).map_err(|error| match error.kind() {
+            ErrorKind::NotFound => ERROR_FILE_NOT_FOUND,
+            _ => ERROR_ACCESS_DENIED,
+        })?;
+
+        let handle = (FIRST_FILE_HANDLE..)
+            .find(|handle| !self.files.contains_key(handle))
+            .expect("there is always a free handle");
+        self.files.insert(
+            handle,
+            OpenFile {
+                file,
+                path,
+                access,
+                position: 0,
+            },
+        );
+
+        Ok(handle)
+    }
+
+    fn read(&mut self, handle: u16, length: usize) -> std::result::Result<Vec<u8>, u16> {
+        if handle == 0 {
+            let length = length.min(self.keyboard.len());
+            return Ok(self.keyboard.drain(..length).collect());
+        }
+
+        let open_file = self.files.get_mut(&handle).ok_or(ERROR_INVALID_HANDLE)?;
+
+        let mut bytes = Vec::new();
+        (&mut open_file.file)
+            .take(length as u64)
+            .read_to_end(&mut bytes)
+            .map_err(|_| ERROR_ACCESS_DENIED)?;
+        open_file.position += bytes.len() as u64;
+
+        Ok(bytes)
+    }
+
+    fn write(&mut self, handle: u16, bytes: &[u8]) -> std::result::Result<usize, u16> {
+        if handle == 1 || handle == 2 {
+            self.output.extend_from_slice(bytes);
+            return Ok(bytes.len());
+        }
+
+        let open_file = self.files.get_mut(&handle).ok_or(ERROR_INVALID_HANDLE)?;
+        open_file
+            .file
+            .write_all(bytes)
+            .map_err(|_| ERROR_ACCESS_DENIED)?;
+        open_file.position += bytes.len() as u64;
+
+        Ok(bytes.len())
+    }
+
+    fn close(&mut self, handle: u16) -> std::result::Result<(), u16> {
+        match self.files.remove(&handle) {
+            Some(_) => Ok(()),
+            None => Err(ERROR_INVALID_HANDLE),
+        }
+    }
+
+    pub fn mark(&self) -> ServicesMark {
+        ServicesMark {
+            output_length: self.output.len(),
+            keyboard: self.keyboard.clone(),
+            files: self
+                .files
+                .iter()
+                .map(|(handle, open_file)| {
+                    (
+                        *handle,
+                        (open_file.path.clone(), open_file.access, open_file.position),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    //* Goes back to a mark taken earlier, reopening files closed since and closing the
+    //* ones opened since
+    pub fn restore(&mut self, mark: &ServicesMark) -> Result<()> {
+        self.output.truncate(mark.output_length);
+        self.keyboard = mark.keyboard.clone();
+
+        self.files.retain(|handle, open_file| {
+            mark.files.get(handle).is_some_and(|(path, access, _)| {
+                *path == open_file.path && *access == open_file.access
+            })
+        });
+
+        for (handle, (path, access, position)) in &mark.files {
+            if !self.files.contains_key(handle) {
+                let Some(options) = open_options(*access) else {
+                    bail!("Invalid access mode {:#04x} for {}", access, path.display());
+                };
+                let file = options.open(path)?;
+                self.files.insert(
+                    *handle,
+                    OpenFile {
+                        file,
+                        path: path.clone(),
+                        access: *access,
+                        position: 0,
+                    },
+                );
+            }
+
+            let open_file = self.files.get_mut(handle).expect("opened above");
+            if open_file.position != *position {
+                open_file.file.seek(SeekFrom::Start(*position))?;
+                open_file.position = *position;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn data_segment(cpu: &Cpu) -> u16 {
+    cpu.state.segment_registers[DS as usize]
+}
+
+//* Bytes at ds:dx up to the terminator, which is not included
+fn read_terminated(cpu: &Cpu, terminator: u8) -> Result<Vec<u8>> {
+    let (segment, start) = (data_segment(cpu), cpu.get_register(DX));
+
+    let mut bytes = Vec::new();
+    for i in 0..=u16::MAX {
+        let byte = cpu.memory.read_byte(segment, start.wrapping_add(i));
+        if byte == terminator {
+            return Ok(bytes);
+        }
+        bytes.push(byte);
+    }
+
+    bail!(
+        "String at {:04x}:{:04x} is missing its {:#04x} terminator",
+        segment,
+        start,
+        terminator
+    )
+}
+
+//* File services report errors with the carry flag and the error code in ax
+fn complete(cpu: &mut Cpu, result: std::result::Result<u16, u16>) {
+    match result {
+        Ok(value) => {
+            cpu.set_register(AX, value);
+            cpu.set_flag(FLAG_CARRY, false);
+        }
+        Err(error) => {
+            cpu.set_register(AX, error);
+            cpu.set_flag(FLAG_CARRY, true);
+        }
+    }
+}
+
+fn unsupported(cpu: &Cpu, instruction: &Instruction) -> Result<()> {
+    bail!(
+        "Unsupported service `{}` with ah = {:#04x} at {:04x}:{:04x}",
+        format_instruction(instruction, &Default::default()),
+        cpu.get_register(AH),
+        cpu.state.segment_registers[CS as usize],
+        instruction.offset
+    )
+}
+
+fn dos_service(cpu: &mut Cpu, services: &mut DosServices, instruction: &Instruction) -> Result<()> {
+    match cpu.get_register(AH) {
+        //* Character output
+        0x02 => {
+            let character = cpu.get_register(DL);
+            services.output.push(character as u8);
+            cpu.set_register(AL, character);
+        }
+        //* `$` terminated string output
+        0x09 => {
+            let string = read_terminated(cpu, b'$')?;
+            services.output.extend_from_slice(&string);
+            cpu.set_register(AL, b'$' as u16);
+        }
+        //* Open an existing file, al is the access mode
+        0x3D => {
+            let name = String::from_utf8_lossy(&read_terminated(cpu, 0)?).into_owned();
+            let result = services.open(&name, cpu.get_register(AL) as u8);
+            complete(cpu, result);
+        }
+        0x3E => {
+            let result = services.close(cpu.get_register(BX)).map(|_| 0);
+            complete(cpu, result);
+        }
+        //* Read cx bytes from handle bx into ds:dx
+        0x3F => {
+            let (segment, buffer) = (data_segment(cpu), cpu.get_register(DX));
+            let result = services.read(cpu.get_register(BX), cpu.get_register(CX) as usize);
+
+            if let Ok(bytes) = &result {
+                for (i, byte) in bytes.iter().enumerate() {
+                    cpu.memory
+                        .write_byte(segment, buffer.wrapping_add(i as u16), *byte);
+                }
+            }
+            complete(cpu, result.map(|bytes| bytes.len() as u16));
+        }
+        //* Write cx bytes from ds:dx to handle bx
+        0x40 => {
+            let (segment, buffer) = (data_segment(cpu), cpu.get_register(DX));
+            let bytes: Vec<u8> = (0..cpu.get_register(CX))
+                .map(|i| cpu.memory.read_byte(segment, buffer.wrapping_add(i)))
+                .collect();
+
+            let result = services.write(cpu.get_register(BX), &bytes);
+            complete(cpu, result.map(|length| length as u16));
+        }
+        //* Terminate with the return code in al
+        0x4C => cpu.exit_code = Some(cpu.get_register(AL) as u8),
+        _ => unsupported(cpu, instruction)?,
+    }
+
+    Ok(())
+}
+
+fn video_service(
+    cpu: &mut Cpu,
+    services: &mut DosServices,
+    instruction: &Instruction,
+) -> Result<()> {
+    match cpu.get_register(AH) {
+        //* Teletype output
+        0x0E => services.output.push(cpu.get_register(AL) as u8),
+        _ => unsupported(cpu, instruction)?,
+    }
+
+    Ok(())
+}
+
+fn keyboard_service(
+    cpu: &mut Cpu,
+    services: &mut DosServices,
+    instruction: &Instruction,
+) -> Result<()> {
+    match cpu.get_register(AH) {
+        //* Wait for a key, ah is the scan code which scripted input doesn't have
+        0x00 => {
+            let Some(key) = services.keyboard.pop_front() else {
+                bail!(
+                    "`{}` waits for a key but the scripted keyboard input ran out",
+                    format_instruction(instruction, &Default::default())
+                );
+            };
+            cpu.set_register(AX, key as u16);
+        }
+        //* Check for a key without removing it, zf is set when there is none
+        0x01 => match services.keyboard.front() {
+            Some(key) => {
+                cpu.set_register(AX, *key as u16);
+                cpu.set_flag(FLAG_ZERO, false);
+            }
+            None => cpu.set_flag(FLAG_ZERO, true),
+        },
+        _ => unsupported(cpu, instruction)?,
+    }
+
+    Ok(())
+}
+
+pub fn handle_interrupt(
+    cpu: &mut Cpu,
+    services: &mut DosServices,
+    instruction: &Instruction,
+    vector: u8,
+) -> Result<()> {
+    match vector {
+        0x10 => video_service(cpu, services, instruction),
+        0x16 => keyboard_service(cpu, services, instruction),
+        //* Terminate program
+        0x20 => {
+            cpu.exit_code = Some(0);
+            Ok(())
+        }
+        0x21 => dos_service(cpu, services, instruction),
+        _ => bail!(
+            "Unsupported interrupt `{}` at {:04x}:{:04x}",
+            format_instruction(instruction, &Default::default()),
+            cpu.state.segment_registers[CS as usize],
+            instruction.offset
+        ),
+    }
+}
@@ -0,0 +1,90 @@
+use crate::cfg::*;
+use crate::instruction::*;
+use crate::prelude::*;
+
+use std::{collections::HashMap, fmt::Write};
+
+//* For inside a quoted DOT string
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+//* The label the listing uses for an offset, the offset itself otherwise
+fn block_name(offset: usize, labels: &HashMap<usize, String>) -> String {
+    match labels.get(&offset) {
+        Some(label) => label.clone(),
+        None => format!("{:#06x}", offset),
+    }
+}
+
+fn edge_attributes(kind: EdgeKind) -> &'static str {
+    match kind {
+        EdgeKind::Fallthrough => "label=\"fallthrough\"",
+        EdgeKind::Conditional => "label=\"taken\", color=\"darkgreen\"",
+        EdgeKind::Unconditional => "label=\"jmp\"",
+        EdgeKind::Loop => "label=\"loop\", color=\"darkgreen\"",
+        EdgeKind::JumpTable => "label=\"table\"",
+        EdgeKind::Call => "label=\"call\", style=\"dashed\", color=\"blue\"",
+        EdgeKind::Return => "label=\"return\", style=\"dotted\", color=\"gray\"",
+    }
+}
+
+//* One box per basic block holding its disassembly, render with `dot -Tsvg`
+pub fn write_cfg_dot(cfg: &ControlFlowGraph, labels: &HashMap<usize, String>) -> Result<String> {
+    let mut output = String::new();
+
+    writeln!(output, "digraph cfg {{")?;
+    writeln!(output, "    node [shape=box, fontname=\"monospace\"];")?;
+
+    for block in cfg.blocks.values() {
+        let mut text = format!("{}:\\l", escape_dot(&block_name(block.start, labels)));
+        for instruction in &block.instructions {
+            let line = format_instruction(instruction, labels);
+            write!(text, "    {}\\l", escape_dot(&line))?;
+        }
+
+        writeln!(output, "    b{:x} [label=\"{}\"];", block.start, text)?;
+    }
+
+    for edge in cfg.edges() {
+        writeln!(
+            output,
+            "    b{:x} -> b{:x} [{}];",
+            edge.from,
+            edge.to,
+            edge_attributes(edge.kind)
+        )?;
+    }
+
+    writeln!(output, "}}")?;
+
+    Ok(output)
+}
+
+//* One node per procedure, with an edge for each procedure it calls
+pub fn write_call_graph_dot(
+    cfg: &ControlFlowGraph,
+    labels: &HashMap<usize, String>,
+) -> Result<String> {
+    let mut output = String::new();
+
+    writeln!(output, "digraph calls {{")?;
+    writeln!(output, "    node [shape=box, fontname=\"monospace\"];")?;
+
+    for entry in cfg.procedure_entries() {
+        writeln!(
+            output,
+            "    p{:x} [label=\"{}\"];",
+            entry,
+            escape_dot(&block_name(entry, labels))
+        )?;
+    }
+
+    for (caller, callee) in cfg.call_graph() {
+        writeln!(output, "    p{:x} -> p{:x};", caller, callee)?;
+    }
+
+    writeln!(output, "}}")?;
+
+    Ok(output)
+}
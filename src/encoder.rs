@@ -0,0 +1,358 @@
+use crate::instruction::*;
+use crate::prelude::*;
+
+fn fits_in_i8(value: i16) -> bool {
+    (i8::MIN as i16..=i8::MAX as i16).contains(&value)
+}
+
+fn push_byte_or_word(bytes: &mut Vec<u8>, value: u16, word: bool) {
+    if word {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    } else {
+        bytes.push(value as u8);
+    }
+}
+
+//* Appends the mod reg r/m byte and any displacement for a register or memory operand
+pub fn push_mod_reg_rm(bytes: &mut Vec<u8>, reg: u8, operand: &Operand) -> Result<()> {
+    match operand {
+        Operand::Register(register) => {
+            bytes.push(0b11000000 | (reg << 3) | register.reg);
+        }
+        Operand::Memory(address) => match address.rm {
+            None => {
+                bytes.push((reg << 3) | 0b110);
+                bytes.extend_from_slice(&address.displacement.to_le_bytes());
+            }
+            Some(rm) => {
+                //* Keep the displacement size the address was decoded or parsed with
+                let displacement_size = address
+                    .displacement_size
+                    .max(address.minimal_displacement_size());
+
+                match displacement_size {
+                    0 => bytes.push((reg << 3) | rm),
+                    1 => {
+                        bytes.push(0b01000000 | (reg << 3) | rm);
+                        bytes.push(address.displacement as u8);
+                    }
+                    _ => {
+                        bytes.push(0b10000000 | (reg << 3) | rm);
+                        bytes.extend_from_slice(&address.displacement.to_le_bytes());
+                    }
+                }
+            }
+        },
+        _ => bail!(
+            "Operand {:?} can not be encoded in a mod reg r/m byte",
+            operand
+        ),
+    }
+
+    Ok(())
+}
+
+fn encode_mov(destination: &Operand, source: &Operand, bytes: &mut Vec<u8>) -> Result<()> {
+    match (destination, source) {
+        //* Memory to accumulator or accumulator to memory
+        (Operand::Register(register), Operand::Memory(address))
+            if register.reg == 0 && address.rm.is_none() =>
+        {
+            bytes.push(0b10100000 | u8::from(register.word));
+            bytes.extend_from_slice(&address.displacement.to_le_bytes());
+        }
+        (Operand::Memory(address), Operand::Register(register))
+            if register.reg == 0 && address.rm.is_none() =>
+        {
+            bytes.push(0b10100010 | u8::from(register.word));
+            bytes.extend_from_slice(&address.displacement.to_le_bytes());
+        }
+        //* Register/Memory to/from segment register
+        (Operand::SegmentRegister(sr), _) => {
+            bytes.push(0b10001110);
+            push_mod_reg_rm(bytes, *sr, source)?;
+        }
+        (_, Operand::SegmentRegister(sr)) => {
+            bytes.push(0b10001100);
+            push_mod_reg_rm(bytes, *sr, destination)?;
+        }
+        //* Register/Memory to/from register
+        (Operand::Register(register), Operand::Memory(_)) => {
+            bytes.push(0b10001010 | u8::from(register.word));
+            push_mod_reg_rm(bytes, register.reg, source)?;
+        }
+        (_, Operand::Register(register)) => {
+            bytes.push(0b10001000 | u8::from(register.word));
+            push_mod_reg_rm(bytes, register.reg, destination)?;
+        }
+        //* Immediate to register
+        (Operand::Register(register), Operand::Immediate(immediate)) => {
+            bytes.push(0b10110000 | (u8::from(register.word) << 3) | register.reg);
+            push_byte_or_word(bytes, immediate.value, register.word);
+        }
+        //* Immediate to memory
+        (Operand::Memory(_), Operand::Immediate(immediate)) => {
+            bytes.push(0b11000110 | u8::from(immediate.word));
+            push_mod_reg_rm(bytes, 0b000, destination)?;
+            push_byte_or_word(bytes, immediate.value, immediate.word);
+        }
+        _ => bail!("Invalid mov operands {:?}, {:?}", destination, source),
+    }
+
+    Ok(())
+}
+
+fn encode_add_sub_cmp(
+    reg_mem_to_reg_mem_opcode: u8,
+    imm_to_acc_opcode: u8,
+    group_reg: u8,
+    destination: &Operand,
+    source: &Operand,
+    bytes: &mut Vec<u8>,
+) -> Result<()> {
+    match (destination, source) {
+        //* Reg/Memory to reg/memory
+        (Operand::Register(register), Operand::Memory(_)) => {
+            bytes.push((reg_mem_to_reg_mem_opcode << 2) | 0b10 | u8::from(register.word));
+            push_mod_reg_rm(bytes, register.reg, source)?;
+        }
+        (_, Operand::Register(register)) => {
+            bytes.push((reg_mem_to_reg_mem_opcode << 2) | u8::from(register.word));
+            push_mod_reg_rm(bytes, register.reg, destination)?;
+        }
+        (_, Operand::Immediate(immediate)) => {
+            let is_accumulator =
+                matches!(destination, Operand::Register(register) if register.reg == 0);
+
+            if immediate.word && immediate.sign_extended {
+                //* Sign extended 8 bit immediate
+                if !fits_in_i8(immediate.value as i16) {
+                    bail!(
+                        "Immediate {} does not fit in a sign extended byte",
+                        immediate.value
+                    );
+                }
+
+                bytes.push(0b10000011);
+                push_mod_reg_rm(bytes, group_reg, destination)?;
+                bytes.push(immediate.value as u8);
+            } else if is_accumulator {
+                //* Immediate to accumulator
+                bytes.push((imm_to_acc_opcode << 1) | u8::from(immediate.word));
+                push_byte_or_word(bytes, immediate.value, immediate.word);
+            } else {
+                bytes.push(0b10000000 | u8::from(immediate.word));
+                push_mod_reg_rm(bytes, group_reg, destination)?;
+                push_byte_or_word(bytes, immediate.value, immediate.word);
+            }
+        }
+        _ => bail!("Invalid operands {:?}, {:?}", destination, source),
+    }
+
+    Ok(())
+}
+
+fn encode_conditional_jump(
+    opcode: u8,
+    offset: usize,
+    target: usize,
+    bytes: &mut Vec<u8>,
+) -> Result<()> {
+    let increment = target as isize - (offset as isize + 2);
+    if !(i8::MIN as isize..=i8::MAX as isize).contains(&increment) {
+        bail!(
+            "Jump from {:#x} to {:#x} does not fit in an 8 bit increment",
+            offset,
+            target
+        );
+    }
+
+    bytes.push(opcode);
+    bytes.push(increment as i8 as u8);
+
+    Ok(())
+}
+
+//* The segment override prefix of a memory operand, when it has one
+fn push_segment_prefix(bytes: &mut Vec<u8>, instruction: &Instruction) {
+    for operand in instruction.operands() {
+        if let Operand::Memory(EffectiveAddress {
+            segment: Some(sr), ..
+        }) = operand
+        {
+            bytes.push(0x26 | (sr << 3));
+        }
+    }
+}
+
+//* The 0xFE/0xFF group, `reg` picks the operation
+fn encode_group_fe_ff(
+    instruction: &Instruction,
+    reg: u8,
+    operand: &Operand,
+    bytes: &mut Vec<u8>,
+) -> Result<()> {
+    let Some(word) = instruction.is_word() else {
+        bail!(
+            "Operation size not specified for `{}`",
+            instruction.mnemonic
+        );
+    };
+
+    push_segment_prefix(bytes, instruction);
+    bytes.push(if word { 0xFF } else { 0xFE });
+    push_mod_reg_rm(bytes, reg, operand)
+}
+
+//* Encodes the displacement and immediate sizes the instruction describes,
+//* for choices the assembler syntax can't express it picks what nasm would
+pub fn encode_instruction(instruction: &Instruction) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    if let Some((opcode, _)) = CONDITIONAL_JUMP_MAPPING
+        .iter()
+        .find(|(_, jump_str)| *jump_str == instruction.mnemonic)
+    {
+        let Some(target) = instruction.jump_target() else {
+            bail!("`{}` is missing its jump target", instruction.mnemonic);
+        };
+        encode_conditional_jump(*opcode, instruction.offset, target, &mut bytes)?;
+
+        return Ok(bytes);
+    }
+
+    if let Some((opcode, _)) = FLAG_INSTRUCTION_MAPPING
+        .iter()
+        .find(|(_, mnemonic)| *mnemonic == instruction.mnemonic)
+    {
+        bytes.push(*opcode);
+
+        return Ok(bytes);
+    }
+
+    match (instruction.mnemonic, &instruction.operands) {
+        ("jmp" | "call", [Some(Operand::JumpTarget(target)), None]) => {
+            //* Only jmp has a short form, the decoded or assembled size picks it
+            if instruction.is_short_jump() {
+                encode_conditional_jump(0xEB, instruction.offset, *target, &mut bytes)?;
+            } else {
+                let opcode = if instruction.mnemonic == "call" {
+                    0xE8
+                } else {
+                    0xE9
+                };
+                let increment = (*target as u16).wrapping_sub(instruction.offset as u16 + 3);
+
+                bytes.push(opcode);
+                bytes.extend_from_slice(&increment.to_le_bytes());
+            }
+
+            return Ok(bytes);
+        }
+        ("jmp" | "call", [Some(operand @ (Operand::Register(_) | Operand::Memory(_))), None]) => {
+            let far = instruction.memory_size == Some(MemorySize::Far);
+            let reg = match (instruction.mnemonic, far) {
+                ("call", false) => 0b010,
+                ("call", true) => 0b011,
+                ("jmp", false) => 0b100,
+                _ => 0b101,
+            };
+            encode_group_fe_ff(instruction, reg, operand, &mut bytes)?;
+
+            return Ok(bytes);
+        }
+        ("jmp" | "call", _) => bail!(
+            "`{}` expects a jump target, register or memory",
+            instruction.mnemonic
+        ),
+        //* Word registers have their own one byte encoding
+        ("inc" | "dec", [Some(Operand::Register(register)), None]) if register.word => {
+            let opcode = if instruction.mnemonic == "inc" {
+                0x40
+            } else {
+                0x48
+            };
+            return Ok(vec![opcode | register.reg]);
+        }
+        ("inc" | "dec", [Some(operand @ (Operand::Register(_) | Operand::Memory(_))), None]) => {
+            let reg = if instruction.mnemonic == "inc" {
+                0b000
+            } else {
+                0b001
+            };
+            encode_group_fe_ff(instruction, reg, operand, &mut bytes)?;
+
+            return Ok(bytes);
+        }
+        ("inc" | "dec", _) => bail!("`{}` expects a register or memory", instruction.mnemonic),
+        ("ret" | "retf", [None, None]) => {
+            return Ok(vec![if instruction.mnemonic == "ret" {
+                0xC3
+            } else {
+                0xCB
+            }]);
+        }
+        ("ret" | "retf", [Some(Operand::Immediate(immediate)), None]) => {
+            bytes.push(if instruction.mnemonic == "ret" {
+                0xC2
+            } else {
+                0xCA
+            });
+            bytes.extend_from_slice(&immediate.value.to_le_bytes());
+
+            return Ok(bytes);
+        }
+        ("ret" | "retf", _) => bail!(
+            "`{}` expects at most a byte count to pop",
+            instruction.mnemonic
+        ),
+        ("push" | "pop", [Some(Operand::Register(register)), None]) if register.word => {
+            let opcode = if instruction.mnemonic == "push" {
+                0x50
+            } else {
+                0x58
+            };
+            return Ok(vec![opcode | register.reg]);
+        }
+        ("push", [Some(operand @ Operand::Memory(_)), None]) => {
+            encode_group_fe_ff(instruction, 0b110, operand, &mut bytes)?;
+
+            return Ok(bytes);
+        }
+        ("push", _) => bail!("`push` expects a word register or memory"),
+        ("pop", _) => bail!("`pop` expects a word register"),
+        ("int3", _) => return Ok(vec![0xCC]),
+        ("int", [Some(Operand::Immediate(immediate)), None]) => {
+            return Ok(vec![0xCD, immediate.value as u8]);
+        }
+        ("int", _) => bail!("`int` expects an interrupt type"),
+        _ => {}
+    }
+
+    let [Some(destination), Some(source)] = &instruction.operands else {
+        bail!("`{}` expects two operands", instruction.mnemonic);
+    };
+
+    //* Segment override prefix
+    push_segment_prefix(&mut bytes, instruction);
+
+    if instruction.mnemonic == "mov" {
+        encode_mov(destination, source, &mut bytes)?;
+    } else if let Some((_, reg_mem_opcode, acc_opcode, group_reg)) = ARITHMETIC_OPCODE_MAPPING
+        .iter()
+        .find(|(opname, ..)| *opname == instruction.mnemonic)
+    {
+        encode_add_sub_cmp(
+            *reg_mem_opcode,
+            *acc_opcode,
+            *group_reg,
+            destination,
+            source,
+            &mut bytes,
+        )?;
+    } else {
+        bail!("Encoding `{}` is not supported", instruction.mnemonic);
+    }
+
+    Ok(bytes)
+}
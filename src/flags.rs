@@ -0,0 +1,114 @@
+pub const FLAG_CARRY: u16 = 1 << 0;
+pub const FLAG_PARITY: u16 = 1 << 2;
+pub const FLAG_AUXILIARY_CARRY: u16 = 1 << 4;
+pub const FLAG_ZERO: u16 = 1 << 6;
+pub const FLAG_SIGN: u16 = 1 << 7;
+pub const FLAG_TRAP: u16 = 1 << 8;
+pub const FLAG_INTERRUPT: u16 = 1 << 9;
+pub const FLAG_DIRECTION: u16 = 1 << 10;
+pub const FLAG_OVERFLOW: u16 = 1 << 11;
+
+//* The flags arithmetic and logic instructions set from their result
+pub const STATUS_FLAGS: u16 =
+    FLAG_CARRY | FLAG_PARITY | FLAG_AUXILIARY_CARRY | FLAG_ZERO | FLAG_SIGN | FLAG_OVERFLOW;
+
+//* In the order the reference listings print them
+const FLAG_NAME_MAPPING: [(u16, char); 9] = [
+    (FLAG_CARRY, 'C'),
+    (FLAG_PARITY, 'P'),
+    (FLAG_AUXILIARY_CARRY, 'A'),
+    (FLAG_ZERO, 'Z'),
+    (FLAG_SIGN, 'S'),
+    (FLAG_TRAP, 'T'),
+    (FLAG_INTERRUPT, 'I'),
+    (FLAG_DIRECTION, 'D'),
+    (FLAG_OVERFLOW, 'O'),
+];
+
+fn sign_bit(word: bool) -> u16 {
+    if word {
+        0x8000
+    } else {
+        0x80
+    }
+}
+
+fn mask(word: bool) -> u16 {
+    if word {
+        0xFFFF
+    } else {
+        0xFF
+    }
+}
+
+//* PF, ZF and SF, which every arithmetic and logic instruction derives from the result.
+//* PF only looks at the low byte, even for word operations
+pub fn result_flags(result: u16, word: bool) -> u16 {
+    let mut flags = 0;
+
+    if (result as u8).count_ones().is_multiple_of(2) {
+        flags |= FLAG_PARITY;
+    }
+    if result & mask(word) == 0 {
+        flags |= FLAG_ZERO;
+    }
+    if result & sign_bit(word) != 0 {
+        flags |= FLAG_SIGN;
+    }
+
+    flags
+}
+
+//* add and adc, returns the result and the status flags
+pub fn add_with_flags(left: u16, right: u16, carry: bool, word: bool) -> (u16, u16) {
+    let full = left as u32 + right as u32 + carry as u32;
+    let result = full as u16 & mask(word);
+
+    let mut flags = result_flags(result, word);
+
+    if full > mask(word) as u32 {
+        flags |= FLAG_CARRY;
+    }
+    if (left ^ right ^ result) & 0x10 != 0 {
+        flags |= FLAG_AUXILIARY_CARRY;
+    }
+    //* Both operands have the same sign and the result's sign differs
+    if (left ^ result) & (right ^ result) & sign_bit(word) != 0 {
+        flags |= FLAG_OVERFLOW;
+    }
+
+    (result, flags)
+}
+
+//* sub, sbb and cmp, returns the result and the status flags
+pub fn sub_with_flags(left: u16, right: u16, borrow: bool, word: bool) -> (u16, u16) {
+    let result = left.wrapping_sub(right).wrapping_sub(borrow as u16) & mask(word);
+
+    let mut flags = result_flags(result, word);
+
+    if (left as u32) < right as u32 + borrow as u32 {
+        flags |= FLAG_CARRY;
+    }
+    if (left ^ right ^ result) & 0x10 != 0 {
+        flags |= FLAG_AUXILIARY_CARRY;
+    }
+    //* Operands have different signs and the result's sign differs from the left one
+    if (left ^ right) & (left ^ result) & sign_bit(word) != 0 {
+        flags |= FLAG_OVERFLOW;
+    }
+
+    (result, flags)
+}
+
+//* and, or, xor and test clear CF and OF, AF is undefined and left cleared
+pub fn logic_flags(result: u16, word: bool) -> u16 {
+    result_flags(result, word)
+}
+
+pub fn format_flags(flags: u16) -> String {
+    FLAG_NAME_MAPPING
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| name)
+        .collect()
+}
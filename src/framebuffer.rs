@@ -0,0 +1,248 @@
+use crate::assembler::parse_number;
+use crate::memory::*;
+use crate::prelude::*;
+
+use std::{path::Path, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelFormat {
+    //* 4 bytes per pixel, alpha is ignored. What listing 54 draws
+    #[default]
+    Rgba,
+    Rgb,
+    Bgra,
+    //* 1 byte per pixel
+    Gray,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba | PixelFormat::Bgra => 4,
+            PixelFormat::Rgb => 3,
+            PixelFormat::Gray => 1,
+        }
+    }
+
+    fn to_rgb(self, pixel: &[u8]) -> [u8; 3] {
+        match self {
+            PixelFormat::Rgba | PixelFormat::Rgb => [pixel[0], pixel[1], pixel[2]],
+            PixelFormat::Bgra => [pixel[2], pixel[1], pixel[0]],
+            PixelFormat::Gray => [pixel[0]; 3],
+        }
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rgba" => Ok(PixelFormat::Rgba),
+            "rgb" => Ok(PixelFormat::Rgb),
+            "bgra" => Ok(PixelFormat::Bgra),
+            "gray" => Ok(PixelFormat::Gray),
+            _ => bail!(
+                "Invalid pixel format `{}`, expected rgba, rgb, bgra or gray",
+                s
+            ),
+        }
+    }
+}
+
+//* A region of memory holding pixels, row after row without padding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    //* Physical address of the first pixel
+    pub address: usize,
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+}
+
+impl Default for Framebuffer {
+    //* The 64x64 image listing 54 draws at 256
+    fn default() -> Self {
+        Self {
+            address: 256,
+            width: 64,
+            height: 64,
+            format: PixelFormat::Rgba,
+        }
+    }
+}
+
+impl Framebuffer {
+    pub fn size_in_bytes(&self) -> usize {
+        self.width * self.height * self.format.bytes_per_pixel()
+    }
+
+    //* 3 bytes per pixel, the layout both PPM and PNG store
+    pub fn to_rgb(&self, memory: &Memory) -> Result<Vec<u8>> {
+        let pixels = memory.range(self.address, self.size_in_bytes())?;
+
+        Ok(pixels
+            .chunks_exact(self.format.bytes_per_pixel())
+            .flat_map(|pixel| self.format.to_rgb(pixel))
+            .collect())
+    }
+}
+
+//* `address,WIDTHxHEIGHT[,format]`, e.g. `256,64x64,rgba`
+impl FromStr for Framebuffer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+
+        let (address, size, format) = match parts.as_slice() {
+            [address, size] => (address, size, PixelFormat::default()),
+            [address, size, format] => (address, size, format.parse()?),
+            _ => bail!(
+                "Invalid framebuffer `{}`, expected address,WIDTHxHEIGHT[,format]",
+                s
+            ),
+        };
+
+        let Some((width, height)) = size.split_once('x') else {
+            bail!("Invalid framebuffer size `{}`, expected WIDTHxHEIGHT", size);
+        };
+
+        let number = |text: &str| -> Result<usize> {
+            let value = parse_number(text)?;
+            if value < 0 {
+                bail!("Framebuffer values can not be negative, got `{}`", text);
+            }
+            Ok(value as usize)
+        };
+
+        Ok(Self {
+            address: number(address)?,
+            width: number(width)?,
+            height: number(height)?,
+            format,
+        })
+    }
+}
+
+//* Binary PPM (P6)
+pub fn encode_ppm(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    bytes.extend_from_slice(rgb);
+    bytes
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+
+    table
+};
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+pub fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    (b << 16) | a
+}
+
+//* zlib stream of stored (uncompressed) deflate blocks, which needs no compressor
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK_SIZE: usize = 0xFFFF;
+
+    let mut bytes = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        bytes.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let length = block.len() as u16;
+
+        bytes.push(u8::from(is_final));
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(&(!length).to_le_bytes());
+        bytes.extend_from_slice(block);
+    }
+
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
+
+fn push_png_chunk(bytes: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = bytes.len();
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(data);
+    let crc = crc32(&bytes[start..]);
+
+    bytes.extend_from_slice(&crc.to_be_bytes());
+}
+
+//* 8 bit truecolor PNG, rows are stored unfiltered
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    //* Bit depth 8, color type 2 (rgb), default compression, filter and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    push_png_chunk(&mut bytes, b"IHDR", &header);
+
+    let mut scanlines = Vec::with_capacity(rgb.len() + height);
+    for row in rgb.chunks(width * 3).take(height) {
+        //* Filter type 0, none
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    push_png_chunk(&mut bytes, b"IDAT", &zlib_stored(&scanlines));
+
+    push_png_chunk(&mut bytes, b"IEND", &[]);
+
+    bytes
+}
+
+//* Picks PNG or PPM from the file extension
+pub fn write_image(path: &Path, memory: &Memory, framebuffer: &Framebuffer) -> Result<()> {
+    let rgb = framebuffer.to_rgb(memory)?;
+
+    let bytes = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => encode_png(framebuffer.width, framebuffer.height, &rgb),
+        Some("ppm") => encode_ppm(framebuffer.width, framebuffer.height, &rgb),
+        _ => bail!(
+            "Unknown image format for `{}`, expected a .png or .ppm file",
+            path.display()
+        ),
+    };
+
+    std::fs::write(path, bytes)?;
+
+    Ok(())
+}
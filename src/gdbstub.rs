@@ -0,0 +1,407 @@
+use crate::memory::*;
+use crate::prelude::*;
+use crate::simulator::*;
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    io::{ErrorKind, Read, Write as IoWrite},
+    net::{TcpListener, TcpStream},
+};
+
+pub const DEFAULT_GDB_ADDRESS: &str = "127.0.0.1:1234";
+
+//* gdb's i8086 architecture uses the i386 register file: eax, ecx, edx, ebx, esp, ebp,
+//* esi, edi, eip, eflags, cs, ss, ds, es, fs, gs, 32 bits each.
+//* gdb has no notion of segments, so eip holds the physical address of CS:IP, the same
+//* address space memory and breakpoint packets use
+const GDB_REGISTER_COUNT: usize = 16;
+const GDB_IP: usize = 8;
+const GDB_FLAGS: usize = 9;
+//* Index of each segment register in SEGMENT_REGISTER_NAME_MAPPING, in gdb's order
+const GDB_SEGMENT_REGISTERS: [usize; 4] = [1, 2, 3, 0];
+
+//* Steps between checks for a ^C from gdb while continuing
+const INTERRUPT_POLL_INTERVAL: usize = 1024;
+
+//* Stop replies
+const SIGINT: &str = "S02";
+const SIGTRAP: &str = "S05";
+const EXITED: &str = "W00";
+
+pub struct GdbStub {
+    pub cpu: Cpu,
+    //* Physical addresses of software breakpoints
+    pub breakpoints: BTreeSet<usize>,
+}
+
+//* What the connection should do after a packet was handled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Packet(String),
+    //* Run until a breakpoint, checking for an interrupt from gdb in between
+    Continue,
+    Close,
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        bail!("Odd number of hex digits in `{}`", text);
+    }
+
+    text.as_bytes()
+        .chunks(2)
+        .map(|digits| Ok(u8::from_str_radix(std::str::from_utf8(digits)?, 16)?))
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut output, byte| {
+        let _ = write!(output, "{:02x}", byte);
+        output
+    })
+}
+
+fn parse_hex(text: &str) -> Result<usize> {
+    Ok(usize::from_str_radix(text, 16)?)
+}
+
+//* `addr,length`
+fn parse_range(text: &str) -> Result<(usize, usize)> {
+    let Some((address, length)) = text.split_once(',') else {
+        bail!("Invalid memory range `{}`", text);
+    };
+
+    Ok((parse_hex(address)?, parse_hex(length)?))
+}
+
+pub fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+//* `$data#checksum`
+pub fn encode_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+impl GdbStub {
+    pub fn new(cpu: Cpu) -> Self {
+        Self {
+            cpu,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    fn read_register(&self, index: usize) -> Option<u32> {
+        let state = &self.cpu.state;
+
+        match index {
+            0..WORD_REGISTER_COUNT => Some(state.registers[index] as u32),
+            GDB_IP => Some(self.physical_ip() as u32),
+            GDB_FLAGS => Some(state.flags as u32),
+            10..=13 => Some(state.segment_registers[GDB_SEGMENT_REGISTERS[index - 10]] as u32),
+            //* fs and gs don't exist on the 8086
+            14 | 15 => Some(0),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, index: usize, value: u32) -> Result<()> {
+        let state = &mut self.cpu.state;
+
+        match index {
+            0..WORD_REGISTER_COUNT => state.registers[index] = value as u16,
+            GDB_IP => self.set_physical_ip(value as usize),
+            GDB_FLAGS => state.flags = value as u16,
+            10..=13 => state.segment_registers[GDB_SEGMENT_REGISTERS[index - 10]] = value as u16,
+            14 | 15 => {}
+            _ => bail!("No register {}", index),
+        }
+
+        Ok(())
+    }
+
+    fn physical_ip(&self) -> usize {
+        let code_segment = self.cpu.state.segment_registers[CS as usize];
+        Memory::physical_address(code_segment, self.cpu.state.ip)
+    }
+
+    //* Moves ip so CS:IP is the physical address, CS stays as it is
+    fn set_physical_ip(&mut self, physical_address: usize) {
+        let code_segment =
+            Memory::physical_address(self.cpu.state.segment_registers[CS as usize], 0);
+        self.cpu.state.ip = physical_address.wrapping_sub(code_segment) as u16;
+    }
+
+    //* Executes one instruction, returning the stop reply
+    pub fn step(&mut self) -> String {
+        if self.cpu.is_halted() {
+            return EXITED.to_owned();
+        }
+
+        match self.cpu.step() {
+            Ok(_) if self.cpu.is_halted() => EXITED.to_owned(),
+            Ok(_) => SIGTRAP.to_owned(),
+            //* Report an instruction the simulator can't execute as an illegal instruction
+            Err(_) => "S04".to_owned(),
+        }
+    }
+
+    //* Steps at most `limit` instructions, stopping at breakpoints.
+    //* None means the limit ran out while still running
+    pub fn run(&mut self, limit: usize) -> Option<String> {
+        for _ in 0..limit {
+            let reply = self.step();
+            if reply != SIGTRAP || self.breakpoints.contains(&self.physical_ip()) {
+                return Some(reply);
+            }
+        }
+
+        None
+    }
+
+    fn handle_memory_write(&mut self, args: &str) -> Result<()> {
+        let Some((range, data)) = args.split_once(':') else {
+            bail!("Invalid memory write `{}`", args);
+        };
+        let (address, length) = parse_range(range)?;
+        let bytes = decode_hex(data)?;
+
+        if bytes.len() != length
+            || address
+                .checked_add(length)
+                .is_none_or(|end| end > MEMORY_SIZE)
+        {
+            bail!("Invalid memory write `{}`", args);
+        }
+
+        for (i, byte) in bytes.iter().enumerate() {
+            self.cpu.memory.restore_byte(address + i, *byte);
+        }
+
+        Ok(())
+    }
+
+    fn handle_packet_inner(&mut self, packet: &str) -> Result<Reply> {
+        let reply = |text: &str| Ok(Reply::Packet(text.to_owned()));
+
+        let Some(command) = packet.chars().next() else {
+            return reply("");
+        };
+        let args = &packet[command.len_utf8()..];
+
+        match command {
+            '?' => reply(SIGTRAP),
+            'g' => {
+                let mut registers = String::new();
+                for index in 0..GDB_REGISTER_COUNT {
+                    let value = self.read_register(index).unwrap_or_default();
+                    registers.push_str(&encode_hex(&value.to_le_bytes()));
+                }
+                reply(&registers)
+            }
+            'G' => {
+                let bytes = decode_hex(args)?;
+                let values: Vec<u32> = bytes
+                    .chunks_exact(4)
+                    .take(GDB_REGISTER_COUNT)
+                    .map(|value| u32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                    .collect();
+                //* eip goes last, it is relative to the cs written along with it
+                for (index, value) in values.iter().enumerate() {
+                    if index != GDB_IP {
+                        self.write_register(index, *value)?;
+                    }
+                }
+                if let Some(ip) = values.get(GDB_IP) {
+                    self.write_register(GDB_IP, *ip)?;
+                }
+                reply("OK")
+            }
+            'p' => match self.read_register(parse_hex(args)?) {
+                Some(value) => reply(&encode_hex(&value.to_le_bytes())),
+                None => reply("E45"),
+            },
+            'P' => {
+                let Some((index, value)) = args.split_once('=') else {
+                    bail!("Invalid register write `{}`", args);
+                };
+                let bytes = decode_hex(value)?;
+                if !(2..=4).contains(&bytes.len()) {
+                    bail!("Invalid register value `{}`", value);
+                }
+                let value = bytes
+                    .iter()
+                    .rev()
+                    .fold(0, |value, byte| (value << 8) | *byte as u32);
+                self.write_register(parse_hex(index)?, value)?;
+                reply("OK")
+            }
+            'm' => {
+                let (address, length) = parse_range(args)?;
+                reply(&encode_hex(self.cpu.memory.range(address, length)?))
+            }
+            'M' => {
+                self.handle_memory_write(args)?;
+                reply("OK")
+            }
+            //* Software and hardware breakpoints behave the same in the simulator
+            'Z' | 'z' => {
+                let mut fields = args.split(',');
+                let (Some(kind), Some(address)) = (fields.next(), fields.next()) else {
+                    bail!("Invalid breakpoint `{}`", packet);
+                };
+                if kind != "0" && kind != "1" {
+                    return reply("");
+                }
+
+                let address = parse_hex(address)?;
+                if command == 'Z' {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                reply("OK")
+            }
+            's' => {
+                if !args.is_empty() {
+                    self.set_physical_ip(parse_hex(args)?);
+                }
+                reply(&self.step())
+            }
+            'c' => {
+                if !args.is_empty() {
+                    self.set_physical_ip(parse_hex(args)?);
+                }
+                Ok(Reply::Continue)
+            }
+            'H' => reply("OK"),
+            'k' => Ok(Reply::Close),
+            'D' => reply("OK"),
+            'q' if args.starts_with("Supported") => reply("PacketSize=4000"),
+            'q' if args == "Attached" => reply("1"),
+            'q' if args == "C" => reply("QC1"),
+            'q' if args == "fThreadInfo" => reply("m1"),
+            'q' if args == "sThreadInfo" => reply("l"),
+            //* Anything else is unsupported, which gdb expects an empty reply for
+            _ => reply(""),
+        }
+    }
+
+    //* Errors become `E01` replies instead of dropping the connection
+    pub fn handle_packet(&mut self, packet: &str) -> Reply {
+        self.handle_packet_inner(packet)
+            .unwrap_or_else(|_| Reply::Packet("E01".to_owned()))
+    }
+}
+
+fn read_byte(stream: &mut TcpStream) -> Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+//* A ^C outside of a packet is gdb asking to stop
+const INTERRUPT: u8 = 0x03;
+
+//* Reads the next packet, acknowledging it. None once gdb disconnects
+fn read_packet(stream: &mut TcpStream) -> Result<Option<String>> {
+    loop {
+        //* Skip acks and anything else before the start of a packet
+        match read_byte(stream)? {
+            None => return Ok(None),
+            Some(b'$') => {}
+            Some(INTERRUPT) => return Ok(Some((INTERRUPT as char).to_string())),
+            Some(_) => continue,
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+
+        let mut checksum_digits = [0; 2];
+        stream.read_exact(&mut checksum_digits)?;
+
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let expected = u8::from_str_radix(&String::from_utf8_lossy(&checksum_digits), 16).ok();
+
+        if expected == Some(checksum(&data)) {
+            stream.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+
+        //* Ask for a retransmission
+        stream.write_all(b"-")?;
+    }
+}
+
+fn write_packet(stream: &mut TcpStream, data: &str) -> Result<()> {
+    stream.write_all(encode_packet(data).as_bytes())?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+//* Checks for a ^C without blocking
+fn is_interrupted(stream: &mut TcpStream) -> Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = stream.read(&mut byte);
+    stream.set_nonblocking(false)?;
+
+    match result {
+        Ok(1) => Ok(byte[0] == INTERRUPT),
+        Ok(_) => Ok(false),
+        Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+//* Serves a single gdb connection until it detaches, kills the target or disconnects
+pub fn serve_connection(stub: &mut GdbStub, stream: &mut TcpStream) -> Result<()> {
+    stream.set_nodelay(true)?;
+
+    while let Some(packet) = read_packet(stream)? {
+        if packet == (INTERRUPT as char).to_string() {
+            write_packet(stream, SIGINT)?;
+            continue;
+        }
+
+        match stub.handle_packet(&packet) {
+            Reply::Packet(reply) => {
+                write_packet(stream, &reply)?;
+                if packet.starts_with('D') {
+                    break;
+                }
+            }
+            Reply::Continue => {
+                let reply = loop {
+                    if let Some(reply) = stub.run(INTERRUPT_POLL_INTERVAL) {
+                        break reply;
+                    }
+                    if is_interrupted(stream)? {
+                        break SIGINT.to_owned();
+                    }
+                };
+                write_packet(stream, &reply)?;
+            }
+            Reply::Close => break,
+        }
+    }
+
+    Ok(())
+}
+
+//* Waits for gdb to attach, e.g. `target remote 127.0.0.1:1234` after `set architecture i8086`
+pub fn serve(stub: &mut GdbStub, listener: &TcpListener) -> Result<()> {
+    let (mut stream, _) = listener.accept()?;
+
+    serve_connection(stub, &mut stream)
+}
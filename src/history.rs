@@ -0,0 +1,279 @@
+use crate::dos::*;
+use crate::instruction::*;
+use crate::memory::*;
+use crate::prelude::*;
+use crate::simulator::*;
+
+use std::collections::VecDeque;
+
+//* Steps kept as undoable deltas before the oldest ones are dropped
+pub const DEFAULT_CAPACITY: usize = 10_000;
+//* Steps between full snapshots, which replay forward to anything the deltas no longer cover
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1_000;
+pub const DEFAULT_MAX_SNAPSHOTS: usize = 16;
+
+//* What executing one instruction changed, enough to undo it
+#[derive(Debug, Clone)]
+pub struct StepRecord {
+    //* Number of instructions executed before this one
+    pub step: u64,
+    pub instruction: Instruction,
+    //* State and clock total before the instruction executed
+    pub state: CpuState,
+    pub clocks: u64,
+    pub writes: Vec<MemoryWrite>,
+    //* DOS services before an interrupt, other instructions don't touch them
+    pub services: Option<ServicesMark>,
+}
+
+struct Snapshot {
+    step: u64,
+    state: CpuState,
+    clocks: u64,
+    memory: Memory,
+    services: Option<ServicesMark>,
+}
+
+impl Snapshot {
+    fn new(step: u64, cpu: &Cpu) -> Self {
+        Self {
+            step,
+            state: cpu.state,
+            clocks: cpu.clocks,
+            memory: cpu.memory.clone(),
+            services: cpu.services.as_ref().map(DosServices::mark),
+        }
+    }
+}
+
+pub struct History {
+    records: VecDeque<StepRecord>,
+    capacity: usize,
+    //* Step 0 is kept apart so the program start is always reachable
+    initial: Snapshot,
+    snapshots: VecDeque<Snapshot>,
+    snapshot_interval: u64,
+    max_snapshots: usize,
+    //* Number of instructions executed so far
+    pub step: u64,
+}
+
+impl History {
+    pub fn new(cpu: &Cpu) -> Self {
+        Self::with_limits(
+            cpu,
+            DEFAULT_CAPACITY,
+            DEFAULT_SNAPSHOT_INTERVAL,
+            DEFAULT_MAX_SNAPSHOTS,
+        )
+    }
+
+    pub fn with_limits(
+        cpu: &Cpu,
+        capacity: usize,
+        snapshot_interval: u64,
+        max_snapshots: usize,
+    ) -> Self {
+        Self {
+            records: VecDeque::new(),
+            capacity,
+            initial: Snapshot::new(0, cpu),
+            snapshots: VecDeque::new(),
+            snapshot_interval: snapshot_interval.max(1),
+            max_snapshots,
+            step: 0,
+        }
+    }
+
+    //* Executes one instruction, recording how to undo it. Returns its trace line
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<String> {
+        let has_snapshot = self
+            .snapshots
+            .back()
+            .is_some_and(|snapshot| snapshot.step == self.step);
+
+        if self.step > 0 && self.step.is_multiple_of(self.snapshot_interval) && !has_snapshot {
+            if self.snapshots.len() == self.max_snapshots {
+                self.snapshots.pop_front();
+            }
+            if self.max_snapshots > 0 {
+                self.snapshots.push_back(Snapshot::new(self.step, cpu));
+            }
+        }
+
+        let (state, clocks) = (cpu.state, cpu.clocks);
+        let instruction = cpu.decode_at_ip()?;
+        let services = match instruction.mnemonic {
+            "int" | "int3" => cpu.services.as_ref().map(DosServices::mark),
+            _ => None,
+        };
+
+        cpu.memory.start_journal();
+        let trace = step_with_trace(cpu, true);
+        let writes = cpu.memory.take_journal();
+
+        if let Err(error) = trace {
+            //* Leave nothing half executed behind
+            undo(cpu, &state, clocks, &writes, services.as_ref())?;
+            return Err(error);
+        }
+
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        if self.capacity > 0 {
+            self.records.push_back(StepRecord {
+                step: self.step,
+                instruction,
+                state,
+                clocks,
+                writes,
+                services,
+            });
+        }
+        self.step += 1;
+
+        trace
+    }
+
+    //* Moves the cpu to the moment before instruction number `target` executed.
+    //* Undoes recorded deltas when they reach back far enough, otherwise restores
+    //* the closest earlier snapshot and executes forward from there
+    pub fn goto(&mut self, cpu: &mut Cpu, target: u64) -> Result<()> {
+        if target > self.step {
+            bail!(
+                "Step {} has not been executed yet, the current step is {}",
+                target,
+                self.step
+            );
+        }
+
+        let reachable_by_undo = match self.records.front() {
+            Some(oldest) => oldest.step <= target,
+            None => target == self.step,
+        };
+
+        if reachable_by_undo {
+            while self.step > target {
+                let record = self
+                    .records
+                    .pop_back()
+                    .expect("records cover every step back to the target");
+
+                undo(
+                    cpu,
+                    &record.state,
+                    record.clocks,
+                    &record.writes,
+                    record.services.as_ref(),
+                )?;
+                self.step = record.step;
+            }
+        } else {
+            let snapshot = self
+                .snapshots
+                .iter()
+                .rev()
+                .find(|snapshot| snapshot.step <= target)
+                .unwrap_or(&self.initial);
+
+            cpu.state = snapshot.state;
+            cpu.clocks = snapshot.clocks;
+            cpu.memory = snapshot.memory.clone();
+            cpu.exit_code = None;
+            if let (Some(services), Some(mark)) = (&mut cpu.services, &snapshot.services) {
+                services.restore(mark)?;
+            }
+            self.step = snapshot.step;
+            self.records.clear();
+
+            let snapshot_step = self.step;
+            self.snapshots
+                .retain(|snapshot| snapshot.step <= snapshot_step);
+
+            while self.step < target {
+                self.step(cpu)?;
+            }
+        }
+
+        //* Snapshots past the target get taken again when execution gets there
+        self.snapshots.retain(|snapshot| snapshot.step <= target);
+        cpu.last_clocks = None;
+
+        Ok(())
+    }
+
+    pub fn back(&mut self, cpu: &mut Cpu, count: u64) -> Result<()> {
+        if count > self.step {
+            bail!("Only {} instructions have been executed", self.step);
+        }
+
+        self.goto(cpu, self.step - count)
+    }
+
+    //* Moves to the moment before the instruction at `ip` executed for the `count`th time,
+    //* counting from the start of the program
+    pub fn rewind_to_hit(&mut self, cpu: &mut Cpu, ip: u16, count: u64) -> Result<()> {
+        if count == 0 {
+            bail!("Hit counts start at 1");
+        }
+
+        let current = self.step;
+        self.goto(cpu, 0)?;
+
+        let mut hits = 0;
+        loop {
+            if cpu.state.ip == ip {
+                hits += 1;
+                if hits == count {
+                    return Ok(());
+                }
+            }
+
+            if self.step == current {
+                break;
+            }
+            self.step(cpu)?;
+        }
+
+        bail!(
+            "{:#06x} was reached {} time(s) up to step {}",
+            ip,
+            hits,
+            current
+        )
+    }
+
+    //* Most recent recorded instruction that wrote the byte at a physical address
+    pub fn last_write(&self, physical_address: usize) -> Option<&StepRecord> {
+        self.records.iter().rev().find(|record| {
+            record
+                .writes
+                .iter()
+                .any(|write| write.address == physical_address)
+        })
+    }
+}
+
+fn undo(
+    cpu: &mut Cpu,
+    state: &CpuState,
+    clocks: u64,
+    writes: &[MemoryWrite],
+    services: Option<&ServicesMark>,
+) -> Result<()> {
+    for write in writes.iter().rev() {
+        cpu.memory.restore_byte(write.address, write.old);
+    }
+
+    cpu.state = *state;
+    cpu.clocks = clocks;
+    //* Nothing executes after the program exits, so every undone step ran before it
+    cpu.exit_code = None;
+
+    if let (Some(services), Some(mark)) = (&mut cpu.services, services) {
+        services.restore(mark)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use crate::instruction::*;
+use crate::prelude::*;
+
+use std::{collections::HashMap, fmt::Write};
+
+const STYLESHEET: &str = "
+body { margin: 0; display: flex; font-family: monospace; background: #1e1e1e; color: #d4d4d4; }
+nav { position: sticky; top: 0; height: 100vh; overflow-y: auto; min-width: 12em; padding: 1em; background: #252526; }
+nav a { display: block; color: #c586c0; text-decoration: none; }
+main { padding: 1em; flex: 1; }
+table { border-collapse: collapse; }
+td { padding: 0 1em 0 0; white-space: pre; }
+tr:hover { background: #2a2d2e; }
+tr:target { background: #37373d; }
+.offset { color: #858585; }
+.bytes { color: #6a9955; }
+.label { color: #c586c0; font-weight: bold; }
+.mnemonic { color: #569cd6; font-weight: bold; }
+.register { color: #4ec9b0; }
+.immediate { color: #dcdcaa; }
+.memory { color: #9cdcfe; }
+.size-hint { color: #808080; }
+a.jump-target { color: #c586c0; }
+";
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+//* Wraps each token in a span, jump targets become links to the label's anchor
+pub struct HtmlStyle;
+
+impl Style for HtmlStyle {
+    fn write_token(&self, output: &mut String, kind: TokenKind, text: &str) -> Result<()> {
+        let text = escape_html(text);
+
+        let class = match kind {
+            TokenKind::Mnemonic => "mnemonic",
+            TokenKind::Register => "register",
+            TokenKind::Immediate => "immediate",
+            TokenKind::Memory => "memory",
+            TokenKind::SizeHint => "size-hint",
+            TokenKind::Label => "label",
+            TokenKind::JumpTarget => {
+                //* Unlabelled targets are written relative to `$` and have nothing to link to
+                if text.starts_with('$') {
+                    write!(output, "<span class=\"jump-target\">{}</span>", text)?;
+                } else {
+                    write!(
+                        output,
+                        "<a class=\"jump-target\" href=\"#{}\">{}</a>",
+                        text, text
+                    )?;
+                }
+                return Ok(());
+            }
+            TokenKind::Punctuation => {
+                output.push_str(&text);
+                return Ok(());
+            }
+        };
+
+        write!(output, "<span class=\"{}\">{}</span>", class, text)?;
+
+        Ok(())
+    }
+}
+
+//* Builds a single self contained page, `instructions` is the raw image the listing was decoded from
+pub fn write_html_report(
+    title: &str,
+    instructions: &[u8],
+    decoded: &[Instruction],
+    labels: &HashMap<usize, String>,
+) -> Result<String> {
+    let mut output = String::new();
+    let title = escape_html(title);
+
+    writeln!(output, "<!DOCTYPE html>")?;
+    writeln!(output, "<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(output, "<title>{}</title>", title)?;
+    writeln!(output, "<style>{}</style>\n</head>\n<body>", STYLESHEET)?;
+
+    //* Sidebar index of labels, sorted by the address they point at
+    let mut sorted_labels: Vec<_> = labels.iter().collect();
+    sorted_labels.sort();
+
+    writeln!(output, "<nav>\n<h3>Labels</h3>")?;
+    for (offset, label) in sorted_labels {
+        let label = escape_html(label);
+        writeln!(
+            output,
+            "<a href=\"#{}\">{} <span class=\"offset\">{:04x}</span></a>",
+            label, label, offset
+        )?;
+    }
+    writeln!(output, "</nav>")?;
+
+    writeln!(output, "<main>\n<h2>{}</h2>\n<table>", title)?;
+    for ins in decoded {
+        let raw_bytes = format_bytes(&instructions[ins.offset..ins.offset + ins.size]);
+
+        if let Some(label) = labels.get(&ins.offset) {
+            let label = escape_html(label);
+            writeln!(
+                output,
+                "<tr id=\"{}\"><td></td><td colspan=\"2\"><span class=\"label\">{}:</span></td></tr>",
+                label, label
+            )?;
+        }
+
+        let mut line = String::new();
+        write_instruction(&mut line, ins, labels, &HtmlStyle)?;
+
+        writeln!(
+            output,
+            "<tr id=\"offset_{:04x}\" title=\"{}\"><td class=\"offset\">{:04x}</td><td class=\"bytes\">{}</td><td>{}</td></tr>",
+            ins.offset, raw_bytes, ins.offset, raw_bytes, line
+        )?;
+    }
+    writeln!(output, "</table>\n</main>\n</body>\n</html>")?;
+
+    Ok(output)
+}
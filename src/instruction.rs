@@ -0,0 +1,472 @@
+use crate::prelude::*;
+
+use std::{collections::HashMap, fmt::Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Register {
+    pub reg: u8,
+    pub word: bool,
+}
+
+impl Register {
+    pub fn name(&self) -> &'static str {
+        get_register_name(self.reg, self.word)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectiveAddress {
+    //* None for the direct address case (mode = 00, rm = 110)
+    pub rm: Option<u8>,
+    pub displacement: i16,
+    //* Number of displacement bytes actually present in the encoding: 0, 1 or 2
+    pub displacement_size: u8,
+    //* Segment override prefix, indexes SEGMENT_REGISTER_NAME_MAPPING
+    pub segment: Option<u8>,
+}
+
+impl EffectiveAddress {
+    //* Shortest displacement that can express the address, the way nasm picks it
+    pub fn minimal_displacement_size(&self) -> u8 {
+        let fits_in_i8 = (i8::MIN as i16..=i8::MAX as i16).contains(&self.displacement);
+
+        match self.rm {
+            None => 2,
+            //* [bp] has no mode 00 encoding, that slot is the direct address
+            Some(0b110) if self.displacement == 0 => 1,
+            Some(_) if self.displacement == 0 => 0,
+            Some(_) if fits_in_i8 => 1,
+            Some(_) => 2,
+        }
+    }
+
+    pub fn has_minimal_displacement(&self) -> bool {
+        self.displacement_size <= self.minimal_displacement_size()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Immediate {
+    pub value: u16,
+    pub word: bool,
+    //* Set when an 8 bit immediate was sign extended to a word (s = 1, w = 1)
+    pub sign_extended: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    SegmentRegister(u8),
+    Memory(EffectiveAddress),
+    Immediate(Immediate),
+    JumpTarget(usize),
+}
+
+//* How bytes marked as data are written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataKind {
+    Byte,
+    Word,
+    //* Printable runs quoted, everything else as bytes
+    String,
+    //* Words holding code offsets, e.g. a jump table, written as labels where there is one
+    Address,
+}
+
+//* Width of a memory operand nothing else in the instruction implies, e.g. `inc byte [bx]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemorySize {
+    Byte,
+    Word,
+    //* A segment:offset pointer for the far forms of call and jmp
+    Far,
+}
+
+impl MemorySize {
+    pub fn name(self) -> &'static str {
+        match self {
+            MemorySize::Byte => "byte",
+            MemorySize::Word => "word",
+            MemorySize::Far => "far",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Data {
+    pub kind: DataKind,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Instruction {
+    pub offset: usize,
+    pub size: NumBytesInInstruction,
+    pub mnemonic: &'static str,
+    pub operands: [Option<Operand>; 2],
+    //* Set for bytes that are data rather than code, written as `db`/`dw`
+    pub data: Option<Data>,
+    //* Only set for the single memory operand of inc, dec, push and indirect call/jmp
+    pub memory_size: Option<MemorySize>,
+}
+
+impl Instruction {
+    pub fn operands(&self) -> impl Iterator<Item = &Operand> {
+        self.operands.iter().flatten()
+    }
+
+    //* Width of the data being operated on, if it can be determined from the operands
+    pub fn is_word(&self) -> Option<bool> {
+        if let Some(size) = self.memory_size {
+            return Some(size != MemorySize::Byte);
+        }
+
+        self.operands().find_map(|operand| match operand {
+            Operand::Register(register) => Some(register.word),
+            Operand::SegmentRegister(_) => Some(true),
+            Operand::Immediate(immediate) => Some(immediate.word),
+            _ => None,
+        })
+    }
+
+    //* An immediate stored to memory needs an explicit byte/word for the assembler
+    pub fn is_size_ambiguous(&self) -> bool {
+        self.operands()
+            .any(|operand| matches!(operand, Operand::Memory(_)))
+            && !self.operands().any(|operand| {
+                matches!(operand, Operand::Register(_) | Operand::SegmentRegister(_))
+            })
+    }
+
+    //* A word immediate that nasm would shrink to a sign extended byte unless told `strict word`
+    pub fn needs_strict_immediate(&self) -> bool {
+        let has_sign_extended_form = ARITHMETIC_OPCODE_MAPPING
+            .iter()
+            .any(|(opname, ..)| *opname == self.mnemonic);
+
+        has_sign_extended_form
+            && self.operands().any(|operand| match operand {
+                Operand::Immediate(immediate) => {
+                    immediate.word
+                        && !immediate.sign_extended
+                        && (i8::MIN as i16..=i8::MAX as i16).contains(&(immediate.value as i16))
+                }
+                _ => false,
+            })
+    }
+
+    //* The two byte encoding of jmp, every other jmp is near
+    pub fn is_short_jump(&self) -> bool {
+        self.mnemonic == "jmp" && self.size == 2
+    }
+
+    //* A three byte jmp whose target the two byte one reaches too, which nasm would
+    //* shrink unless told `near`
+    pub fn needs_near_jump(&self) -> bool {
+        let Some(target) = self.jump_target() else {
+            return false;
+        };
+        let increment = (target as u16).wrapping_sub((self.offset + 2) as u16) as i16;
+
+        self.mnemonic == "jmp"
+            && self.size == 3
+            && (i8::MIN as i16..=i8::MAX as i16).contains(&increment)
+    }
+
+    //* call or jmp through a register or memory, e.g. `jmp word [cs:bx + 16]`
+    pub fn is_indirect_transfer(&self) -> bool {
+        matches!(self.mnemonic, "call" | "jmp")
+            && matches!(
+                self.operands[0],
+                Some(Operand::Register(_)) | Some(Operand::Memory(_))
+            )
+    }
+
+    pub fn jump_target(&self) -> Option<usize> {
+        self.operands().find_map(|operand| match operand {
+            Operand::JumpTarget(target) => Some(*target),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Mnemonic,
+    Register,
+    Immediate,
+    Memory,
+    SizeHint,
+    JumpTarget,
+    Label,
+    Punctuation,
+}
+
+//* Decides how each token of a formatted instruction is written out,
+//* plain text just passes the token through
+pub trait Style {
+    fn write_token(&self, output: &mut String, kind: TokenKind, text: &str) -> Result<()>;
+}
+
+pub struct PlainStyle;
+
+impl Style for PlainStyle {
+    fn write_token(&self, output: &mut String, _: TokenKind, text: &str) -> Result<()> {
+        output.push_str(text);
+        Ok(())
+    }
+}
+
+pub fn format_address(address: &EffectiveAddress) -> String {
+    let mut address_str = String::new();
+
+    //* Keep a displacement nasm would otherwise shrink or drop
+    if address.rm.is_some() && !address.has_minimal_displacement() {
+        let hint = if address.displacement_size == 2 {
+            "word "
+        } else {
+            "byte "
+        };
+        address_str.push_str(hint);
+    }
+
+    if let Some(sr) = address.segment {
+        let _ = write!(
+            address_str,
+            "{}:",
+            SEGMENT_REGISTER_NAME_MAPPING[sr as usize]
+        );
+    }
+
+    match address.rm {
+        None => {
+            let _ = write!(address_str, "{}", address.displacement);
+        }
+        Some(rm) => {
+            address_str.push_str(MEM_ADDR_MODE_MAPPING[rm as usize]);
+
+            //* No need to print displacement if it's 0
+            if address.displacement != 0 {
+                let _ = write!(address_str, " + {}", address.displacement);
+            }
+        }
+    }
+
+    address_str
+}
+
+pub fn format_immediate(immediate: &Immediate) -> String {
+    if immediate.sign_extended {
+        format!("{}", immediate.value as i16)
+    } else {
+        format!("{}", immediate.value)
+    }
+}
+
+pub fn write_operand(
+    output: &mut String,
+    instruction: &Instruction,
+    operand: &Operand,
+    labels: &HashMap<usize, String>,
+    style: &dyn Style,
+) -> Result<()> {
+    match operand {
+        Operand::Register(register) => {
+            style.write_token(output, TokenKind::Register, register.name())?;
+        }
+        Operand::SegmentRegister(sr) => {
+            style.write_token(
+                output,
+                TokenKind::Register,
+                SEGMENT_REGISTER_NAME_MAPPING[*sr as usize],
+            )?;
+        }
+        Operand::Memory(address) => {
+            if let Some(size) = instruction.memory_size {
+                style.write_token(output, TokenKind::SizeHint, size.name())?;
+                style.write_token(output, TokenKind::Punctuation, " ")?;
+            }
+
+            //* Direct addresses with a name use it, like jump targets do
+            let text = match (
+                address.rm,
+                labels.get(&(address.displacement as u16 as usize)),
+            ) {
+                (None, Some(label)) => match address.segment {
+                    Some(sr) => {
+                        format!("[{}:{}]", SEGMENT_REGISTER_NAME_MAPPING[sr as usize], label)
+                    }
+                    None => format!("[{}]", label),
+                },
+                _ => format!("[{}]", format_address(address)),
+            };
+            style.write_token(output, TokenKind::Memory, &text)?;
+        }
+        Operand::Immediate(immediate) => {
+            let hint = if instruction.needs_strict_immediate() {
+                Some("strict word")
+            } else if instruction.is_size_ambiguous() {
+                Some(if immediate.word { "word" } else { "byte" })
+            } else {
+                None
+            };
+
+            if let Some(hint) = hint {
+                style.write_token(output, TokenKind::SizeHint, hint)?;
+                style.write_token(output, TokenKind::Punctuation, " ")?;
+            }
+            style.write_token(output, TokenKind::Immediate, &format_immediate(immediate))?;
+        }
+        Operand::JumpTarget(target) => {
+            let text = match labels.get(target) {
+                Some(label) => label.clone(),
+                //* Relative to the start of the current instruction, as nasm's `$`. Targets
+                //* wrap around within the segment
+                None => format!(
+                    "${:+}",
+                    (*target as u16).wrapping_sub(instruction.offset as u16) as i16
+                ),
+            };
+
+            let hint = if instruction.is_short_jump() {
+                Some("short")
+            } else if instruction.needs_near_jump() {
+                Some("near")
+            } else {
+                None
+            };
+            if let Some(hint) = hint {
+                style.write_token(output, TokenKind::SizeHint, hint)?;
+                style.write_token(output, TokenKind::Punctuation, " ")?;
+            }
+            style.write_token(output, TokenKind::JumpTarget, &text)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn write_instruction(
+    output: &mut String,
+    instruction: &Instruction,
+    labels: &HashMap<usize, String>,
+    style: &dyn Style,
+) -> Result<()> {
+    if let Some(data) = &instruction.data {
+        return write_data(output, data, labels, style);
+    }
+
+    style.write_token(output, TokenKind::Mnemonic, instruction.mnemonic)?;
+
+    for (i, operand) in instruction.operands().enumerate() {
+        let separator = if i == 0 { " " } else { ", " };
+        style.write_token(output, TokenKind::Punctuation, separator)?;
+        write_operand(output, instruction, operand, labels, style)?;
+    }
+
+    Ok(())
+}
+
+//* Quoted runs can't hold the quote itself or anything unprintable
+fn is_quotable(byte: u8) -> bool {
+    (b' '..=b'~').contains(&byte) && byte != b'\''
+}
+
+//* The comma separated items after `db`/`dw`
+pub fn data_items(data: &Data, labels: &HashMap<usize, String>) -> Vec<String> {
+    let mut items = Vec::new();
+
+    match data.kind {
+        DataKind::Byte => {
+            items.extend(data.bytes.iter().map(|byte| format!("{:#04x}", byte)));
+        }
+        DataKind::Word => {
+            items.extend(
+                data.bytes
+                    .chunks_exact(2)
+                    .map(|word| format!("{:#06x}", u16::from_le_bytes([word[0], word[1]]))),
+            );
+        }
+        DataKind::Address => {
+            items.extend(data.bytes.chunks_exact(2).map(|word| {
+                let address = u16::from_le_bytes([word[0], word[1]]);
+                match labels.get(&(address as usize)) {
+                    Some(label) => label.clone(),
+                    None => format!("{:#06x}", address),
+                }
+            }));
+        }
+        DataKind::String => {
+            //* A DOS `$` terminator is written on its own, `'Hello', '$'`
+            let (mut bytes, terminator) = match data.bytes.split_last() {
+                Some((b'$', text)) if !text.is_empty() => (text, Some("'$'")),
+                _ => (data.bytes.as_slice(), None),
+            };
+
+            while let Some(&byte) = bytes.first() {
+                let run = bytes.iter().take_while(|byte| is_quotable(**byte)).count();
+
+                if run == 0 {
+                    items.push(format!("{:#04x}", byte));
+                    bytes = &bytes[1..];
+                } else {
+                    items.push(format!("'{}'", String::from_utf8_lossy(&bytes[..run])));
+                    bytes = &bytes[run..];
+                }
+            }
+            items.extend(terminator.map(str::to_owned));
+        }
+    }
+
+    items
+}
+
+//* e.g. `db 0x01, 0x02`, `dw 0x1234`, `dw label1, label2` or `db 'Hello', 0x0d, 0x0a, '$'`
+pub fn write_data(
+    output: &mut String,
+    data: &Data,
+    labels: &HashMap<usize, String>,
+    style: &dyn Style,
+) -> Result<()> {
+    let mnemonic = match data.kind {
+        DataKind::Word | DataKind::Address => "dw",
+        DataKind::Byte | DataKind::String => "db",
+    };
+    style.write_token(output, TokenKind::Mnemonic, mnemonic)?;
+
+    for (i, item) in data_items(data, labels).iter().enumerate() {
+        let separator = if i == 0 { " " } else { ", " };
+        style.write_token(output, TokenKind::Punctuation, separator)?;
+        style.write_token(output, TokenKind::Immediate, item)?;
+    }
+
+    Ok(())
+}
+
+//* Fallback for encodings no assembler syntax can reproduce, e.g. the 0x82 alias
+pub fn write_raw_instruction(
+    output: &mut String,
+    bytes: &[u8],
+    instruction: &Instruction,
+    labels: &HashMap<usize, String>,
+    style: &dyn Style,
+) -> Result<()> {
+    style.write_token(output, TokenKind::Mnemonic, "db")?;
+
+    for (i, byte) in bytes.iter().enumerate() {
+        let separator = if i == 0 { " " } else { ", " };
+        style.write_token(output, TokenKind::Punctuation, separator)?;
+        style.write_token(output, TokenKind::Immediate, &format!("{:#04x}", byte))?;
+    }
+
+    style.write_token(output, TokenKind::Punctuation, " ; ")?;
+    write_instruction(output, instruction, labels, style)?;
+
+    Ok(())
+}
+
+pub fn format_instruction(instruction: &Instruction, labels: &HashMap<usize, String>) -> String {
+    let mut output = String::new();
+    let _ = write_instruction(&mut output, instruction, labels, &PlainStyle);
+    output
+}
@@ -0,0 +1,301 @@
+use crate::cfg::*;
+use crate::dataflow::*;
+use crate::instruction::*;
+
+use std::collections::HashMap;
+
+const AL: Register = Register {
+    reg: 0,
+    word: false,
+};
+const AH: Register = Register {
+    reg: 4,
+    word: false,
+};
+
+//* Which registers pick the service of an interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selector {
+    //* The interrupt does one thing, e.g. INT 20h
+    None,
+    //* AH, and AL for the services that have subfunctions
+    Ah,
+    //* All of AX, e.g. the mouse driver
+    Ax,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Service {
+    pub ah: u8,
+    //* Only set for services that also look at AL
+    pub al: Option<u8>,
+    pub name: &'static str,
+}
+
+//* The services behind one interrupt vector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Catalog {
+    pub vector: u8,
+    pub name: &'static str,
+    pub selector: Selector,
+    pub services: &'static [Service],
+}
+
+const fn service(ah: u8, name: &'static str) -> Service {
+    Service { ah, al: None, name }
+}
+
+const fn subfunction(ah: u8, al: u8, name: &'static str) -> Service {
+    Service {
+        ah,
+        al: Some(al),
+        name,
+    }
+}
+
+pub const DOS_TERMINATE: Catalog = Catalog {
+    vector: 0x20,
+    name: "DOS",
+    selector: Selector::None,
+    services: &[service(0x00, "terminate program")],
+};
+
+pub const DOS: Catalog = Catalog {
+    vector: 0x21,
+    name: "DOS",
+    selector: Selector::Ah,
+    services: &[
+        service(0x00, "terminate program"),
+        service(0x01, "read character with echo"),
+        service(0x02, "write character"),
+        service(0x06, "direct console I/O"),
+        service(0x07, "direct character input"),
+        service(0x08, "read character without echo"),
+        service(0x09, "print string"),
+        service(0x0A, "buffered input"),
+        service(0x0B, "check input status"),
+        service(0x0C, "flush buffer and read"),
+        service(0x0E, "select disk"),
+        service(0x19, "get current disk"),
+        service(0x1A, "set disk transfer address"),
+        service(0x25, "set interrupt vector"),
+        service(0x2A, "get date"),
+        service(0x2B, "set date"),
+        service(0x2C, "get time"),
+        service(0x2D, "set time"),
+        service(0x30, "get DOS version"),
+        service(0x31, "terminate and stay resident"),
+        service(0x35, "get interrupt vector"),
+        service(0x39, "create directory"),
+        service(0x3A, "remove directory"),
+        service(0x3B, "change directory"),
+        service(0x3C, "create file"),
+        service(0x3D, "open file"),
+        service(0x3E, "close file"),
+        service(0x3F, "read from file or device"),
+        service(0x40, "write to file or device"),
+        service(0x41, "delete file"),
+        service(0x42, "move file pointer"),
+        subfunction(0x43, 0x00, "get file attributes"),
+        subfunction(0x43, 0x01, "set file attributes"),
+        subfunction(0x44, 0x00, "get device information"),
+        subfunction(0x44, 0x01, "set device information"),
+        service(0x44, "IOCTL"),
+        service(0x47, "get current directory"),
+        service(0x48, "allocate memory"),
+        service(0x49, "free memory"),
+        service(0x4A, "resize memory block"),
+        subfunction(0x4B, 0x00, "load and execute program"),
+        subfunction(0x4B, 0x03, "load overlay"),
+        service(0x4C, "terminate with return code"),
+        service(0x4D, "get return code"),
+        service(0x4E, "find first file"),
+        service(0x4F, "find next file"),
+        service(0x56, "rename file"),
+        subfunction(0x57, 0x00, "get file date and time"),
+        subfunction(0x57, 0x01, "set file date and time"),
+    ],
+};
+
+pub const BIOS_VIDEO: Catalog = Catalog {
+    vector: 0x10,
+    name: "BIOS video",
+    selector: Selector::Ah,
+    services: &[
+        service(0x00, "set video mode"),
+        service(0x01, "set cursor shape"),
+        service(0x02, "set cursor position"),
+        service(0x03, "get cursor position"),
+        service(0x05, "select active page"),
+        service(0x06, "scroll window up"),
+        service(0x07, "scroll window down"),
+        service(0x08, "read character and attribute"),
+        service(0x09, "write character and attribute"),
+        service(0x0A, "write character"),
+        service(0x0B, "set palette"),
+        service(0x0C, "write pixel"),
+        service(0x0D, "read pixel"),
+        service(0x0E, "teletype output"),
+        service(0x0F, "get video mode"),
+        service(0x13, "write string"),
+    ],
+};
+
+pub const BIOS_DISK: Catalog = Catalog {
+    vector: 0x13,
+    name: "BIOS disk",
+    selector: Selector::Ah,
+    services: &[
+        service(0x00, "reset disk system"),
+        service(0x01, "get status of last operation"),
+        service(0x02, "read sectors"),
+        service(0x03, "write sectors"),
+        service(0x04, "verify sectors"),
+        service(0x08, "get drive parameters"),
+    ],
+};
+
+pub const BIOS_KEYBOARD: Catalog = Catalog {
+    vector: 0x16,
+    name: "BIOS keyboard",
+    selector: Selector::Ah,
+    services: &[
+        service(0x00, "read key"),
+        service(0x01, "check for key"),
+        service(0x02, "get shift flags"),
+    ],
+};
+
+pub const BIOS_TIME: Catalog = Catalog {
+    vector: 0x1A,
+    name: "BIOS time",
+    selector: Selector::Ah,
+    services: &[
+        service(0x00, "get tick count"),
+        service(0x01, "set tick count"),
+    ],
+};
+
+pub const MOUSE: Catalog = Catalog {
+    vector: 0x33,
+    name: "mouse",
+    selector: Selector::Ax,
+    services: &[
+        subfunction(0x00, 0x00, "reset driver"),
+        subfunction(0x00, 0x01, "show cursor"),
+        subfunction(0x00, 0x02, "hide cursor"),
+        subfunction(0x00, 0x03, "get position and buttons"),
+        subfunction(0x00, 0x04, "set cursor position"),
+        subfunction(0x00, 0x07, "set horizontal range"),
+        subfunction(0x00, 0x08, "set vertical range"),
+        subfunction(0x00, 0x0C, "set event handler"),
+    ],
+};
+
+pub const EMS: Catalog = Catalog {
+    vector: 0x67,
+    name: "EMS",
+    selector: Selector::Ah,
+    services: &[
+        service(0x40, "get status"),
+        service(0x41, "get page frame segment"),
+        service(0x42, "get page counts"),
+        service(0x43, "allocate pages"),
+        service(0x44, "map page"),
+        service(0x45, "free pages"),
+        service(0x46, "get version"),
+    ],
+};
+
+//* Every catalog the listing annotates with, add new ones here
+pub const CATALOGS: &[Catalog] = &[
+    DOS_TERMINATE,
+    DOS,
+    BIOS_VIDEO,
+    BIOS_DISK,
+    BIOS_KEYBOARD,
+    BIOS_TIME,
+    MOUSE,
+    EMS,
+];
+
+pub fn find_catalog(vector: u8) -> Option<&'static Catalog> {
+    CATALOGS.iter().find(|catalog| catalog.vector == vector)
+}
+
+impl Catalog {
+    //* A service with a matching subfunction wins over the general one
+    pub fn find_service(&self, ah: Option<u8>, al: Option<u8>) -> Option<&'static Service> {
+        if self.selector == Selector::None {
+            return self.services.first();
+        }
+
+        let ah = ah?;
+        let matches = |service: &&Service| service.ah == ah;
+
+        self.services
+            .iter()
+            .filter(matches)
+            .find(|service| service.al.is_some() && service.al == al)
+            .or_else(|| {
+                self.services
+                    .iter()
+                    .filter(matches)
+                    .find(|service| service.al.is_none() && self.selector == Selector::Ah)
+            })
+    }
+
+    //* `(AH=09h)`, `(AH=44h, AL=00h)` or `(AX=0001h)`, the registers that picked the service
+    pub fn format_selector(&self, service: &Service) -> String {
+        match (self.selector, service.al) {
+            (Selector::None, _) => String::new(),
+            (Selector::Ax, al) => format!(" (AX={:02X}{:02X}h)", service.ah, al.unwrap_or(0)),
+            (Selector::Ah, Some(al)) => format!(" (AH={:02X}h, AL={:02X}h)", service.ah, al),
+            (Selector::Ah, None) => format!(" (AH={:02X}h)", service.ah),
+        }
+    }
+}
+
+//* `; DOS: print string (AH=09h)` before each INT whose service can be told from
+//* the instructions before it in its basic block
+pub fn service_comments(
+    dataflow: &Dataflow,
+    cfg: &ControlFlowGraph,
+) -> HashMap<usize, Vec<String>> {
+    let mut comments = HashMap::new();
+
+    for block in cfg.blocks.values() {
+        let flow = &dataflow.blocks[&block.start];
+
+        for (i, instruction) in block.instructions.iter().enumerate() {
+            let Some(Operand::Immediate(vector)) = instruction.operands[0] else {
+                continue;
+            };
+            if instruction.mnemonic != "int" {
+                continue;
+            }
+            let Some(catalog) = find_catalog(vector.value as u8) else {
+                continue;
+            };
+
+            let state = &flow.states[i];
+            let (ah, al) = (state.get(AH), state.get(AL));
+            let (ah, al) = (ah.map(|ah| ah as u8), al.map(|al| al as u8));
+            let Some(service) = catalog.find_service(ah, al) else {
+                continue;
+            };
+
+            comments.insert(
+                instruction.offset,
+                vec![format!(
+                    "; {}: {}{}",
+                    catalog.name,
+                    service.name,
+                    catalog.format_selector(service)
+                )],
+            );
+        }
+    }
+
+    comments
+}
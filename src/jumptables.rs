@@ -0,0 +1,120 @@
+use crate::instruction::*;
+
+//* A `jmp word [bx + table]` whose index was bounds checked, with the entries it can reach
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTable {
+    //* Offset of the indirect jmp
+    pub jump: usize,
+    //* Offset of the first entry in the image
+    pub table: usize,
+    pub targets: Vec<usize>,
+}
+
+impl JumpTable {
+    //* Bytes the table takes up, two per entry
+    pub fn length(&self) -> usize {
+        self.targets.len() * 2
+    }
+}
+
+//* Instructions between the bounds check and the jmp we look through
+const MAX_DISPATCH_LENGTH: usize = 4;
+
+//* The word register an addressing mode with a single index register uses
+fn index_register(rm: u8) -> Option<u8> {
+    //* si, di, bx
+    match rm {
+        0b100 => Some(6),
+        0b101 => Some(7),
+        0b111 => Some(3),
+        _ => None,
+    }
+}
+
+fn is_word_register(operand: &Option<Operand>, reg: u8) -> bool {
+    matches!(operand, Some(Operand::Register(register)) if register.word && register.reg == reg)
+}
+
+//* Recognises the dispatch ending at the last decoded instruction:
+//*
+//*     cmp bx, 3
+//*     ja default          ; or jae/jnb, one entry less
+//*     add bx, bx          ; scales an entry number to a byte offset, if present
+//*     jmp word [cs:bx + table]
+//*
+//* The table is read from the image and every entry must point inside it. The table
+//* address and the entries are relative to `origin`, where the image is loaded
+pub fn recognise_jump_table(
+    decoded: &[Instruction],
+    image: &[u8],
+    origin: usize,
+) -> Option<JumpTable> {
+    let (jump, before) = decoded.split_last()?;
+
+    let Some(Operand::Memory(address)) = jump.operands[0] else {
+        return None;
+    };
+    if jump.mnemonic != "jmp" || jump.memory_size != Some(MemorySize::Word) {
+        return None;
+    }
+    let index = index_register(address.rm?)?;
+
+    //* Walk back to the bounds check, nothing else may change the index on the way
+    let mut scaled = false;
+    let mut bound = None;
+    for (i, instruction) in before.iter().rev().take(MAX_DISPATCH_LENGTH).enumerate() {
+        match instruction.mnemonic {
+            "add"
+                if is_word_register(&instruction.operands[0], index)
+                    && is_word_register(&instruction.operands[1], index)
+                    && !scaled =>
+            {
+                scaled = true;
+            }
+            "jnbe" | "jnb" => {
+                let cmp = before.get(before.len().checked_sub(i + 2)?)?;
+                let Some(Operand::Immediate(limit)) = cmp.operands[1] else {
+                    return None;
+                };
+                if cmp.mnemonic != "cmp" || !is_word_register(&cmp.operands[0], index) {
+                    return None;
+                }
+
+                //* ja lets the limit itself through, jae stops before it
+                let inclusive = instruction.mnemonic == "jnbe";
+                bound = Some((limit.value as usize, inclusive));
+                break;
+            }
+            _ => return None,
+        }
+    }
+    let (limit, inclusive) = bound?;
+
+    //* Without scaling the index is already a byte offset, only even ones hold an entry
+    let entries = match (scaled, inclusive) {
+        (true, true) => limit + 1,
+        (true, false) => limit,
+        (false, true) => limit / 2 + 1,
+        (false, false) => limit.div_ceil(2),
+    };
+
+    let table = (address.displacement as u16 as usize).checked_sub(origin)?;
+    let bytes = image.get(table..table.checked_add(entries * 2)?)?;
+    let targets = bytes
+        .chunks_exact(2)
+        .map(|entry| {
+            let target = (u16::from_le_bytes([entry[0], entry[1]]) as usize).checked_sub(origin)?;
+            (target < image.len()).then_some(target)
+        })
+        .collect::<Option<Vec<usize>>>()?;
+
+    if targets.is_empty() {
+        return None;
+    }
+
+    Some(JumpTable {
+        jump: jump.offset,
+        table,
+        targets,
+    })
+}
@@ -0,0 +1,90 @@
+#![allow(clippy::let_and_return)]
+#![allow(clippy::too_many_arguments)]
+
+pub mod assembler;
+pub mod cfg;
+pub mod clocks;
+pub mod color;
+pub mod constants;
+pub mod dataflow;
+pub mod debugger;
+pub mod decoder;
+pub mod dos;
+pub mod dot;
+pub mod encoder;
+pub mod flags;
+pub mod framebuffer;
+pub mod gdbstub;
+pub mod history;
+pub mod html;
+pub mod instruction;
+pub mod interrupts;
+pub mod jumptables;
+pub mod memory;
+pub mod procedures;
+pub mod simulator;
+pub mod stack;
+pub mod strings;
+pub mod symbols;
+mod tests;
+pub mod verify;
+pub mod xref;
+
+use anyhow::Context;
+
+use std::{fs::File, io::Write as IoWrite, path::PathBuf, str::FromStr};
+
+pub mod prelude {
+    pub use crate::constants::*;
+    pub use anyhow::{bail, Result};
+}
+
+pub use decoder::*;
+pub use instruction::*;
+use prelude::*;
+
+pub fn write_to_file(input_filepath: PathBuf, output: String) -> Result<()> {
+    let output_filename = {
+        let mut filename = input_filepath
+            .file_name()
+            .context("Could not locate the filename")?
+            .to_str()
+            .context("Filename is not valid, could not convert to string")?
+            .to_owned();
+
+        filename.push_str("_output.asm");
+
+        filename
+    };
+
+    let mut output_filepath = input_filepath.parent().unwrap().to_path_buf();
+    output_filepath.push(output_filename);
+
+    let mut output_file = File::create(output_filepath)?;
+    output_file.write_all(output.as_bytes())?;
+
+    Ok(())
+}
+
+pub fn write_to_test_file(input_filepath: &str, outputs: Vec<String>) -> Result<String> {
+    let input_filepath = PathBuf::from_str(input_filepath)?;
+    let output_filename = {
+        let mut filename = input_filepath
+            .file_name()
+            .context("Could not locate the filename")?
+            .to_str()
+            .context("Filename is not valid, could not convert to string")?
+            .to_owned();
+
+        filename.push_str("_test.asm");
+
+        filename
+    };
+
+    let mut output_file = File::create(&output_filename)?;
+    for output in outputs {
+        output_file.write_all(output.as_bytes())?;
+    }
+
+    Ok(output_filename)
+}
@@ -1,174 +1,400 @@
-#![allow(clippy::let_and_return)]
-#![allow(clippy::too_many_arguments)]
-
-mod constants;
-mod decoder;
-mod tests;
-
-use anyhow::Context;
-use execute::Execute;
-use std::process::Command;
-
 use std::{
-    fs::{self, File},
-    io::Write as IoWrite,
-    path::PathBuf,
-    str::FromStr,
+    env, fs,
+    io::{self, BufReader},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process,
 };
 
-pub mod prelude {
-    pub use crate::constants::*;
-    pub use anyhow::{bail, Result};
+use disassembler::assembler::{assemble, parse_number};
+use disassembler::clocks::CpuModel;
+use disassembler::color::ColorChoice;
+use disassembler::debugger::{run_debugger, Debugger};
+use disassembler::dos::{DosServices, COM_OFFSET};
+use disassembler::framebuffer::{write_image, Framebuffer};
+use disassembler::gdbstub::{serve, GdbStub, DEFAULT_GDB_ADDRESS};
+use disassembler::memory::MEMORY_SIZE;
+use disassembler::prelude::*;
+use disassembler::*;
+
+struct Args {
+    color: ColorChoice,
+    html_filepath: Option<String>,
+    //* Graphviz files for the control-flow graph and the call graph
+    dot_filepath: Option<String>,
+    call_graph_filepath: Option<String>,
+    verify: bool,
+    //* `; xref:` comments in the listing
+    xref: bool,
+    exec: bool,
+    debug: bool,
+    //* Commands for the debugger, read from stdin otherwise
+    debug_script: Option<String>,
+    //* Address to wait for a gdb connection on
+    gdb_address: Option<String>,
+    clocks: Option<CpuModel>,
+    dump_filepath: Option<String>,
+    //* Physical start address and length, the whole 1 MiB by default
+    dump_range: (usize, usize),
+    image_filepath: Option<String>,
+    framebuffer: Framebuffer,
+    //* Run as a DOS .COM program with emulated DOS and BIOS services
+    com: bool,
+    //* Directory the program can open files in, the current directory by default
+    sandbox: Option<String>,
+    //* Scripted keyboard input for INT 16h and reads from stdin
+    keyboard_filepath: Option<String>,
+    //* Names, data ranges and comments to load, and where to save them with the generated labels
+    symbols_filepath: Option<String>,
+    save_symbols_filepath: Option<String>,
+    //* Where the listed image is loaded in its segment, 0x100 for .COM files by default
+    origin: Option<usize>,
+    input_filepath: Option<String>,
 }
 
-use decoder::*;
-use prelude::*;
+//* `start,length`, e.g. `256,0x4000`
+fn parse_range(text: &str) -> Result<(usize, usize)> {
+    let Some((start, length)) = text.split_once(',') else {
+        bail!("Invalid range `{}`, expected start,length", text);
+    };
 
-//* Outputs the assembled file in the same folder as asm_filepath
-fn execute_nasm(asm_filepath: &str) -> Result<()> {
-    //*give output to nasm, then open test and compare bytes
-    const NASM_PATH: &str = "nasm";
+    let (start, length) = (parse_number(start.trim())?, parse_number(length.trim())?);
+    if start < 0 || length < 0 {
+        bail!("Range `{}` can not be negative", text);
+    }
 
-    let mut first_command = Command::new(NASM_PATH);
+    Ok((start as usize, length as usize))
+}
 
-    first_command.arg(asm_filepath);
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        color: ColorChoice::Auto,
+        html_filepath: None,
+        dot_filepath: None,
+        call_graph_filepath: None,
+        verify: false,
+        xref: false,
+        exec: false,
+        debug: false,
+        debug_script: None,
+        gdb_address: None,
+        clocks: None,
+        dump_filepath: None,
+        dump_range: (0, MEMORY_SIZE),
+        image_filepath: None,
+        framebuffer: Framebuffer::default(),
+        com: false,
+        sandbox: None,
+        keyboard_filepath: None,
+        symbols_filepath: None,
+        save_symbols_filepath: None,
+        origin: None,
+        input_filepath: None,
+    };
 
-    if first_command.execute_check_exit_status_code(0).is_err() {
-        bail!(
-            "The path `{}` is not a correct nasm executable binary file.",
-            NASM_PATH
-        );
+    for arg in env::args().skip(1) {
+        if let Some(choice) = arg.strip_prefix("--color=") {
+            args.color = choice.parse()?;
+        } else if let Some(filepath) = arg.strip_prefix("--html=") {
+            args.html_filepath = Some(filepath.to_owned());
+        } else if let Some(filepath) = arg.strip_prefix("--dot=") {
+            args.dot_filepath = Some(filepath.to_owned());
+        } else if let Some(filepath) = arg.strip_prefix("--call-graph=") {
+            args.call_graph_filepath = Some(filepath.to_owned());
+        } else if let Some(model) = arg.strip_prefix("--clocks=") {
+            args.clocks = Some(model.parse()?);
+        } else if arg == "--clocks" {
+            args.clocks = Some(CpuModel::default());
+        } else if let Some(filepath) = arg.strip_prefix("--dump=") {
+            //* Dumping and rendering memory only make sense after simulating
+            args.dump_filepath = Some(filepath.to_owned());
+            args.exec = true;
+        } else if let Some(range) = arg.strip_prefix("--dump-range=") {
+            args.dump_range = parse_range(range)?;
+        } else if let Some(filepath) = arg.strip_prefix("--image=") {
+            args.image_filepath = Some(filepath.to_owned());
+            args.exec = true;
+        } else if let Some(framebuffer) = arg.strip_prefix("--framebuffer=") {
+            args.framebuffer = framebuffer.parse()?;
+        } else if let Some(filepath) = arg.strip_prefix("--debug=") {
+            args.debug = true;
+            args.debug_script = Some(filepath.to_owned());
+        } else if let Some(address) = arg.strip_prefix("--gdb=") {
+            args.gdb_address = Some(address.to_owned());
+        } else if arg == "--gdb" {
+            args.gdb_address = Some(DEFAULT_GDB_ADDRESS.to_owned());
+        } else if let Some(directory) = arg.strip_prefix("--sandbox=") {
+            args.sandbox = Some(directory.to_owned());
+        } else if let Some(filepath) = arg.strip_prefix("--input=") {
+            args.keyboard_filepath = Some(filepath.to_owned());
+        } else if let Some(filepath) = arg.strip_prefix("--symbols=") {
+            args.symbols_filepath = Some(filepath.to_owned());
+        } else if let Some(filepath) = arg.strip_prefix("--save-symbols=") {
+            args.save_symbols_filepath = Some(filepath.to_owned());
+        } else if let Some(origin) = arg.strip_prefix("--origin=") {
+            let origin = parse_number(origin)?;
+            if !(0..=0xFFFF).contains(&origin) {
+                bail!("Origin `{:#x}` is outside the segment", origin);
+            }
+            args.origin = Some(origin as usize);
+        } else if arg == "--com" {
+            args.com = true;
+        } else if arg == "--debug" {
+            args.debug = true;
+        } else if arg == "--exec" {
+            args.exec = true;
+        } else if arg == "--xref" {
+            args.xref = true;
+        } else if arg == "--verify" {
+            args.verify = true;
+        } else if arg == "--color" {
+            args.color = ColorChoice::Always;
+        } else if arg.starts_with("--") {
+            bail!("Unknown option `{}`", arg);
+        } else {
+            args.input_filepath = Some(arg);
+        }
     }
 
-    Ok(())
+    Ok(args)
 }
 
-fn decode_single_instruction(
-    decoder: &mut Decoder,
-    instructions: &[u8],
-    offset: usize,
-    outputs: &mut Vec<InstructionWithOffset>,
-) -> Result<NumBytesInInstruction> {
-    let mut output = String::new();
+//* The cpu every simulating mode starts from
+fn create_cpu(args: &Args, program: Vec<u8>) -> Result<simulator::Cpu> {
+    let mut cpu = if args.com {
+        let sandbox = PathBuf::from(args.sandbox.as_deref().unwrap_or("."));
+        let keyboard = match &args.keyboard_filepath {
+            Some(filepath) => fs::read(filepath)?,
+            None => Vec::new(),
+        };
+
+        simulator::Cpu::load_com(program, DosServices::new(sandbox, &keyboard))?
+    } else {
+        simulator::Cpu::new(program)
+    };
+    cpu.clock_model = args.clocks;
 
-    let first_byte = instructions[offset];
-    let num_bytes_in_instruction =
-        decoder.funcs[first_byte as usize](instructions, offset, &mut output, decoder)?;
+    Ok(cpu)
+}
 
-    outputs.push(InstructionWithOffset { offset, output });
+fn main() -> Result<()> {
+    let args = parse_args()?;
 
-    Ok(num_bytes_in_instruction)
-}
+    let (filepath, bytes_of_correct) = match &args.input_filepath {
+        //* Disassemble an already assembled binary
+        Some(filepath) => (filepath.to_owned(), fs::read(filepath)?),
+        None => {
+            let filepath = format!("{}/{}", FILE_DIR, "listing_0041_add_sub_cmp_jnz");
 
-fn decode_instructions(instructions: &[u8]) -> Result<Vec<String>> {
-    let mut outputs: Vec<InstructionWithOffset> = vec![];
+            let mut correct_asm_filepath = filepath.to_owned();
+            correct_asm_filepath.push_str(".asm");
 
-    let mut decoder = Decoder::new();
+            //* assemble correct asm
+            let bytes = assemble(&fs::read_to_string(&correct_asm_filepath)?)?;
+            (filepath, bytes)
+        }
+    };
 
-    let mut bytes_processed = 0;
-    while bytes_processed < instructions.len() {
-        bytes_processed +=
-            decode_single_instruction(&mut decoder, instructions, bytes_processed, &mut outputs)?;
-    }
+    let instructions = &bytes_of_correct;
+
+    if let Some(gdb_address) = &args.gdb_address {
+        let listener = TcpListener::bind(gdb_address)?;
+        eprintln!(
+            "Waiting for gdb on {} (set architecture i8086, target remote {})",
+            listener.local_addr()?,
+            listener.local_addr()?
+        );
 
-    for ins in &outputs {
-        println!("offset: {:x}\noutput: {}", ins.offset, ins.output);
+        let mut stub = GdbStub::new(create_cpu(&args, bytes_of_correct.clone())?);
+        serve(&mut stub, &listener)?;
+
+        return Ok(());
     }
 
-    for ins in decoder.enqued_labels {
-        println!("enq_offset: {:x}\nenq_output: {}\n", ins.offset, ins.output);
+    if args.debug {
+        let cpu = create_cpu(&args, bytes_of_correct.clone())?;
+        let mut debugger = Debugger::new(cpu);
+
+        match &args.debug_script {
+            Some(script_filepath) => {
+                let mut script = BufReader::new(fs::File::open(script_filepath)?);
+                run_debugger(&mut debugger, &mut script, &mut io::stdout(), false)?;
+            }
+            None => run_debugger(
+                &mut debugger,
+                &mut io::stdin().lock(),
+                &mut io::stdout(),
+                true,
+            )?,
+        }
 
-        if let Ok(index) = outputs.binary_search(&InstructionWithOffset {
-            offset: ins.offset,
-            output: String::new(),
-        }) {
-            outputs.insert(
-                index,
-                InstructionWithOffset {
-                    offset: ins.offset,
-                    output: format!("{}:\n", ins.output),
-                },
-            );
+        //* What the program printed up to where the debugger left it
+        if let Some(services) = &debugger.cpu.services {
+            println!("\nProgram output:");
+            print!("{}", String::from_utf8_lossy(&services.output));
         }
-    }
 
-    let mut output_str_vec = Vec::new();
-    output_str_vec.push("bits 16\n\n".to_owned());
-    for ins in outputs {
-        output_str_vec.push(ins.output);
+        return Ok(());
     }
 
-    Ok(output_str_vec)
-}
-
-#[allow(dead_code)]
-fn write_to_file(input_filepath: PathBuf, output: String) -> Result<()> {
-    let output_filename = {
-        let mut filename = input_filepath
-            .file_name()
-            .context("Could not locate the filename")?
-            .to_str()
-            .context("Filename is not valid, could not convert to string")?
-            .to_owned();
+    if args.exec || args.com {
+        let mut cpu = create_cpu(&args, bytes_of_correct.clone())?;
+
+        if args.exec {
+            println!("--- {} execution ---", filepath);
+            print!("{}", simulator::run_with_trace(&mut cpu, true)?);
+        } else {
+            //* Without a trace only what the program printed is shown
+            while !cpu.is_halted() {
+                cpu.step()?;
+            }
+        }
 
-        filename.push_str("_output.asm");
+        if let Some(services) = &cpu.services {
+            if args.exec {
+                println!("\nProgram output:");
+            }
+            print!("{}", String::from_utf8_lossy(&services.output));
+        }
 
-        filename
-    };
+        if let Some(dump_filepath) = &args.dump_filepath {
+            let (start, length) = args.dump_range;
+            fs::write(dump_filepath, cpu.memory.range(start, length)?)?;
+        }
 
-    let mut output_filepath = input_filepath.parent().unwrap().to_path_buf();
-    output_filepath.push(output_filename);
+        if let Some(image_filepath) = &args.image_filepath {
+            write_image(Path::new(image_filepath), &cpu.memory, &args.framebuffer)?;
+        }
 
-    let mut output_file = File::create(output_filepath)?;
-    output_file.write_all(output.as_bytes())?;
+        if let Some(exit_code) = cpu.exit_code.filter(|code| *code != 0) {
+            process::exit(exit_code as i32);
+        }
 
-    Ok(())
-}
+        return Ok(());
+    }
 
-#[allow(dead_code)]
-fn write_to_test_file(input_filepath: &str, outputs: Vec<String>) -> Result<String> {
-    let input_filepath = PathBuf::from_str(input_filepath)?;
-    let output_filename = {
-        let mut filename = input_filepath
-            .file_name()
-            .context("Could not locate the filename")?
-            .to_str()
-            .context("Filename is not valid, could not convert to string")?
-            .to_owned();
+    let mut symbols = match &args.symbols_filepath {
+        Some(symbols_filepath) => fs::read_to_string(symbols_filepath)?.parse()?,
+        None => symbols::Symbols::default(),
+    };
 
-        filename.push_str("_test.asm");
+    let is_com_file = Path::new(&filepath)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("com"));
 
-        filename
+    let mut decoder = Decoder::new();
+    decoder.data_ranges = symbols.data_ranges();
+    decoder.origin = match args.origin {
+        Some(origin) => origin,
+        None if is_com_file => COM_OFFSET as usize,
+        None => 0,
     };
 
-    let mut output_file = File::create(&output_filename)?;
-    for output in outputs {
-        output_file.write_all(output.as_bytes())?;
-    }
+    //* Text the code never reaches becomes `db 'Hello', '$'` instead of garbage instructions
+    let strings = strings::detect_strings(&decoder, instructions, |offset| symbols.is_code(offset));
+    decoder
+        .data_ranges
+        .extend(strings.iter().map(strings::FoundString::range));
 
-    Ok(output_filename)
-}
+    let decoded = decoder.decode_all(instructions)?;
 
-fn main() -> Result<()> {
-    let filepath = format!("{}/{}", FILE_DIR, "listing_0041_add_sub_cmp_jnz");
+    //* Procedures are labelled `proc_XXXX` everywhere the code is shown, unless named
+    let cfg = cfg::build_cfg_with_jump_tables(&decoded, &decoder.jump_tables)?;
+    let procedures = procedures::detect_procedures(&cfg);
+    procedures::name_procedures(&procedures, &mut decoder.labels);
+    symbols.apply_names(&mut decoder.labels);
 
-    let mut correct_asm_filepath = filepath.to_owned();
-    correct_asm_filepath.push_str(".asm");
+    if let Some(save_symbols_filepath) = &args.save_symbols_filepath {
+        symbols.add_labels(&decoder.labels);
+        fs::write(save_symbols_filepath, symbols.to_string())?;
+    }
 
-    //* assemble correct asm
-    execute_nasm(&correct_asm_filepath)?;
-    let bytes_of_correct = fs::read(filepath)?;
+    if let Some(html_filepath) = &args.html_filepath {
+        let report = html::write_html_report(&filepath, instructions, &decoded, &decoder.labels)?;
+        fs::write(html_filepath, report)?;
 
-    let instructions = &bytes_of_correct;
+        return Ok(());
+    }
 
-    // println!("{:?}", instructions);
+    if args.dot_filepath.is_some() || args.call_graph_filepath.is_some() {
+        if let Some(dot_filepath) = &args.dot_filepath {
+            fs::write(dot_filepath, dot::write_cfg_dot(&cfg, &decoder.labels)?)?;
+        }
+        if let Some(call_graph_filepath) = &args.call_graph_filepath {
+            fs::write(
+                call_graph_filepath,
+                dot::write_call_graph_dot(&cfg, &decoder.labels)?,
+            )?;
+        }
 
-    let outputs = decode_instructions(instructions)?;
+        return Ok(());
+    }
+
+    let mut comments = procedures::procedure_comments(&procedures);
+    for (offset, lines) in stack::stack_comments(&stack::analyse_stacks(&cfg, &procedures)) {
+        comments.entry(offset).or_default().extend(lines);
+    }
+    for (offset, lines) in symbols.listing_comments() {
+        comments.entry(offset).or_default().extend(lines);
+    }
+    for (offset, lines) in
+        strings::string_comments(&decoded, instructions, &decoder.data_ranges, decoder.origin)
+    {
+        comments.entry(offset).or_default().extend(lines);
+    }
+    let dataflow = dataflow::Dataflow::build(&cfg);
+    for (offset, lines) in interrupts::service_comments(&dataflow, &cfg) {
+        comments.entry(offset).or_default().extend(lines);
+    }
+    for (offset, lines) in
+        dataflow::dataflow_comments(&dataflow, &cfg, instructions, &decoder.labels)
+    {
+        comments.entry(offset).or_default().extend(lines);
+    }
+    let xrefs = xref::XrefIndex::build(&decoded);
+    if args.xref {
+        for (offset, lines) in xrefs.listing_comments(&decoded) {
+            comments.entry(offset).or_default().extend(lines);
+        }
+    }
+
+    let outputs = write_listing(
+        instructions,
+        &decoded,
+        &decoder.labels,
+        &comments,
+        args.color.style(),
+        args.clocks,
+    )?;
 
     for line in outputs {
         print!("{}", line);
     }
 
+    //* Data the code refers to isn't part of the listing, its references go at the end
+    let data_comments = xrefs.data_comments(&decoded);
+    if args.xref && !data_comments.is_empty() {
+        println!();
+        for comment in data_comments {
+            println!("{}", comment);
+        }
+    }
+
+    if args.verify {
+        let mismatches = verify::verify_reassembly(instructions, &decoded)?;
+
+        for mismatch in &mismatches {
+            eprintln!("{}", verify::format_mismatch(mismatch));
+        }
+
+        if !mismatches.is_empty() {
+            bail!(
+                "{} instruction(s) will not reassemble byte-identically",
+                mismatches.len()
+            );
+        }
+    }
+
     // write_to_file(input_filepath, output)?;
     // write_to_test_file(output)?;
 
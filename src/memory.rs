@@ -0,0 +1,153 @@
+use crate::instruction::*;
+use crate::prelude::*;
+
+//* 1 MiB addressable by 20 bit physical addresses
+pub const MEMORY_SIZE: usize = 1 << 20;
+const ADDRESS_MASK: usize = MEMORY_SIZE - 1;
+
+//* Indices into SEGMENT_REGISTER_NAME_MAPPING
+pub const ES: u8 = 0;
+pub const CS: u8 = 1;
+pub const SS: u8 = 2;
+pub const DS: u8 = 3;
+
+//* A byte write, with the value it replaced so it can be undone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub old: u8,
+    pub new: u8,
+}
+
+#[derive(Clone)]
+pub struct Memory {
+    bytes: Vec<u8>,
+    //* Collects every write while set, for stepping backwards
+    journal: Option<Vec<MemoryWrite>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            bytes: vec![0; MEMORY_SIZE],
+            journal: None,
+        }
+    }
+
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    //* Stops journaling, returning the writes since start_journal
+    pub fn take_journal(&mut self) -> Vec<MemoryWrite> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    //* Puts a byte back without journaling it
+    pub fn restore_byte(&mut self, physical_address: usize, value: u8) {
+        self.bytes[physical_address & ADDRESS_MASK] = value;
+    }
+
+    //* segment * 16 + offset, wrapping around at 1 MiB like the 8086
+    pub fn physical_address(segment: u16, offset: u16) -> usize {
+        (((segment as usize) << 4) + offset as usize) & ADDRESS_MASK
+    }
+
+    pub fn read_byte(&self, segment: u16, offset: u16) -> u8 {
+        self.bytes[Self::physical_address(segment, offset)]
+    }
+
+    pub fn write_byte(&mut self, segment: u16, offset: u16, value: u8) {
+        let address = Self::physical_address(segment, offset);
+
+        if let Some(journal) = &mut self.journal {
+            journal.push(MemoryWrite {
+                address,
+                old: self.bytes[address],
+                new: value,
+            });
+        }
+
+        self.bytes[address] = value;
+    }
+
+    //* Little endian, the high byte of a word at offset 0xFFFF wraps to offset 0 of the segment
+    pub fn read_word(&self, segment: u16, offset: u16) -> u16 {
+        u16::from_le_bytes([
+            self.read_byte(segment, offset),
+            self.read_byte(segment, offset.wrapping_add(1)),
+        ])
+    }
+
+    pub fn write_word(&mut self, segment: u16, offset: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_byte(segment, offset, low);
+        self.write_byte(segment, offset.wrapping_add(1), high);
+    }
+
+    pub fn read(&self, segment: u16, offset: u16, word: bool) -> u16 {
+        if word {
+            self.read_word(segment, offset)
+        } else {
+            self.read_byte(segment, offset) as u16
+        }
+    }
+
+    pub fn write(&mut self, segment: u16, offset: u16, value: u16, word: bool) {
+        if word {
+            self.write_word(segment, offset, value);
+        } else {
+            self.write_byte(segment, offset, value as u8);
+        }
+    }
+
+    //* Copies bytes in starting at a physical address
+    pub fn load(&mut self, physical_address: usize, data: &[u8]) -> Result<()> {
+        if physical_address + data.len() > MEMORY_SIZE {
+            bail!(
+                "{} bytes at {:#07x} do not fit in memory",
+                data.len(),
+                physical_address
+            );
+        }
+
+        self.bytes[physical_address..physical_address + data.len()].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    //* `length` bytes starting at a physical address, without wrapping around
+    pub fn range(&self, physical_address: usize, length: usize) -> Result<&[u8]> {
+        match physical_address.checked_add(length) {
+            Some(end) if end <= MEMORY_SIZE => Ok(&self.bytes[physical_address..end]),
+            _ => bail!(
+                "{} bytes at {:#07x} are outside of memory",
+                length,
+                physical_address
+            ),
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//* bp based addresses default to SS, everything else to DS, unless overridden
+pub fn effective_segment(address: &EffectiveAddress) -> u8 {
+    if let Some(sr) = address.segment {
+        return sr;
+    }
+
+    match address.rm {
+        //* bp + si, bp + di, bp + displacement
+        Some(0b010) | Some(0b011) | Some(0b110) => SS,
+        _ => DS,
+    }
+}
@@ -0,0 +1,89 @@
+use crate::cfg::*;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Procedure {
+    pub entry: usize,
+    //* Offsets of the calls into it
+    pub callers: Vec<usize>,
+    pub has_prologue: bool,
+    //* Offsets of the ret and retf instructions it can reach without following calls
+    pub returns: Vec<usize>,
+}
+
+impl Procedure {
+    pub fn name(&self) -> String {
+        procedure_name(self.entry)
+    }
+}
+
+pub fn procedure_name(entry: usize) -> String {
+    format!("proc_{:04x}", entry)
+}
+
+//* Targets of near calls and code starting with `push bp; mov bp, sp`, in address order
+pub fn detect_procedures(cfg: &ControlFlowGraph) -> Vec<Procedure> {
+    cfg.detected_procedures()
+        .into_iter()
+        .map(|entry| {
+            let block = &cfg.blocks[&entry];
+
+            let callers = block
+                .predecessors
+                .iter()
+                .filter(|edge| edge.kind == EdgeKind::Call)
+                .map(|edge| cfg.blocks[&edge.from].last_instruction().offset)
+                .collect();
+
+            let has_prologue = matches!(
+                block.instructions.as_slice(),
+                [first, second, ..] if is_prologue(first, second)
+            );
+
+            let returns = cfg
+                .procedure_blocks(entry)
+                .into_iter()
+                .map(|start| cfg.blocks[&start].last_instruction())
+                .filter(|last| transfer_kind(last) == Some(EdgeKind::Return))
+                .map(|last| last.offset)
+                .collect();
+
+            Procedure {
+                entry,
+                callers,
+                has_prologue,
+                returns,
+            }
+        })
+        .collect()
+}
+
+//* Procedure entries are labelled `proc_XXXX` instead of the decoder's generic `labelN`
+pub fn name_procedures(procedures: &[Procedure], labels: &mut HashMap<usize, String>) {
+    for procedure in procedures {
+        labels.insert(procedure.entry, procedure.name());
+    }
+}
+
+//* Header comments the listing writes before each procedure
+pub fn procedure_comments(procedures: &[Procedure]) -> HashMap<usize, Vec<String>> {
+    procedures
+        .iter()
+        .map(|procedure| {
+            let callers = match procedure.callers.as_slice() {
+                [] => "no direct callers".to_owned(),
+                callers => {
+                    let offsets: Vec<String> = callers
+                        .iter()
+                        .map(|offset| format!("{:#06x}", offset))
+                        .collect();
+                    format!("called from {}", offsets.join(", "))
+                }
+            };
+
+            let header = format!("; {}: {}", procedure.name(), callers);
+            (procedure.entry, vec![String::new(), header])
+        })
+        .collect()
+}
@@ -0,0 +1,635 @@
+use crate::clocks::*;
+use crate::decoder::*;
+use crate::dos::*;
+use crate::flags::*;
+use crate::instruction::*;
+use crate::memory::*;
+use crate::prelude::*;
+
+use std::fmt::Write;
+
+//* Indexed by the reg field of a word register: ax, cx, dx, bx, sp, bp, si, di
+pub const WORD_REGISTER_COUNT: usize = 8;
+
+//* Index of sp in the word registers
+const SP: usize = 4;
+
+//* Order the registers are listed in the final state, as the reference listings do
+const FINAL_REGISTER_ORDER: [u8; WORD_REGISTER_COUNT] = [0, 3, 1, 2, 4, 5, 6, 7];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuState {
+    pub registers: [u16; WORD_REGISTER_COUNT],
+    pub segment_registers: [u16; 4],
+    pub ip: u16,
+    pub flags: u16,
+}
+
+pub struct Cpu {
+    pub state: CpuState,
+    pub memory: Memory,
+    //* IP past the last loaded byte, where execution stops
+    pub program_end: usize,
+    pub decoder: Decoder,
+    //* Estimates clocks for every executed instruction when set
+    pub clock_model: Option<CpuModel>,
+    pub clocks: u64,
+    pub last_clocks: Option<Clocks>,
+    //* Emulated DOS and BIOS interrupts, without them `int` can't be executed
+    pub services: Option<DosServices>,
+    //* Set once the program terminated through DOS
+    pub exit_code: Option<u8>,
+}
+
+impl Cpu {
+    //* Loads the program at 0000:0000 with every register cleared
+    pub fn new(program: Vec<u8>) -> Self {
+        let mut memory = Memory::new();
+        memory
+            .load(0, &program)
+            .expect("program does not fit in 1 MiB of memory");
+
+        Self {
+            state: CpuState::default(),
+            memory,
+            program_end: program.len(),
+            decoder: Decoder::new(),
+            clock_model: None,
+            clocks: 0,
+            last_clocks: None,
+            services: None,
+            exit_code: None,
+        }
+    }
+
+    //* Loads a .COM program at 1000:0100 with every segment register set to its segment
+    //* and the stack at the top of it, like DOS does
+    pub fn load_com(program: Vec<u8>, services: DosServices) -> Result<Self> {
+        if program.len() > (u16::MAX - COM_OFFSET) as usize {
+            bail!("{} bytes is too large for a .COM program", program.len());
+        }
+
+        let mut cpu = Self::new(Vec::new());
+        cpu.memory
+            .load(Memory::physical_address(COM_SEGMENT, COM_OFFSET), &program)?;
+
+        cpu.state.segment_registers = [COM_SEGMENT; 4];
+        cpu.state.ip = COM_OFFSET;
+        cpu.state.registers[SP] = 0xFFFE;
+        cpu.program_end = COM_OFFSET as usize + program.len();
+        cpu.services = Some(services);
+
+        Ok(cpu)
+    }
+
+    pub fn get_register(&self, register: Register) -> u16 {
+        if register.word {
+            return self.state.registers[register.reg as usize];
+        }
+
+        let full = self.state.registers[(register.reg & 0b11) as usize];
+        if register.reg >> 2 == 0 {
+            //* al, cl, dl, bl
+            full & 0xFF
+        } else {
+            //* ah, ch, dh, bh
+            full >> 8
+        }
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        if register.word {
+            self.state.registers[register.reg as usize] = value;
+            return;
+        }
+
+        let full = &mut self.state.registers[(register.reg & 0b11) as usize];
+        if register.reg >> 2 == 0 {
+            *full = (*full & 0xFF00) | (value & 0xFF);
+        } else {
+            *full = (*full & 0x00FF) | ((value & 0xFF) << 8);
+        }
+    }
+
+    pub fn get_flag(&self, flag: u16) -> bool {
+        self.state.flags & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u16, value: bool) {
+        if value {
+            self.state.flags |= flag;
+        } else {
+            self.state.flags &= !flag;
+        }
+    }
+
+    //* Segment and offset an effective address resolves to with the current registers
+    pub fn resolve_address(&self, address: &EffectiveAddress) -> (u16, u16) {
+        let word_register = |reg| self.state.registers[reg];
+
+        //* Registers by index: bx = 3, bp = 5, si = 6, di = 7
+        let base = match address.rm {
+            None => 0,
+            Some(0b000) => word_register(3).wrapping_add(word_register(6)),
+            Some(0b001) => word_register(3).wrapping_add(word_register(7)),
+            Some(0b010) => word_register(5).wrapping_add(word_register(6)),
+            Some(0b011) => word_register(5).wrapping_add(word_register(7)),
+            Some(0b100) => word_register(6),
+            Some(0b101) => word_register(7),
+            Some(0b110) => word_register(5),
+            Some(_) => word_register(3),
+        };
+        let offset = base.wrapping_add(address.displacement as u16);
+
+        let segment = self.state.segment_registers[effective_segment(address) as usize];
+
+        (segment, offset)
+    }
+
+    fn read_operand(&self, operand: &Operand, word: bool) -> Result<u16> {
+        match operand {
+            Operand::Register(register) => Ok(self.get_register(*register)),
+            Operand::SegmentRegister(sr) => Ok(self.state.segment_registers[*sr as usize]),
+            Operand::Immediate(immediate) => Ok(immediate.value),
+            Operand::Memory(address) => {
+                let (segment, offset) = self.resolve_address(address);
+                Ok(self.memory.read(segment, offset, word))
+            }
+            _ => bail!("Reading operand {:?} is not simulated yet", operand),
+        }
+    }
+
+    fn write_operand(&mut self, operand: &Operand, value: u16, word: bool) -> Result<()> {
+        match operand {
+            Operand::Register(register) => self.set_register(*register, value),
+            Operand::SegmentRegister(sr) => self.state.segment_registers[*sr as usize] = value,
+            Operand::Memory(address) => {
+                let (segment, offset) = self.resolve_address(address);
+                self.memory.write(segment, offset, value, word);
+            }
+            _ => bail!("Writing operand {:?} is not simulated yet", operand),
+        }
+
+        Ok(())
+    }
+
+    fn set_status_flags(&mut self, status_flags: u16) {
+        self.state.flags = (self.state.flags & !STATUS_FLAGS) | status_flags;
+    }
+
+    fn push(&mut self, value: u16) {
+        self.state.registers[SP] = self.state.registers[SP].wrapping_sub(2);
+
+        let stack_segment = self.state.segment_registers[SS as usize];
+        self.memory
+            .write_word(stack_segment, self.state.registers[SP], value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let stack_segment = self.state.segment_registers[SS as usize];
+        let value = self
+            .memory
+            .read_word(stack_segment, self.state.registers[SP]);
+
+        self.state.registers[SP] = self.state.registers[SP].wrapping_add(2);
+        value
+    }
+
+    fn is_jump_taken(&mut self, mnemonic: &str) -> Result<bool> {
+        let cf = self.get_flag(FLAG_CARRY);
+        let pf = self.get_flag(FLAG_PARITY);
+        let zf = self.get_flag(FLAG_ZERO);
+        let sf = self.get_flag(FLAG_SIGN);
+        let of = self.get_flag(FLAG_OVERFLOW);
+
+        let cx = Register { reg: 1, word: true };
+
+        let taken = match mnemonic {
+            "je" => zf,
+            "jl" => sf != of,
+            "jle" => zf || sf != of,
+            "jb" => cf,
+            "jbe" => cf || zf,
+            "jp" => pf,
+            "jo" => of,
+            "js" => sf,
+            "jne" => !zf,
+            "jnl" => sf == of,
+            "jnle" => !zf && sf == of,
+            "jnb" => !cf,
+            "jnbe" => !cf && !zf,
+            "jnp" => !pf,
+            "jno" => !of,
+            "jns" => !sf,
+            "jcxz" => self.get_register(cx) == 0,
+            "jmp" => true,
+            "loop" | "loope" | "loopne" => {
+                //* Loops decrement cx without touching the flags
+                let count = self.get_register(cx).wrapping_sub(1);
+                self.set_register(cx, count);
+
+                match mnemonic {
+                    "loope" => count != 0 && zf,
+                    "loopne" => count != 0 && !zf,
+                    _ => count != 0,
+                }
+            }
+            _ => bail!("Invalid Conditional Jump `{}`", mnemonic),
+        };
+
+        Ok(taken)
+    }
+
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<()> {
+        //* IP already points past the instruction while it executes
+        self.state.ip = self.state.ip.wrapping_add(instruction.size as u16);
+
+        match (instruction.mnemonic, instruction.operands[0]) {
+            ("call", Some(Operand::JumpTarget(target))) => {
+                self.push(self.state.ip);
+                self.state.ip = target as u16;
+                return Ok(());
+            }
+            ("ret" | "retf", operand) => {
+                self.state.ip = self.pop();
+                if instruction.mnemonic == "retf" {
+                    self.state.segment_registers[CS as usize] = self.pop();
+                }
+
+                //* `ret n` also drops n bytes of arguments
+                if let Some(Operand::Immediate(immediate)) = operand {
+                    self.state.registers[SP] =
+                        self.state.registers[SP].wrapping_add(immediate.value);
+                }
+                return Ok(());
+            }
+            ("push", Some(Operand::Register(register))) => {
+                //* push sp pushes the already decremented value on the 8086
+                let value = match register.reg as usize {
+                    SP => self.state.registers[SP].wrapping_sub(2),
+                    reg => self.state.registers[reg],
+                };
+                self.push(value);
+                return Ok(());
+            }
+            ("pop", Some(Operand::Register(register))) => {
+                let value = self.pop();
+                self.set_register(register, value);
+                return Ok(());
+            }
+            ("push", Some(operand @ Operand::Memory(_))) => {
+                let value = self.read_operand(&operand, true)?;
+                self.push(value);
+                return Ok(());
+            }
+            ("call" | "jmp", Some(operand @ (Operand::Register(_) | Operand::Memory(_)))) => {
+                //* A far pointer is the offset followed by the segment
+                let (segment, target) = match (instruction.memory_size, operand) {
+                    (Some(MemorySize::Far), Operand::Memory(address)) => {
+                        let (segment, offset) = self.resolve_address(&address);
+                        (
+                            Some(self.memory.read_word(segment, offset.wrapping_add(2))),
+                            self.memory.read_word(segment, offset),
+                        )
+                    }
+                    _ => (None, self.read_operand(&operand, true)?),
+                };
+
+                if instruction.mnemonic == "call" {
+                    if segment.is_some() {
+                        self.push(self.state.segment_registers[CS as usize]);
+                    }
+                    self.push(self.state.ip);
+                }
+                if let Some(segment) = segment {
+                    self.state.segment_registers[CS as usize] = segment;
+                }
+                self.state.ip = target;
+                return Ok(());
+            }
+            ("inc" | "dec", Some(operand)) => {
+                let word = instruction.is_word().unwrap_or(true);
+                let value = self.read_operand(&operand, word)?;
+                let (result, status_flags) = if instruction.mnemonic == "inc" {
+                    add_with_flags(value, 1, false, word)
+                } else {
+                    sub_with_flags(value, 1, false, word)
+                };
+
+                //* inc and dec leave the carry flag alone
+                let carry = self.get_flag(FLAG_CARRY);
+                self.set_status_flags(status_flags);
+                self.set_flag(FLAG_CARRY, carry);
+
+                self.write_operand(&operand, result, word)?;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if let Some(target) = instruction.jump_target() {
+            if self.is_jump_taken(instruction.mnemonic)? {
+                self.state.ip = target as u16;
+            }
+            return Ok(());
+        }
+
+        if let Some((flag, value)) = match instruction.mnemonic {
+            "clc" => Some((FLAG_CARRY, false)),
+            "stc" => Some((FLAG_CARRY, true)),
+            "cmc" => Some((FLAG_CARRY, !self.get_flag(FLAG_CARRY))),
+            "cli" => Some((FLAG_INTERRUPT, false)),
+            "sti" => Some((FLAG_INTERRUPT, true)),
+            "cld" => Some((FLAG_DIRECTION, false)),
+            "std" => Some((FLAG_DIRECTION, true)),
+            _ => None,
+        } {
+            self.set_flag(flag, value);
+            return Ok(());
+        }
+
+        if let "int" | "int3" = instruction.mnemonic {
+            let vector = match instruction.operands[0] {
+                Some(Operand::Immediate(immediate)) => immediate.value as u8,
+                _ => 3,
+            };
+
+            let Some(mut services) = self.services.take() else {
+                bail!(
+                    "`{}` needs DOS services, run the program with --com",
+                    format_instruction(instruction, &Default::default())
+                );
+            };
+            let result = handle_interrupt(self, &mut services, instruction, vector);
+            self.services = Some(services);
+
+            return result;
+        }
+
+        let [Some(destination), Some(source)] = &instruction.operands else {
+            bail!("Simulating `{}` is not supported", instruction.mnemonic);
+        };
+        let word = instruction.is_word().unwrap_or(true);
+
+        match instruction.mnemonic {
+            "mov" => {
+                let value = self.read_operand(source, word)?;
+                self.write_operand(destination, value, word)?;
+            }
+            "add" | "adc" | "sub" | "sbb" | "cmp" | "and" | "or" | "xor" => {
+                let left = self.read_operand(destination, word)?;
+                let right = self.read_operand(source, word)?;
+                let carry = self.get_flag(FLAG_CARRY);
+
+                let (result, status_flags) = match instruction.mnemonic {
+                    "add" => add_with_flags(left, right, false, word),
+                    "adc" => add_with_flags(left, right, carry, word),
+                    "sub" | "cmp" => sub_with_flags(left, right, false, word),
+                    "sbb" => sub_with_flags(left, right, carry, word),
+                    _ => {
+                        let result = match instruction.mnemonic {
+                            "and" => left & right,
+                            "or" => left | right,
+                            _ => left ^ right,
+                        };
+                        (result, logic_flags(result, word))
+                    }
+                };
+
+                self.set_status_flags(status_flags);
+
+                if instruction.mnemonic != "cmp" {
+                    self.write_operand(destination, result, word)?;
+                }
+            }
+            _ => bail!("Simulating `{}` is not supported", instruction.mnemonic),
+        }
+
+        Ok(())
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.exit_code.is_some() || self.state.ip as usize >= self.program_end
+    }
+
+    //* Decodes the instruction at CS:IP, offsets and jump targets are relative to CS
+    pub fn decode_at_ip(&mut self) -> Result<Instruction> {
+        self.decode_at(self.state.ip)
+    }
+
+    //* Decodes the instruction at CS:offset. Its bytes are fetched wrapping around within
+    //* the segment, like the 8086 prefetches them
+    pub fn decode_at(&mut self, offset: u16) -> Result<Instruction> {
+        let code_segment = self.state.segment_registers[CS as usize];
+        let code: [u8; MAX_INSTRUCTION_LENGTH] = std::array::from_fn(|i| {
+            self.memory
+                .read_byte(code_segment, offset.wrapping_add(i as u16))
+        });
+
+        let mut instruction = self.decoder.decode_single_instruction(&code, 0)?;
+        instruction.offset = offset as usize;
+        for operand in instruction.operands.iter_mut().flatten() {
+            if let Operand::JumpTarget(target) = operand {
+                *target = offset.wrapping_add(*target as u16) as usize;
+            }
+        }
+
+        Ok(instruction)
+    }
+
+    //* Decodes and executes the instruction at CS:IP
+    pub fn step(&mut self) -> Result<Instruction> {
+        let instruction = self.decode_at_ip()?;
+
+        //* Resolve the address before executing, the instruction may change its registers
+        let address = instruction.operands().find_map(|operand| match operand {
+            Operand::Memory(address) => Some(self.resolve_address(address).1),
+            _ => None,
+        });
+        let next_ip = self.state.ip.wrapping_add(instruction.size as u16);
+
+        self.execute(&instruction)?;
+
+        if let Some(model) = self.clock_model {
+            let jump_taken = self.state.ip != next_ip;
+            let clocks = estimate_clocks(&instruction, model, address, jump_taken)?;
+
+            self.clocks += clocks.total() as u64;
+            self.last_clocks = Some(clocks);
+        }
+
+        Ok(instruction)
+    }
+}
+
+//* `ax:0x0->0x1 ` for every register the instruction changed, the reference listings
+//* before the ip register was introduced (0043 - 0047) leave ip out
+pub fn format_register_changes(before: &CpuState, after: &CpuState, show_ip: bool) -> String {
+    let mut output = String::new();
+
+    for reg in 0..WORD_REGISTER_COUNT {
+        let (old, new) = (before.registers[reg], after.registers[reg]);
+        if old != new {
+            let name = get_register_name(reg as u8, true);
+            let _ = write!(output, "{}:{:#x}->{:#x} ", name, old, new);
+        }
+    }
+
+    for (sr, name) in SEGMENT_REGISTER_NAME_MAPPING.iter().enumerate() {
+        let (old, new) = (before.segment_registers[sr], after.segment_registers[sr]);
+        if old != new {
+            let _ = write!(output, "{}:{:#x}->{:#x} ", name, old, new);
+        }
+    }
+
+    if show_ip && before.ip != after.ip {
+        let _ = write!(output, "ip:{:#x}->{:#x} ", before.ip, after.ip);
+    }
+
+    //* e.g. `flags:PZ->S `
+    if before.flags != after.flags {
+        let _ = write!(
+            output,
+            "flags:{}->{} ",
+            format_flags(before.flags),
+            format_flags(after.flags)
+        );
+    }
+
+    output
+}
+
+//* An operand the way the reference decoder writes it: addresses packed as `[bx+di-37]`
+//* and `[+1000]`, relative jumps counted from the end of the instruction as `$-16+2`
+fn format_trace_operand(instruction: &Instruction, operand: &Operand) -> String {
+    match operand {
+        Operand::Register(register) => register.name().to_owned(),
+        Operand::SegmentRegister(sr) => SEGMENT_REGISTER_NAME_MAPPING[*sr as usize].to_owned(),
+        Operand::Memory(address) => {
+            let mut output = String::new();
+
+            //* The size goes on the memory operand unless the destination is a register
+            if !matches!(
+                instruction.operands[0],
+                Some(Operand::Register(_) | Operand::SegmentRegister(_))
+            ) {
+                let size = match instruction.memory_size {
+                    Some(size) => size.name(),
+                    None if instruction.is_word() == Some(false) => "byte",
+                    None => "word",
+                };
+                let _ = write!(output, "{} ", size);
+            }
+
+            if let Some(sr) = address.segment {
+                let _ = write!(output, "{}:", SEGMENT_REGISTER_NAME_MAPPING[sr as usize]);
+            }
+
+            match address.rm {
+                None => {
+                    let _ = write!(output, "[+{}]", address.displacement as u16);
+                }
+                Some(rm) => {
+                    let registers = MEM_ADDR_MODE_MAPPING[rm as usize].replace(' ', "");
+                    let _ = write!(output, "[{}", registers);
+                    if address.displacement != 0 {
+                        let _ = write!(output, "{:+}", address.displacement);
+                    }
+                    output.push(']');
+                }
+            }
+
+            output
+        }
+        Operand::Immediate(immediate) => format_immediate(immediate),
+        Operand::JumpTarget(target) => {
+            let next_instruction = (instruction.offset + instruction.size) as u16;
+            format!(
+                "${:+}+{}",
+                (*target as u16).wrapping_sub(next_instruction) as i16,
+                instruction.size
+            )
+        }
+    }
+}
+
+//* The instruction as it appears in the reference traces, e.g. `mov word [+1000], 1`
+pub fn format_trace_instruction(instruction: &Instruction) -> String {
+    let operands: Vec<String> = instruction
+        .operands()
+        .map(|operand| format_trace_operand(instruction, operand))
+        .collect();
+
+    if operands.is_empty() {
+        instruction.mnemonic.to_owned()
+    } else {
+        format!("{} {}", instruction.mnemonic, operands.join(", "))
+    }
+}
+
+pub fn format_final_registers(state: &CpuState, show_ip: bool) -> String {
+    let mut output = String::new();
+    let _ = writeln!(output, "Final registers:");
+
+    for reg in FINAL_REGISTER_ORDER {
+        let value = state.registers[reg as usize];
+        if value != 0 {
+            let name = get_register_name(reg, true);
+            let _ = writeln!(output, "{:>8}: {:#06x} ({})", name, value, value);
+        }
+    }
+
+    for (sr, name) in SEGMENT_REGISTER_NAME_MAPPING.iter().enumerate() {
+        let value = state.segment_registers[sr];
+        if value != 0 {
+            let _ = writeln!(output, "{:>8}: {:#06x} ({})", name, value, value);
+        }
+    }
+
+    if show_ip && state.ip != 0 {
+        let _ = writeln!(output, "{:>8}: {:#06x} ({})", "ip", state.ip, state.ip);
+    }
+
+    if state.flags != 0 {
+        let _ = writeln!(output, "{:>8}: {}", "flags", format_flags(state.flags));
+    }
+
+    output
+}
+
+//* Executes one instruction, returning its trace line in the computer_enhance reference format
+pub fn step_with_trace(cpu: &mut Cpu, show_ip: bool) -> Result<String> {
+    let mut output = String::new();
+
+    let before = cpu.state;
+    let instruction = cpu.step()?;
+
+    write!(output, "{} ; ", format_trace_instruction(&instruction))?;
+
+    //* e.g. `Clocks: +4 = 4 | ` before the register changes
+    if let Some(clocks) = &cpu.last_clocks {
+        write!(output, "{} | ", format_clocks(clocks, cpu.clocks))?;
+    }
+
+    writeln!(
+        output,
+        "{}",
+        format_register_changes(&before, &cpu.state, show_ip)
+    )?;
+
+    Ok(output)
+}
+
+//* Runs the program to the end, returning the trace in the computer_enhance reference format
+pub fn run_with_trace(cpu: &mut Cpu, show_ip: bool) -> Result<String> {
+    let mut output = String::new();
+
+    while !cpu.is_halted() {
+        output.push_str(&step_with_trace(cpu, show_ip)?);
+    }
+
+    writeln!(output)?;
+    output.push_str(&format_final_registers(&cpu.state, show_ip));
+
+    Ok(output)
+}
@@ -0,0 +1,261 @@
+use crate::cfg::*;
+use crate::dataflow::*;
+use crate::decoder::*;
+use crate::instruction::*;
+use crate::procedures::*;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+//* Registers by index: sp = 4, bp = 5
+const SP: Location = Location::Register(4);
+const BP: Location = Location::Register(5);
+
+//* How stack warnings start in the listing, which writes them after the label of their
+//* instruction rather than before it like other comments
+pub const WARNING_COMMENT: &str = "; warning: ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackWarningKind {
+    //* Two paths reach a block with different depths, the first one is the one kept
+    Mismatch(i32, i32),
+    //* A ret reached with bytes still pushed, or with more popped than pushed
+    UnbalancedReturn(i32),
+    //* The same at an `int 20h` or `int 21h` service 4Ch that ends the program
+    UnbalancedExit(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StackWarning {
+    pub offset: usize,
+    pub kind: StackWarningKind,
+}
+
+impl StackWarning {
+    pub fn message(&self) -> String {
+        match self.kind {
+            StackWarningKind::Mismatch(first, second) => format!(
+                "paths merge with {} and {} bytes on the stack",
+                first, second
+            ),
+            StackWarningKind::UnbalancedReturn(depth) if depth < 0 => {
+                format!("ret with {} bytes popped past the return address", -depth)
+            }
+            StackWarningKind::UnbalancedReturn(depth) => {
+                format!("ret with {} bytes left on the stack", depth)
+            }
+            StackWarningKind::UnbalancedExit(depth) if depth < 0 => {
+                format!("program exits with {} bytes popped past its entry", -depth)
+            }
+            StackWarningKind::UnbalancedExit(depth) => {
+                format!("program exits with {} bytes left on the stack", depth)
+            }
+        }
+    }
+}
+
+//* Where sp stands at each instruction of a procedure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcedureStack {
+    //* The program entry or a detected procedure
+    pub entry: usize,
+    //* Bytes pushed since the entry before each instruction, None once sp is written in
+    //* a way we can't follow
+    pub depths: BTreeMap<usize, Option<i32>>,
+    //* Bytes of arguments its `ret n` drops for the caller, 0 for a plain ret
+    pub cleanup: u16,
+    pub warnings: Vec<StackWarning>,
+}
+
+//* Depth along one path, and the depth `mov bp, sp` saved for `mov sp, bp` to restore
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StackState {
+    depth: Option<i32>,
+    frame: Option<i32>,
+}
+
+fn is_register(operand: &Option<Operand>, location: Location) -> bool {
+    matches!(operand, Some(Operand::Register(register)) if register.word && Location::Register(register.reg) == location)
+}
+
+//* The n of the procedure's `ret n`, the arguments it takes off the caller's stack
+pub fn callee_cleanup(cfg: &ControlFlowGraph, procedure: &Procedure) -> u16 {
+    procedure
+        .returns
+        .iter()
+        .filter_map(|offset| {
+            let block = cfg.block_containing(*offset)?;
+            match block.last_instruction().operands[0] {
+                Some(Operand::Immediate(immediate)) => Some(immediate.value),
+                _ => None,
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+impl StackState {
+    //* The state after the instruction runs, calls are assumed to return with the
+    //* callee's cleanup done
+    fn step(self, instruction: &Instruction, cleanups: &HashMap<usize, u16>) -> Self {
+        let [destination, source] = &instruction.operands;
+        let immediate = match source {
+            Some(Operand::Immediate(immediate)) => Some(immediate.value as i16 as i32),
+            _ => None,
+        };
+
+        let defs = access(instruction).defs;
+        let frame = match instruction.mnemonic {
+            "mov" if is_register(destination, BP) && is_register(source, SP) => self.depth,
+            _ if defs.contains(&BP) => None,
+            _ => self.frame,
+        };
+
+        let change = match (instruction.mnemonic, immediate) {
+            ("push", _) => Some(2),
+            ("pop", _) if !is_register(destination, SP) => Some(-2),
+            ("sub", Some(amount)) if is_register(destination, SP) => Some(amount),
+            ("add", Some(amount)) if is_register(destination, SP) => Some(-amount),
+            ("call", _) => match destination {
+                Some(Operand::JumpTarget(target)) => {
+                    Some(-(cleanups.get(target).copied().unwrap_or(0) as i32))
+                }
+                _ => Some(0),
+            },
+            ("ret" | "retf", _) => Some(0),
+            _ if !defs.contains(&SP) => Some(0),
+            _ => None,
+        };
+
+        let depth = match instruction.mnemonic {
+            //* The epilogue puts sp back where the prologue found it
+            "mov" if is_register(destination, SP) && is_register(source, BP) => self.frame,
+            _ => self.depth.zip(change).map(|(depth, change)| depth + change),
+        };
+
+        Self { depth, frame }
+    }
+}
+
+pub fn analyse_procedure(
+    cfg: &ControlFlowGraph,
+    entry: usize,
+    cleanups: &HashMap<usize, u16>,
+) -> ProcedureStack {
+    let mut depths = BTreeMap::new();
+    let mut warnings = Vec::new();
+    let mut entry_states: BTreeMap<usize, StackState> = BTreeMap::new();
+    let mut pending = vec![(
+        entry,
+        StackState {
+            depth: Some(0),
+            frame: None,
+        },
+    )];
+
+    while let Some((start, mut state)) = pending.pop() {
+        if let Some(seen) = entry_states.get(&start) {
+            if let (Some(first), Some(second)) = (seen.depth, state.depth) {
+                if first != second {
+                    warnings.push(StackWarning {
+                        offset: start,
+                        kind: StackWarningKind::Mismatch(first, second),
+                    });
+                }
+            }
+            continue;
+        }
+        entry_states.insert(start, state);
+
+        let block = &cfg.blocks[&start];
+        let mut exits = false;
+        for (i, instruction) in block.instructions.iter().enumerate() {
+            depths.insert(instruction.offset, state.depth);
+
+            exits = ends_program(&block.instructions[..=i]);
+            let kind = match state.depth.filter(|depth| *depth != 0) {
+                Some(depth) if transfer_kind(instruction) == Some(EdgeKind::Return) => {
+                    Some(StackWarningKind::UnbalancedReturn(depth))
+                }
+                Some(depth) if exits => Some(StackWarningKind::UnbalancedExit(depth)),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                warnings.push(StackWarning {
+                    offset: instruction.offset,
+                    kind,
+                });
+            }
+            //* Nothing runs after the program exits, even where the CFG falls through
+            if exits {
+                break;
+            }
+
+            state = state.step(instruction, cleanups);
+        }
+        if exits {
+            continue;
+        }
+
+        for edge in &block.successors {
+            if !matches!(edge.kind, EdgeKind::Call | EdgeKind::Return) {
+                pending.push((edge.to, state));
+            }
+        }
+    }
+
+    warnings.sort_by_key(|warning| warning.offset);
+    warnings.dedup();
+
+    ProcedureStack {
+        entry,
+        depths,
+        cleanup: cleanups.get(&entry).copied().unwrap_or(0),
+        warnings,
+    }
+}
+
+//* Stack depths of the program entry and every detected procedure, in address order.
+//* The entry code is checked like a procedure, from where the program starts to its
+//* exits and rets
+pub fn analyse_stacks(cfg: &ControlFlowGraph, procedures: &[Procedure]) -> Vec<ProcedureStack> {
+    let cleanups: HashMap<usize, u16> = procedures
+        .iter()
+        .map(|procedure| (procedure.entry, callee_cleanup(cfg, procedure)))
+        .collect();
+
+    cfg.procedure_entries()
+        .into_iter()
+        .map(|entry| analyse_procedure(cfg, entry, &cleanups))
+        .collect()
+}
+
+//* `; warning: ...` at the instructions where the stack doesn't add up, and the
+//* arguments a procedure drops after its header. A warning found from several entries,
+//* e.g. in code the entry code and a procedure share, is written once
+pub fn stack_comments(stacks: &[ProcedureStack]) -> HashMap<usize, Vec<String>> {
+    let mut comments: HashMap<usize, Vec<String>> = HashMap::new();
+    let mut written = HashSet::new();
+
+    for stack in stacks {
+        if stack.cleanup > 0 {
+            comments.entry(stack.entry).or_default().push(format!(
+                "; {}: callee cleans up {} bytes of arguments",
+                procedure_name(stack.entry),
+                stack.cleanup
+            ));
+        }
+
+        for warning in &stack.warnings {
+            if !written.insert(*warning) {
+                continue;
+            }
+            comments.entry(warning.offset).or_default().push(format!(
+                "{}{}",
+                WARNING_COMMENT,
+                warning.message()
+            ));
+        }
+    }
+
+    comments
+}
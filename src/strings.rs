@@ -0,0 +1,161 @@
+use crate::decoder::*;
+use crate::instruction::*;
+
+use std::collections::{HashMap, HashSet};
+
+//* Shorter runs of text show up in code and tables too often
+const MIN_STRING_LENGTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringTerminator {
+    //* What INT 21h service 09h prints up to
+    Dollar,
+    Nul,
+    //* A length byte in front instead, as Pascal stores them
+    LengthPrefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundString {
+    pub start: usize,
+    //* Including the terminator or length byte
+    pub length: usize,
+    pub terminator: StringTerminator,
+}
+
+impl FoundString {
+    pub fn range(&self) -> DataRange {
+        DataRange {
+            start: self.start,
+            length: self.length,
+            kind: DataKind::String,
+        }
+    }
+}
+
+//* Printable ASCII and the whitespace DOS text uses
+fn is_text(byte: u8) -> bool {
+    (b' '..=b'~').contains(&byte) || matches!(byte, b'\r' | b'\n' | b'\t')
+}
+
+//* Looks for strings among the bytes `is_candidate` accepts, a string never spans a byte
+//* it rejects
+pub fn find_strings(image: &[u8], is_candidate: impl Fn(usize) -> bool) -> Vec<FoundString> {
+    let mut strings = Vec::new();
+
+    let mut start = 0;
+    while start < image.len() {
+        if !is_candidate(start) {
+            start += 1;
+            continue;
+        }
+
+        let text_length = (start..image.len())
+            .take_while(|offset| is_candidate(*offset) && is_text(image[*offset]))
+            .take_while(|offset| image[*offset] != b'$')
+            .count();
+        let end = start + text_length;
+
+        let terminator = match image.get(end) {
+            _ if text_length < MIN_STRING_LENGTH || !is_candidate(end) => None,
+            Some(b'$') => Some(StringTerminator::Dollar),
+            Some(0) => Some(StringTerminator::Nul),
+            _ => None,
+        };
+        if let Some(terminator) = terminator {
+            strings.push(FoundString {
+                start,
+                length: text_length + 1,
+                terminator,
+            });
+            start = end + 1;
+            continue;
+        }
+
+        let prefixed_length = image[start] as usize;
+        let text = start + 1..start + 1 + prefixed_length;
+        let is_prefixed = prefixed_length >= MIN_STRING_LENGTH
+            && text.end <= image.len()
+            && text
+                .clone()
+                .all(|offset| is_candidate(offset) && is_text(image[offset]));
+        if is_prefixed {
+            strings.push(FoundString {
+                start,
+                length: prefixed_length + 1,
+                terminator: StringTerminator::LengthPrefix,
+            });
+            start = text.end;
+            continue;
+        }
+
+        //* Any string starting later in the run would end without a terminator as well
+        start += text_length.max(1);
+    }
+
+    strings
+}
+
+//* Strings in the bytes recursive descent doesn't reach as code, outside the decoder's
+//* data ranges and the ranges `is_code` says are code
+pub fn detect_strings(
+    decoder: &Decoder,
+    image: &[u8],
+    is_code: impl Fn(usize) -> bool,
+) -> Vec<FoundString> {
+    let reached: HashSet<usize> = decoder
+        .decode_reachable(image)
+        .iter()
+        .flat_map(|instruction| instruction.offset..instruction.offset + instruction.size)
+        .collect();
+
+    find_strings(image, |offset| {
+        !reached.contains(&offset) && decoder.data_range_at(offset).is_none() && !is_code(offset)
+    })
+}
+
+//* `; dx: 'Hello', '$'` before every `mov` of a string's address into a register,
+//* e.g. the `mov dx, imm` before INT 21h service 09h. `origin` is where the image is
+//* loaded in its segment, 0x100 for a .COM program
+pub fn string_comments(
+    decoded: &[Instruction],
+    image: &[u8],
+    data_ranges: &[DataRange],
+    origin: usize,
+) -> HashMap<usize, Vec<String>> {
+    let strings: HashMap<usize, Data> = data_ranges
+        .iter()
+        .filter(|range| range.kind == DataKind::String)
+        .filter_map(|range| {
+            let bytes = image.get(range.start..range.start + range.length)?;
+            Some((
+                range.start,
+                Data {
+                    kind: DataKind::String,
+                    bytes: bytes.to_vec(),
+                },
+            ))
+        })
+        .collect();
+
+    decoded
+        .iter()
+        .filter_map(|instruction| {
+            let [Some(Operand::Register(register)), Some(Operand::Immediate(address))] =
+                instruction.operands
+            else {
+                return None;
+            };
+            if instruction.mnemonic != "mov" || !register.word {
+                return None;
+            }
+
+            let data = strings.get(&(address.value as usize).checked_sub(origin)?)?;
+            let text = data_items(data, &HashMap::new()).join(", ");
+            Some((
+                instruction.offset,
+                vec![format!("; {}: {}", register.name(), text)],
+            ))
+        })
+        .collect()
+}
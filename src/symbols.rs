@@ -0,0 +1,194 @@
+use crate::assembler::parse_number;
+use crate::decoder::DataRange;
+use crate::instruction::DataKind;
+use crate::prelude::*;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeKind {
+    Code,
+    Data(DataKind),
+}
+
+impl RangeKind {
+    fn keyword(self) -> &'static str {
+        match self {
+            RangeKind::Code => "code",
+            RangeKind::Data(DataKind::Byte) => "byte",
+            RangeKind::Data(DataKind::Word) => "word",
+            RangeKind::Data(DataKind::String) => "string",
+            RangeKind::Data(DataKind::Address) => "address",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolRange {
+    pub start: usize,
+    pub length: usize,
+    pub kind: RangeKind,
+}
+
+//* Names, code/data ranges and comments for a program, one entry per line:
+//*
+//*     # anything after a `#` at the start of a line is ignored
+//*     name 0x0100 start
+//*     code 0x0100 32
+//*     string 0x0120 14
+//*     comment 0x0100 prints the greeting
+//*
+//* Ranges are `byte`, `word`, `string`, `address` or `code` followed by start and length
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    pub names: BTreeMap<usize, String>,
+    pub ranges: Vec<SymbolRange>,
+    pub comments: BTreeMap<usize, Vec<String>>,
+}
+
+fn parse_address(text: &str, line_number: usize) -> Result<usize> {
+    match parse_number(text) {
+        Ok(value) if (0..=u16::MAX as i32).contains(&value) => Ok(value as usize),
+        _ => bail!("line {}: invalid address `{}`", line_number, text),
+    }
+}
+
+impl FromStr for Symbols {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut symbols = Self::default();
+
+        for (i, line) in s.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let keyword = parts.next().unwrap_or_default();
+            let (Some(address), Some(rest)) = (parts.next(), parts.next()) else {
+                bail!(
+                    "line {}: expected `{} <address> <value>`",
+                    line_number,
+                    keyword
+                );
+            };
+            let address = parse_address(address, line_number)?;
+            let rest = rest.trim();
+
+            let kind = match keyword {
+                "name" => {
+                    if rest.contains(char::is_whitespace) {
+                        bail!("line {}: name `{}` contains whitespace", line_number, rest);
+                    }
+                    symbols.names.insert(address, rest.to_owned());
+                    continue;
+                }
+                "comment" => {
+                    symbols
+                        .comments
+                        .entry(address)
+                        .or_default()
+                        .push(rest.to_owned());
+                    continue;
+                }
+                "code" => RangeKind::Code,
+                "byte" => RangeKind::Data(DataKind::Byte),
+                "word" => RangeKind::Data(DataKind::Word),
+                "string" => RangeKind::Data(DataKind::String),
+                "address" => RangeKind::Data(DataKind::Address),
+                _ => bail!("line {}: unknown entry `{}`", line_number, keyword),
+            };
+
+            symbols.ranges.push(SymbolRange {
+                start: address,
+                length: parse_address(rest, line_number)?,
+                kind,
+            });
+        }
+
+        Ok(symbols)
+    }
+}
+
+//* Writes the format FromStr reads
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (address, name) in &self.names {
+            writeln!(f, "name {:#06x} {}", address, name)?;
+        }
+        for range in &self.ranges {
+            writeln!(
+                f,
+                "{} {:#06x} {}",
+                range.kind.keyword(),
+                range.start,
+                range.length
+            )?;
+        }
+        for (address, comments) in &self.comments {
+            for comment in comments {
+                writeln!(f, "comment {:#06x} {}", address, comment)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Symbols {
+    //* What the decoder writes as `db`/`dw` instead of decoding
+    pub fn data_ranges(&self) -> Vec<DataRange> {
+        self.ranges
+            .iter()
+            .filter_map(|range| match range.kind {
+                RangeKind::Data(kind) => Some(DataRange {
+                    start: range.start,
+                    length: range.length,
+                    kind,
+                }),
+                RangeKind::Code => None,
+            })
+            .collect()
+    }
+
+    pub fn is_code(&self, offset: usize) -> bool {
+        self.ranges.iter().any(|range| {
+            range.kind == RangeKind::Code
+                && (range.start..range.start + range.length).contains(&offset)
+        })
+    }
+
+    //* Names from the file win over generated `labelN` and `proc_XXXX` ones
+    pub fn apply_names(&self, labels: &mut HashMap<usize, String>) {
+        labels.extend(
+            self.names
+                .iter()
+                .map(|(address, name)| (*address, name.clone())),
+        );
+    }
+
+    //* Generated labels not named yet become names, so they can be edited and loaded back
+    pub fn add_labels(&mut self, labels: &HashMap<usize, String>) {
+        for (address, label) in labels {
+            self.names.entry(*address).or_insert_with(|| label.clone());
+        }
+    }
+
+    //* `; text` lines before each commented instruction, for write_listing
+    pub fn listing_comments(&self) -> HashMap<usize, Vec<String>> {
+        self.comments
+            .iter()
+            .map(|(address, comments)| {
+                let lines = comments.iter().map(|text| format!("; {}", text)).collect();
+                (*address, lines)
+            })
+            .collect()
+    }
+}
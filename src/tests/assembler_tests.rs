@@ -0,0 +1,188 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::*;
+
+    //* Written in the style of the computer_enhance listings
+    const SOURCE: &str = "
+bits 16
+
+test_label0:
+mov cx, bx ; comment
+mov dx, [bp]
+mov [bp + di], byte 7
+mov al, [bx + si + 4999]
+mov ax, [bx + di - 37]
+mov [2554], ax
+add word [bp + si + 1000], 29
+sub byte [bx], 34
+add si, -2
+cmp ax, 1000
+jnz test_label0
+jz test_label1
+test_label1:
+loopnz $+0
+";
+
+    #[test]
+    fn assembles_listing_syntax() {
+        let bytes = assemble(SOURCE).unwrap();
+
+        assert_eq!(
+            bytes,
+            vec![
+                0x89, 0xd9, //
+                0x8b, 0x56, 0x00, //
+                0xc6, 0x03, 0x07, //
+                0x8a, 0x80, 0x87, 0x13, //
+                0x8b, 0x41, 0xdb, //
+                0xa3, 0xfa, 0x09, //
+                0x83, 0x82, 0xe8, 0x03, 0x1d, //
+                0x80, 0x2f, 0x22, //
+                0x83, 0xc6, 0xfe, //
+                0x3d, 0xe8, 0x03, //
+                0x75, 0xde, //
+                0x74, 0x00, //
+                0xe0, 0xfe, //
+            ]
+        );
+    }
+
+    #[test]
+    fn decoded_listing_reassembles() {
+        let bytes = assemble(SOURCE).unwrap();
+
+        let outputs = decode_instructions(&bytes).unwrap();
+
+        assert_eq!(assemble(&outputs.concat()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn interrupts_round_trip() {
+        let bytes = assemble("int 33\nint3\nint 0x10").unwrap();
+        assert_eq!(bytes, vec![0xcd, 0x21, 0xcc, 0xcd, 0x10]);
+
+        let outputs = decode_instructions(&bytes).unwrap();
+
+        assert_eq!(assemble(&outputs.concat()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn named_memory_operands() {
+        let source = "
+counter equ 1000
+mov [counter], word 5
+add ax, [es:value]
+value:
+dw 7
+";
+        let bytes = assemble(source).unwrap();
+
+        assert_eq!(
+            bytes,
+            assemble("mov [1000], word 5\nadd ax, [es:11]\ndw 7").unwrap()
+        );
+        assert!(assemble("mov ax, [missing]").is_err());
+    }
+
+    #[test]
+    fn transfers_round_trip() {
+        let source = "
+start:
+call start
+jmp short start
+jmp start
+ret
+ret 4
+retf
+retf 2
+push bp
+pop di
+call near start
+jmp near start
+";
+        let bytes = assemble(source).unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                0xe8, 0xfd, 0xff, //
+                0xeb, 0xfb, //
+                0xeb, 0xf9, //
+                0xc3, //
+                0xc2, 0x04, 0x00, //
+                0xcb, //
+                0xca, 0x02, 0x00, //
+                0x55, //
+                0x5f, //
+                0xe8, 0xec, 0xff, //
+                0xe9, 0xe9, 0xff, //
+            ]
+        );
+
+        let outputs = decode_instructions(&bytes).unwrap();
+        assert_eq!(outputs[3], "jmp short label0\n");
+        assert_eq!(outputs[10], "call label0\n");
+        //* A plain `jmp label0` would shrink to the short form
+        assert_eq!(outputs[11], "jmp near label0\n");
+
+        assert_eq!(assemble(&outputs.concat()).unwrap(), bytes);
+
+        //* Like nasm, a plain jmp only takes the near form when its target is out of range
+        let over = |count| assemble(&format!("jmp end\n{}end:\n", "ret\n".repeat(count)));
+        assert_eq!(over(127).unwrap()[..2], [0xeb, 0x7f]);
+        assert_eq!(over(128).unwrap()[..3], [0xe9, 0x80, 0x00]);
+        assert_eq!(assemble("jmp $+0x81").unwrap(), [0xeb, 0x7f]);
+        assert_eq!(assemble("jmp $+0x82").unwrap(), [0xe9, 0x7f, 0x00]);
+        assert!(assemble("start:\njmp near short start").is_err());
+        assert!(assemble("start:\njne near start").is_err());
+    }
+
+    #[test]
+    fn group_fe_ff_round_trip() {
+        let source = "\
+inc byte [bx]
+dec word [bp + 4]
+inc ax
+dec cl
+call bx
+call word [bx]
+call far [bx + si]
+jmp word [es:1000]
+jmp far [di]
+push word [bx]
+";
+        let bytes = assemble(source).unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                0xfe, 0x07, //
+                0xff, 0x4e, 0x04, //
+                0x40, //
+                0xfe, 0xc9, //
+                0xff, 0xd3, //
+                0xff, 0x17, //
+                0xff, 0x18, //
+                0x26, 0xff, 0x26, 0xe8, 0x03, //
+                0xff, 0x2d, //
+                0xff, 0x37, //
+            ]
+        );
+
+        let outputs = decode_instructions(&bytes).unwrap();
+        assert_eq!(outputs[1..].concat(), source);
+
+        assert!(assemble("push [bx]").is_err());
+        assert!(assemble("inc [bx]").is_err());
+        assert!(assemble("call far bx").is_err());
+        assert!(assemble("push byte [bx]").is_err());
+    }
+
+    #[test]
+    fn reports_undefined_labels() {
+        let error = assemble("jne nowhere").unwrap_err();
+
+        assert_eq!(error.to_string(), "line 1: undefined label `nowhere`");
+    }
+}
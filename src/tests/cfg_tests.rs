@@ -0,0 +1,130 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::*;
+
+    fn build(source: &str) -> ControlFlowGraph {
+        let bytes = assembler::assemble(source).unwrap();
+        let decoded = Decoder::new().decode_all(&bytes).unwrap();
+
+        build_cfg(&decoded).unwrap()
+    }
+
+    fn edges(cfg: &ControlFlowGraph) -> Vec<(usize, usize, EdgeKind)> {
+        cfg.edges()
+            .map(|edge| (edge.from, edge.to, edge.kind))
+            .collect()
+    }
+
+    #[test]
+    fn splits_blocks_at_jumps_and_targets() {
+        let cfg = build(
+            "
+mov cx, 3
+top:
+add ax, 1
+cmp ax, 10
+jne top
+mov bx, 1
+",
+        );
+
+        //* mov cx (3) | add ax (3), cmp ax (3), jne (2) | mov bx (3)
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), [0, 3, 11]);
+        assert_eq!(cfg.blocks[&3].instructions.len(), 3);
+        assert_eq!(cfg.blocks[&3].end, 11);
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, 3, EdgeKind::Fallthrough),
+                (3, 3, EdgeKind::Conditional),
+                (3, 11, EdgeKind::Fallthrough),
+            ]
+        );
+        assert_eq!(cfg.blocks[&3].predecessors.len(), 2);
+        assert_eq!(cfg.block_containing(7).unwrap().start, 3);
+    }
+
+    #[test]
+    fn distinguishes_loops_and_unconditional_jumps() {
+        let cfg = build(
+            "
+top:
+loop top
+jcxz done
+jmp top
+done:
+int3
+",
+        );
+
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, 0, EdgeKind::Loop),
+                (0, 2, EdgeKind::Fallthrough),
+                (2, 6, EdgeKind::Loop),
+                (2, 4, EdgeKind::Fallthrough),
+                (4, 0, EdgeKind::Unconditional),
+            ]
+        );
+        assert!(cfg.blocks[&6].successors.is_empty());
+    }
+
+    #[test]
+    fn returns_go_back_to_every_call_site() {
+        let cfg = build(
+            "
+call helper
+call helper
+int 32
+helper:
+cmp ax, 0
+je skip
+add ax, 1
+skip:
+ret
+",
+        );
+
+        //* call (3) | call (3) | int (2) | cmp (3), je (2) | add (3) | ret
+        assert_eq!(
+            cfg.blocks.keys().copied().collect::<Vec<_>>(),
+            [0, 3, 6, 8, 13, 16]
+        );
+        let calls: Vec<_> = edges(&cfg)
+            .into_iter()
+            .filter(|(_, _, kind)| matches!(kind, EdgeKind::Call | EdgeKind::Return))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                (0, 8, EdgeKind::Call),
+                (3, 8, EdgeKind::Call),
+                (16, 3, EdgeKind::Return),
+                (16, 6, EdgeKind::Return),
+            ]
+        );
+        assert!(cfg.blocks[&0]
+            .successors
+            .iter()
+            .any(|edge| edge.to == 3 && edge.kind == EdgeKind::Fallthrough));
+    }
+
+    #[test]
+    fn reports_transfers_outside_the_code() {
+        let cfg = build("jmp $+100\nmov ax, 1");
+
+        assert_eq!(
+            cfg.unresolved,
+            [Edge {
+                from: 0,
+                to: 100,
+                kind: EdgeKind::Unconditional
+            }]
+        );
+        assert!(cfg.blocks[&2].predecessors.is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::clocks::*;
+    use crate::simulator::*;
+
+    fn listing_clocks(source: &str, model: CpuModel) -> Vec<String> {
+        let bytes = assemble(source).unwrap();
+        let mut decoder = Decoder::new();
+        let decoded = decoder.decode_all(&bytes).unwrap();
+
+        write_listing(
+            &bytes,
+            &decoded,
+            &decoder.labels,
+            &Default::default(),
+            &PlainStyle,
+            Some(model),
+        )
+        .unwrap()
+        .into_iter()
+        .skip(1)
+        .map(|line| line.trim_end().to_owned())
+        .collect()
+    }
+
+    #[test]
+    fn effective_address_table() {
+        let address = |rm, displacement_size, segment| EffectiveAddress {
+            rm,
+            displacement: 0,
+            displacement_size,
+            segment,
+        };
+
+        assert_eq!(effective_address_clocks(&address(None, 2, None)), 6);
+        assert_eq!(effective_address_clocks(&address(Some(0b111), 0, None)), 5);
+        assert_eq!(effective_address_clocks(&address(Some(0b110), 1, None)), 9);
+        assert_eq!(effective_address_clocks(&address(Some(0b000), 0, None)), 7);
+        assert_eq!(effective_address_clocks(&address(Some(0b011), 0, None)), 7);
+        assert_eq!(effective_address_clocks(&address(Some(0b001), 0, None)), 8);
+        assert_eq!(effective_address_clocks(&address(Some(0b010), 0, None)), 8);
+        assert_eq!(effective_address_clocks(&address(Some(0b000), 2, None)), 11);
+        assert_eq!(effective_address_clocks(&address(Some(0b010), 1, None)), 12);
+        assert_eq!(
+            effective_address_clocks(&address(Some(0b111), 0, Some(0))),
+            7
+        );
+    }
+
+    #[test]
+    fn listing_comments() {
+        let lines = listing_clocks(
+            "
+mov bx, 1000
+mov cx, [bx]
+mov dx, [1000]
+mov ax, [1000]
+add [bp + si + 4], cx
+cmp word [bx], 4
+",
+            CpuModel::I8086,
+        );
+
+        assert_eq!(
+            lines,
+            [
+                "mov bx, 1000 ; Clocks: +4 = 4",
+                "mov cx, [bx] ; Clocks: +13 = 17 (8 + 5ea)",
+                "mov dx, [1000] ; Clocks: +14 = 31 (8 + 6ea)",
+                "mov ax, [1000] ; Clocks: +10 = 41",
+                "add [bp + si + 4], cx ; Clocks: +28 = 69 (16 + 12ea)",
+                "cmp [bx], word 4 ; Clocks: +15 = 84 (10 + 5ea)",
+            ]
+        );
+    }
+
+    #[test]
+    fn word_transfer_penalties() {
+        let source = "
+mov dx, [1001]
+add [1000], dx
+mov dl, [1001]
+";
+
+        assert_eq!(
+            listing_clocks(source, CpuModel::I8086),
+            [
+                "mov dx, [1001] ; Clocks: +18 = 18 (8 + 6ea + 4p)",
+                "add [1000], dx ; Clocks: +22 = 40 (16 + 6ea)",
+                "mov dl, [1001] ; Clocks: +14 = 54 (8 + 6ea)",
+            ]
+        );
+        assert_eq!(
+            listing_clocks(source, CpuModel::I8088),
+            [
+                "mov dx, [1001] ; Clocks: +18 = 18 (8 + 6ea + 4p)",
+                "add [1000], dx ; Clocks: +30 = 48 (16 + 6ea + 8p)",
+                "mov dl, [1001] ; Clocks: +14 = 62 (8 + 6ea)",
+            ]
+        );
+    }
+
+    #[test]
+    fn simulator_accumulates_clocks() {
+        let mut cpu = Cpu::new(
+            assemble(
+                "
+mov bx, 1001
+mov cx, 2
+loop_start:
+add [bx], cx
+sub cx, 1
+jnz loop_start
+",
+            )
+            .unwrap(),
+        );
+        cpu.clock_model = Some(CpuModel::I8086);
+
+        let trace = run_with_trace(&mut cpu, true).unwrap();
+        let lines: Vec<&str> = trace.lines().take(8).map(str::trim_end).collect();
+
+        assert_eq!(
+            lines,
+            [
+                "mov bx, 1001 ; Clocks: +4 = 4 | bx:0x0->0x3e9 ip:0x0->0x3",
+                "mov cx, 2 ; Clocks: +4 = 8 | cx:0x0->0x2 ip:0x3->0x6",
+                "add word [bx], cx ; Clocks: +29 = 37 (16 + 5ea + 8p) | ip:0x6->0x8",
+                "sub cx, 1 ; Clocks: +4 = 41 | cx:0x2->0x1 ip:0x8->0xb",
+                "jne $-7+2 ; Clocks: +16 = 57 | ip:0xb->0x6",
+                "add word [bx], cx ; Clocks: +29 = 86 (16 + 5ea + 8p) | ip:0x6->0x8 flags:->P",
+                "sub cx, 1 ; Clocks: +4 = 90 | cx:0x1->0x0 ip:0x8->0xb flags:P->PZ",
+                "jne $-7+2 ; Clocks: +4 = 94 | ip:0xb->0xd",
+            ]
+        );
+        assert_eq!(cpu.clocks, 94);
+        assert_eq!(cpu.memory.read_word(0, 1001), 3);
+    }
+}
@@ -0,0 +1,51 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::*;
+
+    //* mov cx, bx / jne back to the mov
+    const INSTRUCTIONS: [u8; 4] = [0x89, 0xd9, 0x75, 0xfc];
+
+    fn listing(style: &dyn Style) -> Vec<String> {
+        let mut decoder = Decoder::new();
+        let decoded = decoder.decode_all(&INSTRUCTIONS).unwrap();
+
+        write_listing(
+            &INSTRUCTIONS,
+            &decoded,
+            &decoder.labels,
+            &Default::default(),
+            style,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn plain_style_matches_decode_instructions() {
+        assert_eq!(
+            listing(ColorChoice::Never.style()),
+            decode_instructions(&INSTRUCTIONS).unwrap()
+        );
+    }
+
+    #[test]
+    fn ansi_style_highlights_tokens() {
+        let outputs = listing(ColorChoice::Always.style());
+
+        assert_eq!(
+            outputs[1],
+            "\x1b[1;35mlabel0\x1b[0m:\n\x1b[1;34mmov\x1b[0m \x1b[32mcx\x1b[0m, \x1b[32mbx\x1b[0m\n"
+        );
+        assert_eq!(outputs[2], "\x1b[1;34mjne\x1b[0m \x1b[4;35mlabel0\x1b[0m\n");
+    }
+
+    #[test]
+    fn color_choice_parses() {
+        assert_eq!("auto".parse::<ColorChoice>().unwrap(), ColorChoice::Auto);
+        assert_eq!("never".parse::<ColorChoice>().unwrap(), ColorChoice::Never);
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::*;
+    use crate::dataflow::*;
+
+    use std::collections::HashMap;
+
+    const CX: Register = Register { reg: 1, word: true };
+    const AH: Register = Register {
+        reg: 4,
+        word: false,
+    };
+    const SP: Register = Register { reg: 4, word: true };
+
+    fn analyse(source: &str) -> (Vec<u8>, ControlFlowGraph, Dataflow) {
+        let bytes = assembler::assemble(source).unwrap();
+        let decoded = Decoder::new().decode_all(&bytes).unwrap();
+        let cfg = build_cfg(&decoded).unwrap();
+        let dataflow = Dataflow::build(&cfg);
+
+        (bytes, cfg, dataflow)
+    }
+
+    fn comments(source: &str) -> Vec<String> {
+        let (bytes, cfg, dataflow) = analyse(source);
+        let comments = dataflow_comments(&dataflow, &cfg, &bytes, &HashMap::new());
+        let mut offsets: Vec<&usize> = comments.keys().collect();
+        offsets.sort();
+
+        offsets
+            .into_iter()
+            .flat_map(|offset| comments[offset].clone())
+            .collect()
+    }
+
+    #[test]
+    fn constants_propagate_through_arithmetic() {
+        let (_, _, dataflow) =
+            analyse("mov cx, 5\nadd cx, 3\nmov ax, 0x1234\nmov ah, 9\ncmp cx, 1\nret");
+        //* Offsets: mov cx 0, add 3, mov ax 6, mov ah 9, cmp 11, ret 14
+        let state = dataflow.state_before(14).unwrap();
+
+        assert_eq!(state.get(CX), Some(8));
+        assert_eq!(state.get(AH), Some(9));
+        assert_eq!(state.get(Register { reg: 0, word: true }), Some(0x0934));
+        assert_eq!(dataflow.state_before(3).unwrap().get(CX), Some(5));
+    }
+
+    #[test]
+    fn unknown_sources_and_calls_forget_values() {
+        let (_, _, dataflow) = analyse("mov cx, 5\nadd cx, bx\nmov dx, 2\nmov bp, 7\nint 33\nret");
+        //* Offsets: mov cx 0, add 3, mov dx 5, mov bp 8, int 11, ret 13
+        let state = dataflow.state_before(13).unwrap();
+
+        assert_eq!(state.get(CX), None);
+        assert_eq!(state.get(Register { reg: 2, word: true }), None);
+        assert_eq!(state.get(Register { reg: 5, word: true }), Some(7));
+    }
+
+    #[test]
+    fn uses_are_chained_to_their_definitions() {
+        let (_, _, dataflow) = analyse("mov bx, 16\nmov ax, [bx + si]\nadd bx, ax\nret");
+        //* Offsets: mov bx 0, mov ax 3, add 5, ret 7
+        let block = &dataflow.blocks[&0];
+
+        assert_eq!(block.definition_of(3, Location::Register(3)), Some(0));
+        assert_eq!(block.definition_of(3, Location::Register(6)), None);
+        assert_eq!(block.definition_of(5, Location::Register(0)), Some(3));
+        assert!(block.chains.contains(&DefUse {
+            location: Location::Segment(3),
+            used_at: 3,
+            defined_at: None,
+        }));
+
+        let access = access(&Decoder::new().decode_all(&[0x31, 0xC0]).unwrap()[0]);
+        assert!(access.uses.is_empty());
+        assert_eq!(
+            access.defs.into_iter().collect::<Vec<_>>(),
+            [Location::Register(0)]
+        );
+    }
+
+    #[test]
+    fn pushes_and_pops_move_values_and_sp() {
+        let (_, _, dataflow) =
+            analyse("mov sp, 0x100\nmov ax, 0xb800\npush ax\npop dx\nmov es, dx\npush cx\nret");
+        //* Offsets: mov sp 0, mov ax 3, push 6, pop 7, mov 8, push 10, ret 11
+        let state = dataflow.state_before(11).unwrap();
+
+        assert_eq!(state.segment(0), Some(0xb800));
+        assert_eq!(state.get(SP), Some(0xFE));
+    }
+
+    #[test]
+    fn segment_loads_and_indirect_targets_are_annotated() {
+        //* The table is at 18, after the 3 byte jmp at 15
+        let comments = comments(
+            "
+mov ax, 0xb800
+mov ds, ax
+mov es, bx
+mov bx, 18
+call [bx]
+mov si, 2
+jmp word [si + 18]
+dw 0x1234, 0x5678
+",
+        );
+
+        assert_eq!(
+            comments,
+            ["; ds = 0xb800", "; target: 0x1234", "; target: 0x5678"]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::debugger::*;
+    use crate::simulator::*;
+
+    fn run_script(source: &str, script: &str) -> Result<String> {
+        let mut debugger = Debugger::new(Cpu::new(assemble(source)?));
+        let mut output = Vec::new();
+
+        run_debugger(&mut debugger, &mut script.as_bytes(), &mut output, false)?;
+
+        Ok(String::from_utf8(output)?)
+    }
+
+    const PROGRAM: &str = "
+mov cx, 2
+mov bx, 1000
+loop_start:
+add word [bx], 5
+sub cx, 1
+jnz loop_start
+";
+
+    #[test]
+    fn scripted_session() {
+        let output = run_script(
+            PROGRAM,
+            "
+# stop at the add every iteration
+break 6
+watch 1000
+step
+continue
+disasm 6 3
+continue
+mem ds:1000 4
+set ax 0x1234
+set ah 0xff
+regs
+quit
+step
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            "=> 0x0000: mov cx, 2
+(dbg) break 6
+Breakpoint set at 0x0006
+(dbg) watch 1000
+Watching 0000:03e8 (0x0)
+(dbg) step
+mov cx, 2 ; cx:0x0->0x2 ip:0x0->0x3 
+=> 0x0003: mov bx, 1000
+(dbg) continue
+mov bx, 1000 ; bx:0x0->0x3e8 ip:0x3->0x6 
+Breakpoint at 0x0006
+=> 0x0006: add [bx], word 5
+(dbg) disasm 6 3
+=> 0x0006: add [bx], word 5
+   0x0009: sub cx, 1
+   0x000c: jne $-6
+(dbg) continue
+add word [bx], 5 ; ip:0x6->0x9 flags:->P 
+watch 0000:03e8: 0x0->0x5
+=> 0x0009: sub cx, 1
+(dbg) mem ds:1000 4
+0000:03e8: 05 00 00 00
+(dbg) set ax 0x1234
+(dbg) set ah 0xff
+(dbg) regs
+      ax: 0xff34 (65332)
+      cx: 0x0002 (2)
+      dx: 0x0000 (0)
+      bx: 0x03e8 (1000)
+      sp: 0x0000 (0)
+      bp: 0x0000 (0)
+      si: 0x0000 (0)
+      di: 0x0000 (0)
+      es: 0x0000 (0)
+      cs: 0x0000 (0)
+      ss: 0x0000 (0)
+      ds: 0x0000 (0)
+      ip: 0x0009 (9)
+   flags: P
+(dbg) quit
+"
+        );
+    }
+
+    #[test]
+    fn continue_runs_to_the_end() {
+        let output = run_script(PROGRAM, "continue\nstep\n").unwrap();
+
+        assert!(output.ends_with(
+            "sub cx, 1 ; cx:0x1->0x0 ip:0x9->0xc flags:P->PZ \njne $-8+2 ; ip:0xc->0xe \nProgram halted\n(dbg) step\nProgram halted\n"
+        ));
+    }
+
+    #[test]
+    fn stepping_backwards() {
+        let output = run_script(
+            PROGRAM,
+            "
+continue
+last-write 1000
+back 2
+rewind 6 1
+back
+",
+        )
+        .unwrap();
+
+        let session = &output[output.find("(dbg) last-write").unwrap()..];
+        assert_eq!(
+            session,
+            "(dbg) last-write 1000
+0000:03e8 was written by `add [bx], word 5` at step 5 (0x5->0xa)
+Step 5
+=> 0x0006: add [bx], word 5
+(dbg) back 2
+Step 3
+=> 0x0009: sub cx, 1
+(dbg) rewind 6 1
+Step 2
+=> 0x0006: add [bx], word 5
+(dbg) back
+Step 1
+=> 0x0003: mov bx, 1000
+"
+        );
+    }
+
+    #[test]
+    fn invalid_commands_fail_scripts() {
+        assert!(run_script(PROGRAM, "frobnicate\n").is_err());
+        assert!(run_script(PROGRAM, "set zx 1\n").is_err());
+        assert!(run_script(PROGRAM, "delete 6\n").is_err());
+        assert!(run_script(PROGRAM, "mem 1000\n").is_err());
+        assert!(run_script(PROGRAM, "back\n").is_err());
+        assert!(run_script(PROGRAM, "last-write 1000\n").is_err());
+    }
+
+    #[test]
+    fn disassembly_wraps_around_the_top_of_memory() {
+        //* 0xffff:0x0010 is physical address 0, where the program is, so the add at 0x000f
+        //* takes its operand bytes from the start of memory
+        let output = run_script(PROGRAM, "set cs 0xffff\ndisasm 0x0f 2\n").unwrap();
+
+        assert!(output.ends_with(
+            "(dbg) disasm 0x0f 2
+   0x000f: add [word bx + di + 2], bh
+   0x0013: mov bx, 1000
+"
+        ));
+    }
+}
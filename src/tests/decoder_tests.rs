@@ -3,32 +3,21 @@ use crate::*;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::assemble;
+    use std::fs;
 
-    fn execute_nasm(asm_filepath: &str) -> Result<()> {
-        //*give output to nasm, then open test and compare bytes
-        const NASM_PATH: &str = "nasm";
+    //* Assembles with the built in assembler instead of shelling out to nasm
+    fn assemble_file(asm_filepath: &str) -> Result<Vec<u8>> {
+        let source = fs::read_to_string(asm_filepath)?;
 
-        let mut first_command = Command::new(NASM_PATH);
-
-        first_command.arg(asm_filepath);
-
-        if first_command.execute_check_exit_status_code(0).is_err() {
-            bail!(
-                "The path `{}` is not a correct FFmpeg executable binary file.",
-                NASM_PATH
-            );
-        }
-
-        Ok(())
+        assemble(&source)
     }
 
     fn decode_bytes(filepath: &str, should_delete_temp_files: bool) -> Result<(Vec<u8>, Vec<u8>)> {
         let mut correct_asm_filepath = filepath.to_owned();
         correct_asm_filepath.push_str(".asm");
         //* assemble correct asm
-        execute_nasm(&correct_asm_filepath).unwrap();
-
-        let correct = fs::read(filepath).unwrap();
+        let correct = assemble_file(&correct_asm_filepath).unwrap();
 
         let instructions = &correct;
         // println!("{:?}", instructions);
@@ -37,13 +26,9 @@ mod tests {
 
         let asm_output_filename = write_to_test_file(filepath, outputs).unwrap();
 
-        execute_nasm(&asm_output_filename).unwrap();
-
-        let output_filename = &asm_output_filename[0..asm_output_filename.len() - 4];
-        let bytes_to_test = fs::read(output_filename).unwrap();
+        let bytes_to_test = assemble_file(&asm_output_filename).unwrap();
 
         if should_delete_temp_files {
-            fs::remove_file(output_filename)?;
             fs::remove_file(asm_output_filename)?;
         }
 
@@ -0,0 +1,257 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dos::*;
+    use crate::flags::*;
+    use crate::memory::*;
+    use crate::simulator::*;
+
+    use std::{fs, path::PathBuf};
+
+    //* Where the tests put strings and buffers, past the programs
+    const DATA: u16 = 0x200;
+    const BUFFER: u16 = 0x300;
+
+    fn load(source: &str, sandbox: PathBuf, keyboard: &[u8]) -> Cpu {
+        let program = assembler::assemble(source).unwrap();
+        Cpu::load_com(program, DosServices::new(sandbox, keyboard)).unwrap()
+    }
+
+    fn store(cpu: &mut Cpu, offset: u16, data: &[u8]) {
+        let address = Memory::physical_address(COM_SEGMENT, offset);
+        cpu.memory.load(address, data).unwrap();
+    }
+
+    fn run(cpu: &mut Cpu) -> Result<()> {
+        while !cpu.is_halted() {
+            cpu.step()?;
+        }
+
+        Ok(())
+    }
+
+    fn output(cpu: &Cpu) -> String {
+        String::from_utf8_lossy(&cpu.services.as_ref().unwrap().output).into_owned()
+    }
+
+    //* An empty directory of its own for every test
+    fn sandbox(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("disassembler_dos_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    #[test]
+    fn loads_com_programs_like_dos() {
+        let cpu = load("mov ax, 1", PathBuf::from("."), &[]);
+
+        assert_eq!(cpu.state.segment_registers, [COM_SEGMENT; 4]);
+        assert_eq!(cpu.state.ip, 0x100);
+        assert_eq!(cpu.state.registers[4], 0xFFFE);
+        assert_eq!(cpu.memory.read_byte(COM_SEGMENT, 0x100), 0xB8);
+    }
+
+    #[test]
+    fn prints_strings_and_exits_with_code() {
+        let mut cpu = load(
+            "
+mov dx, 512
+mov ah, 9
+int 33
+mov ax, 0x4c03
+int 33
+mov bx, 1
+",
+            PathBuf::from("."),
+            &[],
+        );
+        store(&mut cpu, DATA, b"Hello, world!\r\n$");
+
+        run(&mut cpu).unwrap();
+
+        assert_eq!(output(&cpu), "Hello, world!\r\n");
+        assert_eq!(cpu.exit_code, Some(3));
+        //* Nothing after the exit executes
+        assert_eq!(cpu.state.registers[3], 0);
+    }
+
+    #[test]
+    fn prints_characters_through_dos_and_bios() {
+        let mut cpu = load(
+            "
+mov dl, 65
+mov ah, 2
+int 33
+mov al, 66
+mov ah, 14
+int 16
+int 32
+",
+            PathBuf::from("."),
+            &[],
+        );
+
+        run(&mut cpu).unwrap();
+
+        assert_eq!(output(&cpu), "AB");
+        assert_eq!(cpu.exit_code, Some(0));
+    }
+
+    #[test]
+    fn reads_scripted_keyboard_input() {
+        let mut cpu = load(
+            "
+mov ah, 1
+int 22
+mov bx, ax
+mov ah, 0
+int 22
+mov cx, ax
+mov ah, 0
+int 22
+",
+            PathBuf::from("."),
+            b"xy",
+        );
+
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.state.registers[3], b'x' as u16);
+        assert_eq!(cpu.state.registers[1], b'x' as u16);
+        assert!(!cpu.get_flag(FLAG_ZERO));
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.state.registers[0], b'y' as u16);
+
+        let mut cpu = load(
+            "mov ah, 1\nint 22\nmov ah, 0\nint 22",
+            PathBuf::from("."),
+            &[],
+        );
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.get_flag(FLAG_ZERO));
+
+        let error = run(&mut cpu).unwrap_err();
+        assert!(error.to_string().contains("ran out"), "{}", error);
+    }
+
+    #[test]
+    fn reads_and_writes_files_in_the_sandbox() {
+        let directory = sandbox("files");
+        fs::write(directory.join("IN.TXT"), "input data").unwrap();
+        fs::write(directory.join("OUT.TXT"), "").unwrap();
+
+        let mut cpu = load(
+            "
+mov dx, 512
+mov ax, 0x3d00
+int 33
+mov bx, ax
+mov dx, 768
+mov cx, 5
+mov ah, 0x3f
+int 33
+mov si, ax
+mov ah, 0x3e
+int 33
+mov dx, 528
+mov ax, 0x3d01
+int 33
+mov bx, ax
+mov dx, 768
+mov cx, si
+mov ah, 0x40
+int 33
+mov ah, 0x3e
+int 33
+",
+            directory.clone(),
+            &[],
+        );
+        store(&mut cpu, DATA, b"IN.TXT\0");
+        store(&mut cpu, DATA + 16, b"OUT.TXT\0");
+
+        run(&mut cpu).unwrap();
+
+        assert_eq!(
+            cpu.memory
+                .range(Memory::physical_address(COM_SEGMENT, BUFFER), 5)
+                .unwrap(),
+            b"input"
+        );
+        assert_eq!(fs::read(directory.join("OUT.TXT")).unwrap(), b"input");
+        assert!(!cpu.get_flag(FLAG_CARRY));
+
+        let _ = fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn file_errors_set_carry() {
+        let directory = sandbox("errors");
+
+        for (name, error) in [
+            &b"MISSING.TXT\0"[..],
+            b"..\\SECRET.TXT\0",
+            b"C:\\SECRET.TXT\0",
+            b"/etc/passwd\0",
+        ]
+        .into_iter()
+        .zip([2, 3, 3, 3])
+        {
+            let mut cpu = load(
+                "mov dx, 512\nmov ax, 0x3d00\nint 33",
+                directory.clone(),
+                &[],
+            );
+            store(&mut cpu, DATA, name);
+
+            run(&mut cpu).unwrap();
+
+            assert!(cpu.get_flag(FLAG_CARRY));
+            assert_eq!(cpu.state.registers[0], error);
+        }
+
+        let mut cpu = load("mov bx, 9\nmov ah, 0x3e\nint 33", directory.clone(), &[]);
+        run(&mut cpu).unwrap();
+        assert!(cpu.get_flag(FLAG_CARRY));
+        assert_eq!(cpu.state.registers[0], 6);
+
+        let _ = fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn unknown_services_stop_with_the_instruction() {
+        let mut cpu = load("mov ah, 48\nint 33", PathBuf::from("."), &[]);
+
+        let error = run(&mut cpu).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Unsupported service `int 33` with ah = 0x30 at 1000:0102"
+        );
+
+        let mut cpu = load("int3", PathBuf::from("."), &[]);
+        let error = run(&mut cpu).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unsupported interrupt `int3` at 1000:0100"
+        );
+    }
+
+    #[test]
+    fn interrupts_need_services() {
+        let mut cpu = Cpu::new(assembler::assemble("int 33").unwrap());
+
+        let error = cpu.step().unwrap_err();
+
+        assert!(error.to_string().contains("`int 33`"), "{}", error);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::*;
+    use crate::dot::*;
+
+    const PROGRAM: &str = "
+call helper
+int 32
+helper:
+cmp ax, 0
+jne done
+mov [1000], word 1
+done:
+ret
+";
+
+    fn build() -> (ControlFlowGraph, Decoder) {
+        let bytes = assembler::assemble(PROGRAM).unwrap();
+        let mut decoder = Decoder::new();
+        let decoded = decoder.decode_all(&bytes).unwrap();
+
+        (build_cfg(&decoded).unwrap(), decoder)
+    }
+
+    #[test]
+    fn writes_blocks_and_labelled_edges() {
+        let (cfg, decoder) = build();
+
+        let dot = write_cfg_dot(&cfg, &decoder.labels).unwrap();
+
+        assert_eq!(
+            dot,
+            r#"digraph cfg {
+    node [shape=box, fontname="monospace"];
+    b0 [label="0x0000:\l    call label0\l"];
+    b3 [label="0x0003:\l    int 32\l"];
+    b5 [label="label0:\l    cmp ax, 0\l    jne label1\l"];
+    ba [label="0x000a:\l    mov [1000], word 1\l"];
+    b10 [label="label1:\l    ret\l"];
+    b0 -> b5 [label="call", style="dashed", color="blue"];
+    b0 -> b3 [label="fallthrough"];
+    b3 -> b5 [label="fallthrough"];
+    b5 -> b10 [label="taken", color="darkgreen"];
+    b5 -> ba [label="fallthrough"];
+    ba -> b10 [label="fallthrough"];
+    b10 -> b3 [label="return", style="dotted", color="gray"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn writes_call_graph_between_procedures() {
+        let (cfg, decoder) = build();
+
+        let dot = write_call_graph_dot(&cfg, &decoder.labels).unwrap();
+
+        assert_eq!(
+            dot,
+            r#"digraph calls {
+    node [shape=box, fontname="monospace"];
+    p0 [label="0x0000"];
+    p5 [label="label0"];
+    p0 -> p5;
+}
+"#
+        );
+    }
+}
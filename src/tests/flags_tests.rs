@@ -0,0 +1,114 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::*;
+    use crate::simulator::*;
+
+    #[test]
+    fn add_sets_carry_overflow_and_auxiliary_carry() {
+        assert_eq!(
+            add_with_flags(0xFF, 0x01, false, false),
+            (
+                0x00,
+                FLAG_CARRY | FLAG_PARITY | FLAG_AUXILIARY_CARRY | FLAG_ZERO
+            )
+        );
+        assert_eq!(
+            add_with_flags(0x7FFF, 0x0001, false, true),
+            (
+                0x8000,
+                FLAG_PARITY | FLAG_AUXILIARY_CARRY | FLAG_SIGN | FLAG_OVERFLOW
+            )
+        );
+        assert_eq!(add_with_flags(0x10, 0x01, true, false), (0x12, FLAG_PARITY));
+    }
+
+    #[test]
+    fn sub_sets_borrow_and_overflow() {
+        assert_eq!(
+            sub_with_flags(0x0000, 0x0001, false, true),
+            (
+                0xFFFF,
+                FLAG_CARRY | FLAG_PARITY | FLAG_AUXILIARY_CARRY | FLAG_SIGN
+            )
+        );
+        assert_eq!(
+            sub_with_flags(0x80, 0x01, false, false),
+            (0x7F, FLAG_AUXILIARY_CARRY | FLAG_OVERFLOW)
+        );
+        assert_eq!(
+            sub_with_flags(0x05, 0x04, true, false),
+            (0x00, FLAG_PARITY | FLAG_ZERO)
+        );
+    }
+
+    #[test]
+    fn format_flags_in_reference_order() {
+        assert_eq!(format_flags(FLAG_ZERO | FLAG_PARITY), "PZ");
+        assert_eq!(format_flags(FLAG_OVERFLOW | FLAG_CARRY | FLAG_SIGN), "CSO");
+        assert_eq!(format_flags(0), "");
+    }
+
+    #[test]
+    fn trace_shows_flag_transitions() {
+        //* listing_0046_add_sub_cmp
+        let program = assembler::assemble(
+            "
+mov bx, -4093
+mov cx, 3841
+sub bx, cx
+mov sp, 998
+mov bp, 999
+cmp bp, sp
+add bp, 1027
+sub bp, 2026
+",
+        )
+        .unwrap();
+
+        let mut cpu = Cpu::new(program);
+        let trace = run_with_trace(&mut cpu, false).unwrap();
+
+        assert_eq!(
+            trace,
+            "mov bx, 61443 ; bx:0x0->0xf003 
+mov cx, 3841 ; cx:0x0->0xf01 
+sub bx, cx ; bx:0xf003->0xe102 flags:->S 
+mov sp, 998 ; sp:0x0->0x3e6 
+mov bp, 999 ; bp:0x0->0x3e7 
+cmp bp, sp ; flags:S-> 
+add bp, 1027 ; bp:0x3e7->0x7ea 
+sub bp, 2026 ; bp:0x7ea->0x0 flags:->PZ 
+
+Final registers:
+      bx: 0xe102 (57602)
+      cx: 0x0f01 (3841)
+      sp: 0x03e6 (998)
+   flags: PZ
+"
+        );
+    }
+
+    #[test]
+    fn flag_instructions_and_logic_ops() {
+        let program = assembler::assemble(
+            "
+stc
+std
+mov al, 15
+and al, 3
+adc al, 1
+xor ax, ax
+",
+        )
+        .unwrap();
+
+        let mut cpu = Cpu::new(program);
+        run_with_trace(&mut cpu, false).unwrap();
+
+        assert_eq!(cpu.state.registers[0], 0);
+        assert_eq!(cpu.state.flags, FLAG_DIRECTION | FLAG_PARITY | FLAG_ZERO);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::framebuffer::*;
+    use crate::simulator::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn framebuffer_spec() {
+        assert_eq!(
+            "0x100,64x32,bgra".parse::<Framebuffer>().unwrap(),
+            Framebuffer {
+                address: 256,
+                width: 64,
+                height: 32,
+                format: PixelFormat::Bgra,
+            }
+        );
+        assert_eq!(
+            "256,64x64".parse::<Framebuffer>().unwrap(),
+            Framebuffer::default()
+        );
+        assert!("256,64".parse::<Framebuffer>().is_err());
+        assert!("256,64x64,cmyk".parse::<Framebuffer>().is_err());
+    }
+
+    #[test]
+    fn ppm_and_png_encoding() {
+        let rgb = [255, 0, 0, 0, 255, 0];
+
+        assert_eq!(
+            encode_ppm(2, 1, &rgb),
+            b"P6\n2 1\n255\n\xff\x00\x00\x00\xff\x00"
+        );
+
+        let png = encode_png(2, 1, &rgb);
+        assert_eq!(
+            png[..8],
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n']
+        );
+        //* IHDR: 2x1, 8 bit rgb
+        assert_eq!(png[12..16], *b"IHDR");
+        assert_eq!(png[16..29], [0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        //* IDAT: zlib header, one final stored block of the filter byte plus 6 pixel bytes
+        assert_eq!(png[37..41], *b"IDAT");
+        assert_eq!(
+            png[41..53],
+            [0x78, 0x01, 1, 7, 0, 0xF8, 0xFF, 0, 255, 0, 0, 0]
+        );
+        assert_eq!(
+            png[png.len() - 12..],
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn render_simulated_pixels() {
+        //* Fills a 2x2 rgba framebuffer at 256 with (pixel index, 0x80, 0x40, 0xFF)
+        let mut cpu = Cpu::new(
+            assemble(
+                "
+mov bp, 256
+mov cx, 4
+mov dx, 0
+draw:
+mov [bp + 0], dl
+mov byte [bp + 1], 0x80
+mov byte [bp + 2], 0x40
+mov byte [bp + 3], 255
+add bp, 4
+add dx, 1
+sub cx, 1
+jnz draw
+",
+            )
+            .unwrap(),
+        );
+        run_with_trace(&mut cpu, true).unwrap();
+
+        let framebuffer = Framebuffer {
+            address: 256,
+            width: 2,
+            height: 2,
+            format: PixelFormat::Rgba,
+        };
+
+        assert_eq!(
+            framebuffer.to_rgb(&cpu.memory).unwrap(),
+            [0, 0x80, 0x40, 1, 0x80, 0x40, 2, 0x80, 0x40, 3, 0x80, 0x40]
+        );
+
+        let gray = Framebuffer {
+            format: PixelFormat::Gray,
+            ..framebuffer
+        };
+        assert_eq!(
+            gray.to_rgb(&cpu.memory).unwrap()[..6],
+            [0, 0, 0, 0x80, 0x80, 0x80]
+        );
+
+        let out_of_memory = Framebuffer {
+            address: memory::MEMORY_SIZE - 4,
+            ..framebuffer
+        };
+        assert!(out_of_memory.to_rgb(&cpu.memory).is_err());
+    }
+}
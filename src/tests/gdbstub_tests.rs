@@ -0,0 +1,177 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dos::*;
+    use crate::gdbstub::*;
+    use crate::simulator::*;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    const PROGRAM: &str = "
+mov cx, 3
+mov bx, 1000
+loop_start:
+add word [bx], 7
+sub cx, 1
+jnz loop_start
+";
+
+    fn new_stub() -> GdbStub {
+        GdbStub::new(Cpu::new(assembler::assemble(PROGRAM).unwrap()))
+    }
+
+    fn packet(stub: &mut GdbStub, data: &str) -> String {
+        match stub.handle_packet(data) {
+            Reply::Packet(reply) => reply,
+            reply => panic!("expected a packet for `{}`, got {:?}", data, reply),
+        }
+    }
+
+    #[test]
+    fn packet_framing() {
+        assert_eq!(encode_packet("OK"), "$OK#9a");
+        assert_eq!(encode_packet(""), "$#00");
+        assert_eq!(checksum("qSupported"), 0x37);
+    }
+
+    #[test]
+    fn registers() {
+        let mut stub = new_stub();
+
+        assert_eq!(packet(&mut stub, "s"), "S05");
+        //* cx = 3, ip = 3, everything else 0
+        let registers = packet(&mut stub, "g");
+        assert_eq!(registers.len(), 16 * 8);
+        assert_eq!(&registers[8..16], "03000000");
+        assert_eq!(&registers[64..72], "03000000");
+
+        assert_eq!(packet(&mut stub, "P0=34120000"), "OK");
+        assert_eq!(packet(&mut stub, "p0"), "34120000");
+        //* es is gdb register 13
+        assert_eq!(packet(&mut stub, "Pd=00020000"), "OK");
+        assert_eq!(stub.cpu.state.segment_registers[0], 0x200);
+        assert_eq!(packet(&mut stub, "p20"), "E45");
+
+        let mut all = "00".repeat(16 * 4);
+        all.replace_range(24..28, "e803");
+        assert_eq!(packet(&mut stub, &format!("G{}", all)), "OK");
+        assert_eq!(stub.cpu.state.registers[3], 1000);
+        assert_eq!(stub.cpu.state.ip, 0);
+    }
+
+    #[test]
+    fn memory() {
+        let mut stub = new_stub();
+
+        //* mov cx, 3
+        assert_eq!(packet(&mut stub, "m0,3"), "b90300");
+        assert_eq!(packet(&mut stub, "M3e8,2:3412"), "OK");
+        assert_eq!(stub.cpu.memory.read_word(0, 1000), 0x1234);
+        assert_eq!(packet(&mut stub, "M3e8,2:34"), "E01");
+        assert_eq!(packet(&mut stub, "mfffff,2"), "E01");
+        assert_eq!(packet(&mut stub, "Mffffffffffffffff,2:0000"), "E01");
+    }
+
+    #[test]
+    fn addresses_are_physical_when_cs_is_not_zero() {
+        let program = assembler::assemble(PROGRAM).unwrap();
+        let services = DosServices::new(std::env::temp_dir(), b"");
+        let mut stub = GdbStub::new(Cpu::load_com(program, services).unwrap());
+
+        //* eip is 0x1000:0x0100, where memory packets find the program
+        assert_eq!(&packet(&mut stub, "g")[64..72], "00010100");
+        assert_eq!(packet(&mut stub, "p8"), "00010100");
+        assert_eq!(packet(&mut stub, "m10100,3"), "b90300");
+
+        assert_eq!(packet(&mut stub, "Z0,10106,1"), "OK");
+        assert_eq!(stub.handle_packet("c"), Reply::Continue);
+        assert_eq!(stub.run(100), Some("S05".to_owned()));
+        assert_eq!(stub.cpu.state.ip, 0x106);
+
+        assert_eq!(packet(&mut stub, "P8=03010100"), "OK");
+        assert_eq!(stub.cpu.state.ip, 0x103);
+        assert_eq!(stub.cpu.state.segment_registers[1], 0x1000);
+    }
+
+    #[test]
+    fn breakpoints_and_continue() {
+        let mut stub = new_stub();
+
+        assert_eq!(packet(&mut stub, "Z0,6,1"), "OK");
+        assert_eq!(stub.handle_packet("c"), Reply::Continue);
+
+        assert_eq!(stub.run(100), Some("S05".to_owned()));
+        assert_eq!(stub.cpu.state.ip, 6);
+        assert_eq!(stub.run(100), Some("S05".to_owned()));
+        assert_eq!(stub.cpu.memory.read_word(0, 1000), 7);
+
+        assert_eq!(packet(&mut stub, "z0,6,1"), "OK");
+        assert_eq!(stub.run(100), Some("W00".to_owned()));
+        assert_eq!(stub.cpu.memory.read_word(0, 1000), 21);
+        assert_eq!(packet(&mut stub, "s"), "W00");
+        assert_eq!(packet(&mut stub, "vMustReplyEmpty"), "");
+    }
+
+    fn read_reply(stream: &mut TcpStream) -> String {
+        let mut reply = Vec::new();
+        let mut byte = [0];
+
+        //* Everything up to and including the checksum after `#`
+        while !reply.contains(&b'#') {
+            stream.read_exact(&mut byte).unwrap();
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        reply.extend_from_slice(&checksum);
+
+        String::from_utf8(reply).unwrap()
+    }
+
+    fn send(stream: &mut TcpStream, data: &str) -> String {
+        stream.write_all(encode_packet(data).as_bytes()).unwrap();
+
+        let mut ack = [0];
+        stream.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+
+        let reply = read_reply(stream);
+        stream.write_all(b"+").unwrap();
+        reply
+    }
+
+    #[test]
+    fn session_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let mut stub = new_stub();
+            serve(&mut stub, &listener).unwrap();
+            stub.cpu.memory.read_word(0, 1000)
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+
+        //* A corrupted packet is asked for again
+        stream.write_all(b"$?#00").unwrap();
+        let mut nack = [0];
+        stream.read_exact(&mut nack).unwrap();
+        assert_eq!(nack[0], b'-');
+
+        assert_eq!(send(&mut stream, "?"), encode_packet("S05"));
+        assert_eq!(send(&mut stream, "Z0,9,1"), encode_packet("OK"));
+        assert_eq!(send(&mut stream, "c"), encode_packet("S05"));
+        assert_eq!(send(&mut stream, "p8"), encode_packet("09000000"));
+        assert_eq!(send(&mut stream, "z0,9,1"), encode_packet("OK"));
+        assert_eq!(send(&mut stream, "c"), encode_packet("W00"));
+        assert_eq!(send(&mut stream, "D"), encode_packet("OK"));
+
+        assert_eq!(server.join().unwrap(), 21);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dos::*;
+    use crate::history::*;
+    use crate::memory::*;
+    use crate::simulator::*;
+
+    use std::fs;
+
+    const PROGRAM: &str = "
+mov cx, 6
+mov bx, 1000
+loop_start:
+add word [bx], 3
+add bx, 2
+sub cx, 1
+jnz loop_start
+";
+
+    fn new_cpu() -> Cpu {
+        Cpu::new(assembler::assemble(PROGRAM).unwrap())
+    }
+
+    //* State and the memory the program writes after executing `steps` instructions
+    fn reference_after(steps: u64) -> (CpuState, Vec<u8>) {
+        let mut cpu = new_cpu();
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+
+        (cpu.state, cpu.memory.range(1000, 12).unwrap().to_vec())
+    }
+
+    fn current(cpu: &Cpu) -> (CpuState, Vec<u8>) {
+        (cpu.state, cpu.memory.range(1000, 12).unwrap().to_vec())
+    }
+
+    #[test]
+    fn back_undoes_registers_and_memory() {
+        let mut cpu = new_cpu();
+        let mut history = History::new(&cpu);
+
+        for _ in 0..10 {
+            history.step(&mut cpu).unwrap();
+        }
+        assert_eq!(current(&cpu), reference_after(10));
+
+        history.back(&mut cpu, 3).unwrap();
+        assert_eq!(history.step, 7);
+        assert_eq!(current(&cpu), reference_after(7));
+
+        //* Executing forward again gets to the same place
+        for _ in 0..3 {
+            history.step(&mut cpu).unwrap();
+        }
+        assert_eq!(current(&cpu), reference_after(10));
+
+        assert!(history.back(&mut cpu, 11).is_err());
+        assert!(history.goto(&mut cpu, 11).is_err());
+    }
+
+    #[test]
+    fn snapshots_reach_past_the_ring_buffer() {
+        let mut cpu = new_cpu();
+        //* Keeps 3 deltas and the 2 latest snapshots taken every 4 steps
+        let mut history = History::with_limits(&cpu, 3, 4, 2);
+
+        for _ in 0..18 {
+            history.step(&mut cpu).unwrap();
+        }
+
+        //* 17 undoes deltas, 13 replays from the step 12 snapshot, 2 replays from the start
+        for target in [17, 13, 2, 0] {
+            history.goto(&mut cpu, target).unwrap();
+            assert_eq!(history.step, target);
+            assert_eq!(current(&cpu), reference_after(target), "step {}", target);
+        }
+
+        while !cpu.is_halted() {
+            history.step(&mut cpu).unwrap();
+        }
+        assert_eq!(current(&cpu), reference_after(history.step));
+        assert_eq!(cpu.memory.read_word(0, 1010), 3);
+    }
+
+    #[test]
+    fn rewind_to_hit_count() {
+        let mut cpu = new_cpu();
+        let mut history = History::new(&cpu);
+
+        while !cpu.is_halted() {
+            history.step(&mut cpu).unwrap();
+        }
+        let end = history.step;
+
+        //* The add at 6 runs for the third time after 2 + 4 * 2 instructions
+        history.rewind_to_hit(&mut cpu, 6, 3).unwrap();
+        assert_eq!(history.step, 10);
+        assert_eq!(cpu.state.ip, 6);
+        assert_eq!(current(&cpu), reference_after(10));
+
+        //* Failing leaves the cpu where it was
+        history.goto(&mut cpu, 0).unwrap();
+        for _ in 0..end {
+            history.step(&mut cpu).unwrap();
+        }
+        assert!(history.rewind_to_hit(&mut cpu, 6, 7).is_err());
+        assert_eq!(history.step, end);
+        assert_eq!(current(&cpu), reference_after(end));
+    }
+
+    #[test]
+    fn last_write_to_address() {
+        let mut cpu = new_cpu();
+        let mut history = History::new(&cpu);
+
+        while !cpu.is_halted() {
+            history.step(&mut cpu).unwrap();
+        }
+
+        //* The high byte of the word at 1002 is written by the second add
+        let record = history.last_write(1003).unwrap();
+        assert_eq!(record.step, 6);
+        assert_eq!(record.instruction.mnemonic, "add");
+        assert_eq!(record.state.registers[3], 1002);
+
+        assert!(history.last_write(1012).is_none());
+    }
+
+    //* Prints `Hi`, reads a key, then opens in.txt, reads 2 bytes of it to 0x300 and closes it
+    const DOS_PROGRAM: &str = "
+mov dx, 512
+mov ah, 9
+int 33
+mov ah, 0
+int 0x16
+mov dx, 528
+mov ax, 0x3d00
+int 33
+mov bx, ax
+mov cx, 2
+mov dx, 768
+mov ah, 0x3f
+int 33
+mov ah, 0x3e
+int 33
+";
+    const DOS_PROGRAM_LENGTH: u64 = 15;
+
+    fn new_dos_cpu(name: &str) -> Cpu {
+        let sandbox = std::env::temp_dir().join(format!(
+            "disassembler_history_{}_{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&sandbox).unwrap();
+        fs::write(sandbox.join("in.txt"), "xyz").unwrap();
+
+        let program = assembler::assemble(DOS_PROGRAM).unwrap();
+        let mut cpu = Cpu::load_com(program, DosServices::new(sandbox, b"ab")).unwrap();
+        for (offset, data) in [(0x200, &b"Hi$"[..]), (0x210, b"in.txt\0")] {
+            cpu.memory
+                .load(Memory::physical_address(COM_SEGMENT, offset), data)
+                .unwrap();
+        }
+
+        cpu
+    }
+
+    fn dos_effects(cpu: &Cpu) -> (Vec<u8>, Vec<u8>, u16) {
+        let services = cpu.services.as_ref().unwrap();
+        (
+            services.output.clone(),
+            services.keyboard.iter().copied().collect(),
+            cpu.memory.read_word(COM_SEGMENT, 0x300),
+        )
+    }
+
+    #[test]
+    fn dos_services_are_undone_with_the_steps() {
+        let mut cpu = new_dos_cpu("undo");
+        let mut history = History::new(&cpu);
+        for _ in 0..DOS_PROGRAM_LENGTH {
+            history.step(&mut cpu).unwrap();
+        }
+        let finished = (b"Hi".to_vec(), b"b".to_vec(), u16::from_le_bytes(*b"xy"));
+        assert_eq!(dos_effects(&cpu), finished);
+
+        //* Back over the close and the read, the file is open again at its start
+        history.back(&mut cpu, 3).unwrap();
+        assert_eq!(dos_effects(&cpu), (b"Hi".to_vec(), b"b".to_vec(), 0));
+        for _ in 0..3 {
+            history.step(&mut cpu).unwrap();
+        }
+        assert_eq!(dos_effects(&cpu), finished);
+
+        //* Back over the output and the key, then forward again
+        history.goto(&mut cpu, 2).unwrap();
+        assert_eq!(dos_effects(&cpu), (Vec::new(), b"ab".to_vec(), 0));
+        history.step(&mut cpu).unwrap();
+        history.back(&mut cpu, 1).unwrap();
+        for _ in 2..DOS_PROGRAM_LENGTH {
+            history.step(&mut cpu).unwrap();
+        }
+        assert_eq!(dos_effects(&cpu), finished);
+    }
+
+    #[test]
+    fn dos_services_are_restored_from_snapshots() {
+        let mut cpu = new_dos_cpu("snapshots");
+        //* Too few deltas to undo the whole program, going back replays from a snapshot
+        let mut history = History::with_limits(&cpu, 2, 4, 2);
+        for _ in 0..DOS_PROGRAM_LENGTH {
+            history.step(&mut cpu).unwrap();
+        }
+
+        for target in [9, 0] {
+            history.goto(&mut cpu, target).unwrap();
+            for _ in target..DOS_PROGRAM_LENGTH {
+                history.step(&mut cpu).unwrap();
+            }
+            assert_eq!(
+                dos_effects(&cpu),
+                (b"Hi".to_vec(), b"b".to_vec(), u16::from_le_bytes(*b"xy")),
+                "from step {}",
+                target
+            );
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::*;
+
+    #[test]
+    fn jump_targets_link_to_label_anchors() {
+        //* mov cx, bx / jne back to the mov
+        let instructions = [0x89, 0xd9, 0x75, 0xfc];
+
+        let mut decoder = Decoder::new();
+        let decoded = decoder.decode_all(&instructions).unwrap();
+        let report = write_html_report("<test>", &instructions, &decoded, &decoder.labels).unwrap();
+
+        assert!(report.contains("<title>&lt;test&gt;</title>"));
+        assert!(report.contains("<tr id=\"label0\">"));
+        assert!(report.contains("<a class=\"jump-target\" href=\"#label0\">label0</a>"));
+        assert!(
+            report.contains("<a href=\"#label0\">label0 <span class=\"offset\">0000</span></a>")
+        );
+        assert!(report.contains("title=\"75 fc\""));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::*;
+    use crate::dataflow::*;
+    use crate::interrupts::*;
+
+    fn annotations(source: &str) -> Vec<String> {
+        let bytes = assembler::assemble(source).unwrap();
+        let decoded = Decoder::new().decode_all(&bytes).unwrap();
+        let cfg = build_cfg(&decoded).unwrap();
+
+        let comments = service_comments(&Dataflow::build(&cfg), &cfg);
+        let mut offsets: Vec<&usize> = comments.keys().collect();
+        offsets.sort();
+
+        offsets
+            .into_iter()
+            .flat_map(|offset| comments[offset].clone())
+            .collect()
+    }
+
+    #[test]
+    fn annotates_services_from_ah_and_al() {
+        let comments = annotations(
+            "
+mov ah, 9
+mov dx, 100
+int 33
+mov ax, 0x4400
+int 33
+mov ax, 1
+int 0x33
+int 33
+xor ah, ah
+int 0x16
+mov ah, 0x44
+mov al, 5
+int 33
+int 32
+",
+        );
+
+        assert_eq!(
+            comments,
+            [
+                "; DOS: print string (AH=09h)",
+                "; DOS: get device information (AH=44h, AL=00h)",
+                "; mouse: show cursor (AX=0001h)",
+                "; BIOS keyboard: read key (AH=00h)",
+                "; DOS: IOCTL (AH=44h)",
+                "; DOS: terminate program",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_looks_within_the_basic_block() {
+        let comments = annotations(
+            "
+mov ah, 9
+jmp short print
+print:
+int 33
+mov ah, 0x4c
+mov bx, ax
+int 33
+",
+        );
+
+        assert_eq!(comments, ["; DOS: terminate with return code (AH=4Ch)"]);
+    }
+
+    #[test]
+    fn writes_we_cannot_follow_forget_the_value() {
+        let comments =
+            annotations("mov ah, 2\nadd ah, bl\nint 33\nmov ax, 0x0200\nmov al, bl\nint 33");
+
+        assert_eq!(comments, ["; DOS: write character (AH=02h)"]);
+    }
+
+    #[test]
+    fn constants_are_followed_through_arithmetic() {
+        let comments = annotations("mov cx, 0x0803\nmov ah, ch\ninc ah\nint 33");
+
+        assert_eq!(comments, ["; DOS: print string (AH=09h)"]);
+    }
+
+    #[test]
+    fn catalogs_are_found_by_vector() {
+        let mut vectors: Vec<u8> = CATALOGS.iter().map(|catalog| catalog.vector).collect();
+        vectors.sort();
+        vectors.dedup();
+        assert_eq!(vectors.len(), CATALOGS.len());
+
+        let ems = find_catalog(0x67).unwrap();
+        assert_eq!(ems.name, "EMS");
+        assert_eq!(
+            ems.find_service(Some(0x46), None).unwrap().name,
+            "get version"
+        );
+
+        //* The mouse driver needs all of AX
+        assert!(MOUSE.find_service(Some(0), None).is_none());
+        assert!(find_catalog(0x99).is_none());
+    }
+}
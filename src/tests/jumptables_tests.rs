@@ -0,0 +1,170 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::*;
+    use crate::jumptables::*;
+
+    //* cmp (3) | ja (2) | add (2) | jmp (4) | table (6) | inc, jmp short | dec, jmp short | inc word | ret
+    const SWITCH: &str = "
+cmp bx, 2
+ja done
+add bx, bx
+jmp word [cs:bx + 11]
+dw case0, case1, case2
+case0:
+inc ax
+jmp short done
+case1:
+dec ax
+jmp short done
+case2:
+inc word [bx]
+done:
+ret
+";
+
+    fn decode(source: &str) -> (Vec<u8>, Decoder, Vec<Instruction>) {
+        let bytes = assembler::assemble(source).unwrap();
+        let mut decoder = Decoder::new();
+        let decoded = decoder.decode_all(&bytes).unwrap();
+
+        (bytes, decoder, decoded)
+    }
+
+    fn listing(bytes: &[u8], decoder: &Decoder, decoded: &[Instruction]) -> String {
+        write_listing(
+            bytes,
+            decoded,
+            &decoder.labels,
+            &Default::default(),
+            &PlainStyle,
+            None,
+        )
+        .unwrap()
+        .concat()
+    }
+
+    #[test]
+    fn recognises_a_bounds_checked_table() {
+        let (_, decoder, _) = decode(SWITCH);
+
+        assert_eq!(
+            decoder.jump_tables,
+            [JumpTable {
+                jump: 7,
+                table: 11,
+                targets: vec![17, 20, 23],
+            }]
+        );
+    }
+
+    #[test]
+    fn tables_are_written_as_labels() {
+        let (bytes, decoder, decoded) = decode(SWITCH);
+        let output = listing(&bytes, &decoder, &decoded);
+
+        assert_eq!(
+            output,
+            "bits 16
+
+cmp bx, 2
+jnbe label0
+add bx, bx
+jmp word [cs:bx + 11]
+dw label1, label2, label3
+label1:
+inc ax
+jmp short label0
+label2:
+dec ax
+jmp short label0
+label3:
+inc word [bx]
+label0:
+ret
+"
+        );
+        assert_eq!(assembler::assemble(&output).unwrap(), bytes);
+    }
+
+    #[test]
+    fn tables_before_the_jump_are_decoded_again() {
+        //* The table bytes decode as adc, so the first pass gets past them
+        let source = "
+jmp short start
+dw case0, case1
+start:
+cmp si, 4
+jnb out
+jmp word [si + 2]
+int 32
+case0:
+inc ax
+ret
+case1:
+dec ax
+out:
+ret
+";
+        let (bytes, decoder, decoded) = decode(source);
+        assert_eq!(decoder.jump_tables[0].targets, [16, 18]);
+
+        let output = listing(&bytes, &decoder, &decoded);
+        assert!(output.contains("jmp short label0\ndw label2, label3\nlabel0:\n"));
+        assert_eq!(assembler::assemble(&output).unwrap(), bytes);
+    }
+
+    #[test]
+    fn com_tables_are_relative_to_the_load_origin() {
+        //* SWITCH as a .COM program has it, loaded at 0x100. The displacement takes two
+        //* bytes now, so everything after the jmp moves up by one
+        let source = SWITCH
+            .replace("[cs:bx + 11]", "[cs:bx + 0x10c]")
+            .replace("dw case0, case1, case2", "dw 0x112, 0x115, 0x118");
+        let bytes = assembler::assemble(&source).unwrap();
+
+        let mut decoder = Decoder::new();
+        decoder.origin = 0x100;
+        let decoded = decoder.decode_all(&bytes).unwrap();
+        assert_eq!(
+            decoder.jump_tables,
+            [JumpTable {
+                jump: 7,
+                table: 12,
+                targets: vec![18, 21, 24],
+            }]
+        );
+        assert_eq!(decoder.decode_reachable(&bytes).last().unwrap().offset, 26);
+
+        let output = listing(&bytes, &decoder, &decoded);
+        assert!(output.contains("dw 0x0112, 0x0115, 0x0118\nlabel1:\ninc ax\n"));
+        assert_eq!(assembler::assemble(&output).unwrap(), bytes);
+
+        //* Read as image offsets the table is past the end
+        assert_eq!(recognise_jump_table(&decoded[..4], &bytes, 0), None);
+    }
+
+    #[test]
+    fn unchecked_indirect_jumps_are_left_alone() {
+        let (_, decoder, decoded) = decode("add bx, bx\njmp word [cs:bx + 6]\ndw 0, 0");
+
+        assert!(decoder.jump_tables.is_empty());
+        assert!(decoded.iter().all(|instruction| instruction.data.is_none()));
+    }
+
+    #[test]
+    fn targets_become_blocks_with_table_edges() {
+        let (_, decoder, decoded) = decode(SWITCH);
+        let cfg = build_cfg_with_jump_tables(&decoded, &decoder.jump_tables).unwrap();
+
+        let table_edges: Vec<(usize, usize)> = cfg
+            .edges()
+            .filter(|edge| edge.kind == EdgeKind::JumpTable)
+            .map(|edge| (edge.from, edge.to))
+            .collect();
+        assert_eq!(table_edges, [(5, 17), (5, 20), (5, 23)]);
+        assert!(cfg.unresolved.is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::*;
+    use crate::simulator::*;
+
+    fn run(source: &str) -> Cpu {
+        let mut cpu = Cpu::new(assembler::assemble(source).unwrap());
+        run_with_trace(&mut cpu, false).unwrap();
+
+        cpu
+    }
+
+    #[test]
+    fn physical_addresses_wrap_at_1_mib() {
+        assert_eq!(Memory::physical_address(0x1234, 0x0010), 0x12350);
+        assert_eq!(Memory::physical_address(0xFFFF, 0x0010), 0x00000);
+        assert_eq!(Memory::physical_address(0xFFFF, 0xFFFF), 0x0FFEF);
+    }
+
+    #[test]
+    fn words_are_little_endian_and_wrap_within_the_segment() {
+        let mut memory = Memory::new();
+
+        memory.write_word(0x0100, 0x0010, 0xBEEF);
+        assert_eq!(memory.read_byte(0x0100, 0x0010), 0xEF);
+        assert_eq!(memory.read_byte(0x0100, 0x0011), 0xBE);
+        assert_eq!(memory.read_word(0x0101, 0x0000), 0xBEEF);
+
+        memory.write_word(0x2000, 0xFFFF, 0x1234);
+        assert_eq!(memory.read_byte(0x2000, 0xFFFF), 0x34);
+        assert_eq!(memory.read_byte(0x2000, 0x0000), 0x12);
+    }
+
+    #[test]
+    fn default_segments() {
+        let address = |rm, segment| EffectiveAddress {
+            rm,
+            displacement: 0,
+            displacement_size: 0,
+            segment,
+        };
+
+        assert_eq!(effective_segment(&address(None, None)), DS);
+        assert_eq!(effective_segment(&address(Some(0b000), None)), DS);
+        assert_eq!(effective_segment(&address(Some(0b111), None)), DS);
+        assert_eq!(effective_segment(&address(Some(0b010), None)), SS);
+        assert_eq!(effective_segment(&address(Some(0b011), None)), SS);
+        assert_eq!(effective_segment(&address(Some(0b110), None)), SS);
+        assert_eq!(effective_segment(&address(Some(0b110), Some(ES))), ES);
+    }
+
+    #[test]
+    fn memory_operands() {
+        let cpu = run("
+mov word [1000], 1
+mov word [1002], 2
+mov bx, 1000
+mov cx, [bx + 2]
+add [bx], cx
+mov al, [bx]
+");
+
+        assert_eq!(cpu.memory.read_word(0, 1000), 3);
+        assert_eq!(cpu.memory.read_word(0, 1002), 2);
+        assert_eq!(cpu.state.registers[1], 2);
+        assert_eq!(cpu.state.registers[0], 3);
+    }
+
+    #[test]
+    fn bp_addresses_use_ss() {
+        let cpu = run("
+mov ax, 0x100
+mov ss, ax
+mov bp, 4
+mov word [bp], 0x1234
+mov word [di + 4], 0x5678
+");
+
+        assert_eq!(cpu.memory.read_word(0x100, 4), 0x1234);
+        assert_eq!(cpu.memory.read_word(0, 4), 0x5678);
+    }
+
+    #[test]
+    fn segment_override() {
+        let cpu = run("
+mov ax, 0x200
+mov es, ax
+mov word [es:4096], 0xABCD
+mov bx, [es:4096]
+mov cx, [4096]
+");
+
+        assert_eq!(cpu.memory.read_word(0x200, 4096), 0xABCD);
+        assert_eq!(cpu.state.registers[3], 0xABCD);
+        assert_eq!(cpu.state.registers[1], 0);
+    }
+
+    #[test]
+    fn segment_override_roundtrips_through_the_decoder() {
+        let bytes = assembler::assemble("mov ax, [es:bx + 4]\nadd word [cs:1000], 7\n").unwrap();
+        assert_eq!(bytes[0], 0x26);
+
+        let listing = decode_instructions(&bytes).unwrap().concat();
+        assert!(listing.contains("mov ax, [es:bx + 4]"));
+        assert!(listing.contains("add [cs:1000], word 7"));
+    }
+}
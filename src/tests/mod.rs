@@ -1,2 +1,50 @@
 #[cfg(test)]
+mod assembler_tests;
+#[cfg(test)]
+mod cfg_tests;
+#[cfg(test)]
+mod clocks_tests;
+#[cfg(test)]
+mod color_tests;
+#[cfg(test)]
+mod dataflow_tests;
+#[cfg(test)]
+mod debugger_tests;
+#[cfg(test)]
 mod decoder_tests;
+#[cfg(test)]
+mod dos_tests;
+#[cfg(test)]
+mod dot_tests;
+#[cfg(test)]
+mod flags_tests;
+#[cfg(test)]
+mod framebuffer_tests;
+#[cfg(test)]
+mod gdbstub_tests;
+#[cfg(test)]
+mod history_tests;
+#[cfg(test)]
+mod html_tests;
+#[cfg(test)]
+mod interrupts_tests;
+#[cfg(test)]
+mod jumptables_tests;
+#[cfg(test)]
+mod memory_tests;
+#[cfg(test)]
+mod procedures_tests;
+#[cfg(test)]
+mod simulator_tests;
+#[cfg(test)]
+mod stack_tests;
+#[cfg(test)]
+mod strings_tests;
+#[cfg(test)]
+mod symbols_tests;
+#[cfg(test)]
+mod trace_tests;
+#[cfg(test)]
+mod verify_tests;
+#[cfg(test)]
+mod xref_tests;
@@ -0,0 +1,117 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::*;
+    use crate::procedures::*;
+
+    const PROGRAM: &str = "
+call helper
+call helper
+call far_helper
+int 32
+helper:
+cmp ax, 0
+je helper_done
+add ax, 1
+helper_done:
+ret
+push bp
+mov bp, sp
+mov ax, 1
+pop bp
+ret 2
+far_helper:
+retf
+";
+
+    fn detect() -> (Vec<u8>, Vec<Instruction>, Decoder, Vec<Procedure>) {
+        let bytes = assembler::assemble(PROGRAM).unwrap();
+        let mut decoder = Decoder::new();
+        let decoded = decoder.decode_all(&bytes).unwrap();
+        let procedures = detect_procedures(&build_cfg(&decoded).unwrap());
+
+        (bytes, decoded, decoder, procedures)
+    }
+
+    #[test]
+    fn finds_call_targets_and_prologues() {
+        let (_, _, _, procedures) = detect();
+
+        //* call (3) x3 | int (2) | cmp (3), je (2), add (3), ret | push, mov (2), mov (3), pop,
+        //* ret 2 (3) | retf
+        assert_eq!(
+            procedures,
+            [
+                Procedure {
+                    entry: 11,
+                    callers: vec![0, 3],
+                    has_prologue: false,
+                    returns: vec![19],
+                },
+                Procedure {
+                    entry: 20,
+                    callers: vec![],
+                    has_prologue: true,
+                    returns: vec![27],
+                },
+                Procedure {
+                    entry: 30,
+                    callers: vec![6],
+                    has_prologue: false,
+                    returns: vec![30],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn listing_groups_procedures_under_their_labels() {
+        let (bytes, decoded, mut decoder, procedures) = detect();
+        name_procedures(&procedures, &mut decoder.labels);
+
+        let listing = write_listing(
+            &bytes,
+            &decoded,
+            &decoder.labels,
+            &procedure_comments(&procedures),
+            &PlainStyle,
+            None,
+        )
+        .unwrap()
+        .concat();
+
+        assert_eq!(
+            listing,
+            "bits 16
+
+call proc_000b
+call proc_000b
+call proc_001e
+int 32
+
+; proc_000b: called from 0x0000, 0x0003
+proc_000b:
+cmp ax, 0
+je label2
+add ax, 1
+label2:
+ret
+
+; proc_0014: no direct callers
+proc_0014:
+push bp
+mov bp, sp
+mov ax, 1
+pop bp
+ret 2
+
+; proc_001e: called from 0x0006
+proc_001e:
+retf
+"
+        );
+        assert_eq!(assembler::assemble(&listing).unwrap(), bytes);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::*;
+    use crate::memory::*;
+    use crate::simulator::*;
+
+    fn run(source: &str) -> (Cpu, String) {
+        let mut cpu = Cpu::new(assembler::assemble(source).unwrap());
+        let trace = run_with_trace(&mut cpu, false).unwrap();
+
+        (cpu, trace)
+    }
+
+    #[test]
+    fn immediate_and_register_movs() {
+        let (_, trace) = run("
+mov ax, 1
+mov bx, 2
+mov ah, 3
+mov es, bx
+mov cx, es
+");
+
+        assert_eq!(
+            trace,
+            "mov ax, 1 ; ax:0x0->0x1 
+mov bx, 2 ; bx:0x0->0x2 
+mov ah, 3 ; ax:0x1->0x301 
+mov es, bx ; es:0x0->0x2 
+mov cx, es ; cx:0x0->0x2 
+
+Final registers:
+      ax: 0x0301 (769)
+      bx: 0x0002 (2)
+      cx: 0x0002 (2)
+      es: 0x0002 (2)
+"
+        );
+    }
+
+    #[test]
+    fn ip_changes_in_trace() {
+        let mut cpu = Cpu::new(
+            assembler::assemble(
+                "
+mov cx, 200
+mov bx, cx
+add cx, 1000
+mov bx, 2000
+sub cx, bx
+",
+            )
+            .unwrap(),
+        );
+        let trace = run_with_trace(&mut cpu, true).unwrap();
+
+        assert_eq!(
+            trace,
+            "mov cx, 200 ; cx:0x0->0xc8 ip:0x0->0x3 
+mov bx, cx ; bx:0x0->0xc8 ip:0x3->0x5 
+add cx, 1000 ; cx:0xc8->0x4b0 ip:0x5->0x9 flags:->A 
+mov bx, 2000 ; bx:0xc8->0x7d0 ip:0x9->0xc 
+sub cx, bx ; cx:0x4b0->0xfce0 ip:0xc->0xe flags:A->CS 
+
+Final registers:
+      bx: 0x07d0 (2000)
+      cx: 0xfce0 (64736)
+      ip: 0x000e (14)
+   flags: CS
+"
+        );
+    }
+
+    #[test]
+    fn trace_uses_the_reference_operand_syntax() {
+        let (_, trace) = run("
+mov word [1000], 1
+mov bx, 1000
+add word [bx + di - 37], 5
+mov cx, [bx + 4]
+mov byte [bp], 7
+mov [es:bx], cx
+mov cx, 2
+loop_start:
+sub cx, 1
+jnz loop_start
+");
+
+        let instructions: Vec<&str> = trace
+            .lines()
+            .map_while(|line| line.split_once(" ; "))
+            .map(|(instruction, _)| instruction)
+            .collect();
+        assert_eq!(
+            instructions,
+            [
+                "mov word [+1000], 1",
+                "mov bx, 1000",
+                "add word [bx+di-37], 5",
+                "mov cx, [bx+4]",
+                "mov byte [bp], 7",
+                "mov word es:[bx], cx",
+                "mov cx, 2",
+                "sub cx, 1",
+                "jne $-5+2",
+                "sub cx, 1",
+                "jne $-5+2",
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_with_conditional_jump() {
+        let (cpu, _) = run("
+mov cx, 3
+mov bx, 1000
+loop_start:
+add bx, 10
+sub cx, 1
+jnz loop_start
+cmp bx, 1030
+");
+
+        assert_eq!(cpu.state.registers[3], 1030);
+        assert_eq!(cpu.state.registers[1], 0);
+        assert!(cpu.get_flag(FLAG_ZERO));
+    }
+
+    #[test]
+    fn byte_registers_share_word_registers() {
+        let mut cpu = Cpu::new(vec![]);
+
+        cpu.set_register(Register { reg: 3, word: true }, 0x1234);
+        cpu.set_register(
+            Register {
+                reg: 7,
+                word: false,
+            },
+            0xAB,
+        );
+
+        assert_eq!(
+            cpu.get_register(Register {
+                reg: 3,
+                word: false
+            }),
+            0x34
+        );
+        assert_eq!(cpu.get_register(Register { reg: 3, word: true }), 0xAB34);
+    }
+
+    #[test]
+    fn calls_return_through_the_stack() {
+        let (cpu, _) = run("
+mov sp, 1000
+call add_two
+jmp short done
+add_two:
+add ax, 2
+ret
+done:
+mov bx, 1
+");
+
+        assert_eq!(cpu.state.registers[0], 2);
+        assert_eq!(cpu.state.registers[3], 1);
+        assert_eq!(cpu.state.registers[4], 1000);
+        //* The return address is left behind on the stack
+        assert_eq!(cpu.memory.read_word(0, 998), 6);
+    }
+
+    #[test]
+    fn push_and_pop_go_through_the_stack() {
+        let (cpu, _) = run("
+mov sp, 1000
+mov ax, 7
+push ax
+push sp
+pop bx
+pop cx
+");
+
+        assert_eq!(cpu.state.registers[1], 7);
+        //* push sp stores sp after the decrement
+        assert_eq!(cpu.state.registers[3], 996);
+        assert_eq!(cpu.state.registers[4], 1000);
+    }
+
+    #[test]
+    fn indirect_calls_and_jumps() {
+        let (cpu, _) = run("
+mov sp, 1000
+mov bx, 19
+mov [500], bx
+call word [500]
+mov bx, 21
+jmp bx
+add_one:
+inc ax
+ret
+done:
+dec cx
+push word [500]
+");
+
+        assert_eq!(cpu.state.registers[0], 1);
+        assert_eq!(cpu.state.registers[1], 0xffff);
+        assert_eq!(cpu.state.registers[4], 998);
+        assert_eq!(cpu.memory.read_word(0, 998), 19);
+    }
+
+    #[test]
+    fn inc_and_dec_keep_the_carry_flag() {
+        let (cpu, _) = run("
+mov ax, 0xffff
+add ax, 1
+inc ax
+mov [100], byte 0x7f
+inc byte [100]
+");
+
+        assert_eq!(cpu.state.registers[0], 1);
+        assert_eq!(cpu.memory.read_byte(0, 100), 0x80);
+        assert!(cpu.get_flag(FLAG_CARRY));
+        assert!(cpu.get_flag(FLAG_OVERFLOW));
+        assert!(cpu.get_flag(FLAG_SIGN));
+    }
+
+    #[test]
+    fn code_at_the_top_of_memory_wraps_around() {
+        let mut cpu = Cpu::new(vec![]);
+        cpu.program_end = 0x10000;
+        cpu.state.segment_registers[CS as usize] = 0xFFFF;
+        cpu.state.ip = 0x0E;
+        //* mov ax, 0x1234 straddles the end of the 1 MiB, jmp short $-3 follows it
+        for (offset, byte) in [0xB8, 0x34, 0x12, 0xEB, 0xFB].into_iter().enumerate() {
+            cpu.memory.write_byte(0xFFFF, 0x0E + offset as u16, byte);
+        }
+        assert_eq!(cpu.memory.read_byte(0, 0), 0x12);
+
+        let instruction = cpu.step().unwrap();
+        assert_eq!(instruction.offset, 0x0E);
+        assert_eq!(instruction.size, 3);
+        assert_eq!(cpu.state.registers[0], 0x1234);
+
+        let instruction = cpu.step().unwrap();
+        assert_eq!(instruction.jump_target(), Some(0x0E));
+        assert_eq!(
+            format_instruction(&instruction, &Default::default()),
+            "jmp short $-3"
+        );
+        assert_eq!(cpu.state.ip, 0x0E);
+    }
+}
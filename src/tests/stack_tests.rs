@@ -0,0 +1,225 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::*;
+    use crate::procedures::*;
+    use crate::stack::*;
+
+    fn analyse(source: &str) -> Vec<ProcedureStack> {
+        let bytes = assembler::assemble(source).unwrap();
+        let decoded = Decoder::new().decode_all(&bytes).unwrap();
+        let cfg = build_cfg(&decoded).unwrap();
+
+        analyse_stacks(&cfg, &detect_procedures(&cfg))
+    }
+
+    #[test]
+    fn frames_and_locals_balance() {
+        let stacks = analyse(
+            "
+push ax
+push bx
+call frame
+ret
+frame:
+push bp
+mov bp, sp
+sub sp, 4
+push si
+pop si
+mov sp, bp
+pop bp
+ret 4
+",
+        );
+
+        //* push ax, push bx, call (3), ret | push bp, mov (2), sub (3), push si, pop si,
+        //* mov (2), pop bp, ret 4
+        assert_eq!(stacks.len(), 2);
+        assert!(stacks[0].warnings.is_empty());
+        assert_eq!(stacks[0].depths[&5], Some(0));
+        let stack = &stacks[1];
+        assert_eq!(stack.entry, 6);
+        assert_eq!(stack.cleanup, 4);
+        assert!(stack.warnings.is_empty());
+        assert_eq!(stack.depths[&12], Some(6));
+        assert_eq!(stack.depths[&14], Some(6));
+        assert_eq!(stack.depths[&17], Some(0));
+    }
+
+    #[test]
+    fn returns_with_pushed_bytes_are_reported() {
+        let stacks = analyse("call leaky\nret\nleaky:\npush ax\npush bx\npop bx\nret");
+
+        //* call (3), ret | push ax, push bx, pop bx, ret
+        assert_eq!(
+            stacks[1].warnings,
+            [StackWarning {
+                offset: 7,
+                kind: StackWarningKind::UnbalancedReturn(2),
+            }]
+        );
+        assert_eq!(
+            stacks[1].warnings[0].message(),
+            "ret with 2 bytes left on the stack"
+        );
+    }
+
+    #[test]
+    fn paths_merging_with_different_depths_are_reported() {
+        let stacks = analyse(
+            "
+call branchy
+ret
+branchy:
+cmp ax, 0
+je skip
+push ax
+skip:
+pop ax
+ret
+",
+        );
+
+        //* call (3), ret | cmp (3), je (2), push ax | pop ax, ret
+        let merges: Vec<&StackWarning> = stacks[1]
+            .warnings
+            .iter()
+            .filter(|warning| matches!(warning.kind, StackWarningKind::Mismatch(..)))
+            .collect();
+        assert_eq!(merges.len(), 1);
+        assert_eq!(merges[0].offset, 10);
+        assert!(matches!(
+            merges[0].kind,
+            StackWarningKind::Mismatch(0, 2) | StackWarningKind::Mismatch(2, 0)
+        ));
+    }
+
+    #[test]
+    fn callee_cleanup_is_applied_at_call_sites() {
+        let stacks = analyse(
+            "
+call caller
+ret
+caller:
+push ax
+push bx
+call callee
+ret
+callee:
+ret 4
+",
+        );
+
+        //* call (3), ret | push ax, push bx, call (3), ret | ret 4
+        assert!(stacks.iter().all(|stack| stack.warnings.is_empty()));
+        assert_eq!(stacks[1].depths[&9], Some(0));
+
+        let comments = stack_comments(&stacks);
+        assert_eq!(
+            comments[&10],
+            ["; proc_000a: callee cleans up 4 bytes of arguments"]
+        );
+        assert!(!comments.contains_key(&4));
+    }
+
+    #[test]
+    fn writes_to_sp_we_cannot_follow_stop_the_check() {
+        let stacks = analyse("call switch\nret\nswitch:\npush ax\nmov sp, bx\nret");
+
+        //* call (3), ret | push ax, mov (2), ret
+        assert!(stacks[1].warnings.is_empty());
+        assert_eq!(stacks[1].depths[&7], None);
+    }
+
+    #[test]
+    fn entry_code_is_checked_up_to_the_exit() {
+        let stacks = analyse(
+            "
+push ax
+cmp bx, 0
+je done
+push cx
+done:
+mov ax, 0x4c00
+int 33
+",
+        );
+
+        //* push ax, cmp (3), je (2), push cx | mov (3), int
+        assert_eq!(stacks.len(), 1);
+        assert_eq!(stacks[0].entry, 0);
+        let warnings: Vec<String> = stacks[0]
+            .warnings
+            .iter()
+            .map(|warning| format!("{}: {}", warning.offset, warning.message()))
+            .collect();
+        assert!(matches!(
+            warnings.as_slice(),
+            [merge, exit] if merge.starts_with("7: paths merge with")
+                && exit.starts_with("10: program exits with")
+        ));
+
+        let balanced = analyse("push ax\npop ax\nint 32");
+        assert_eq!(balanced.len(), 1);
+        assert!(balanced[0].warnings.is_empty());
+    }
+
+    #[test]
+    fn the_entry_code_stops_at_the_exit() {
+        //* push ax, call (3), int | ret
+        let stacks = analyse("push ax\ncall done\nint 32\ndone:\nret");
+        let comments = stack_comments(&stacks);
+        assert_eq!(
+            comments[&4],
+            ["; warning: program exits with 2 bytes left on the stack"]
+        );
+        assert!(!comments.contains_key(&6));
+
+        //* call (3), int | push ax, ret
+        let stacks = analyse("call leaky\nint 32\nleaky:\npush ax\nret");
+        assert_eq!(stacks[0].depths.keys().copied().collect::<Vec<_>>(), [0, 3]);
+        assert_eq!(
+            stack_comments(&stacks)[&6],
+            ["; warning: ret with 2 bytes left on the stack"]
+        );
+    }
+
+    #[test]
+    fn warnings_follow_the_label_of_their_instruction() {
+        let bytes =
+            assembler::assemble("call spin\nint 32\nspin:\npush ax\njmp short spin").unwrap();
+        let mut decoder = Decoder::new();
+        let decoded = decoder.decode_all(&bytes).unwrap();
+        let cfg = build_cfg(&decoded).unwrap();
+        let procedures = detect_procedures(&cfg);
+        name_procedures(&procedures, &mut decoder.labels);
+
+        let mut comments = procedure_comments(&procedures);
+        for (offset, lines) in stack_comments(&analyse_stacks(&cfg, &procedures)) {
+            comments.entry(offset).or_default().extend(lines);
+        }
+        let listing = write_listing(
+            &bytes,
+            &decoded,
+            &decoder.labels,
+            &comments,
+            &PlainStyle,
+            None,
+        )
+        .unwrap()
+        .concat();
+
+        assert!(listing.contains(
+            "int 32
+
+; proc_0005: called from 0x0000
+proc_0005:
+; warning: paths merge with 0 and 2 bytes on the stack
+push ax
+"
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strings::*;
+
+    //* Code up to 12, then one string of each kind
+    const HELLO: &str = "
+mov ah, 9
+mov dx, 12
+int 33
+mov ax, 0x4c00
+int 33
+db 'Hi!', 13, 10, '$'
+db 'abc', 0
+db 4, 'Name'
+";
+
+    #[test]
+    fn finds_strings_after_the_program_exits() {
+        let bytes = assembler::assemble(HELLO).unwrap();
+        let decoder = Decoder::new();
+
+        assert_eq!(decoder.decode_reachable(&bytes).last().unwrap().offset, 10);
+
+        let found = detect_strings(&decoder, &bytes, |_| false);
+        let summary: Vec<(usize, usize, StringTerminator)> = found
+            .iter()
+            .map(|string| (string.start, string.length, string.terminator))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (12, 6, StringTerminator::Dollar),
+                (18, 4, StringTerminator::Nul),
+                (22, 5, StringTerminator::LengthPrefix),
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_are_written_as_text_and_annotated_where_loaded() {
+        let bytes = assembler::assemble(HELLO).unwrap();
+        let mut decoder = Decoder::new();
+        let found = detect_strings(&decoder, &bytes, |_| false);
+        decoder
+            .data_ranges
+            .extend(found.iter().map(FoundString::range));
+
+        let decoded = decoder.decode_all(&bytes).unwrap();
+        let comments = string_comments(&decoded, &bytes, &decoder.data_ranges, 0);
+        let output = write_listing(
+            &bytes,
+            &decoded,
+            &decoder.labels,
+            &comments,
+            &PlainStyle,
+            None,
+        )
+        .unwrap()
+        .concat();
+
+        assert_eq!(
+            output,
+            "bits 16
+
+mov ah, 9
+; dx: 'Hi!', 0x0d, 0x0a, '$'
+mov dx, 12
+int 33
+mov ax, 19456
+int 33
+db 'Hi!', 0x0d, 0x0a, '$'
+db 'abc', 0x00
+db 0x04, 'Name'
+"
+        );
+        assert_eq!(assembler::assemble(&output).unwrap(), bytes);
+    }
+
+    #[test]
+    fn com_addresses_are_relative_to_the_load_origin() {
+        //* A .COM program is loaded at 0x100, so the string at 12 is at 0x10c
+        let bytes = assembler::assemble(&HELLO.replace("mov dx, 12", "mov dx, 0x10c")).unwrap();
+        let mut decoder = Decoder::new();
+        let found = detect_strings(&decoder, &bytes, |_| false);
+        decoder
+            .data_ranges
+            .extend(found.iter().map(FoundString::range));
+        let decoded = decoder.decode_all(&bytes).unwrap();
+
+        let comments = string_comments(&decoded, &bytes, &decoder.data_ranges, 0x100);
+        assert_eq!(comments[&2], ["; dx: 'Hi!', 0x0d, 0x0a, '$'"]);
+        assert!(string_comments(&decoded, &bytes, &decoder.data_ranges, 0).is_empty());
+    }
+
+    #[test]
+    fn jumped_over_text_is_found() {
+        let bytes = assembler::assemble("jmp short over\ndb 'Text$'\nover:\nret").unwrap();
+        let found = detect_strings(&Decoder::new(), &bytes, |_| false);
+
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].start, found[0].length), (2, 5));
+    }
+
+    #[test]
+    fn code_ranges_are_not_searched() {
+        let bytes = assembler::assemble(HELLO).unwrap();
+        let found = detect_strings(&Decoder::new(), &bytes, |offset| offset >= 12);
+
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn short_or_unterminated_text_is_not_a_string() {
+        let found = find_strings(b"ab$\x01xyz\x02abcdefg", |_| true);
+
+        assert!(found.is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::*;
+
+    use std::collections::HashMap;
+
+    const SYMBOLS: &str = "
+# hello.com
+name 0x0000 start
+name 0x000e greet
+name 1000 counter
+code 0 14
+string 0x000f 6
+word 0x0015 2
+comment 0x0000 sets up the counter
+comment 0x0000 and greets twice
+";
+
+    const PROGRAM: &str = "
+mov [1000], word 2
+call greet
+call greet
+int 32
+greet:
+ret
+db 'Hello$'
+dw 0x1234
+";
+
+    fn listing(symbols: &Symbols) -> String {
+        let bytes = assembler::assemble(PROGRAM).unwrap();
+
+        let mut decoder = Decoder::new();
+        decoder.data_ranges = symbols.data_ranges();
+        let decoded = decoder.decode_all(&bytes).unwrap();
+        symbols.apply_names(&mut decoder.labels);
+
+        write_listing(
+            &bytes,
+            &decoded,
+            &decoder.labels,
+            &symbols.listing_comments(),
+            &PlainStyle,
+            None,
+        )
+        .unwrap()
+        .concat()
+    }
+
+    #[test]
+    fn parses_names_ranges_and_comments() {
+        let symbols: Symbols = SYMBOLS.parse().unwrap();
+
+        assert_eq!(symbols.names[&0x0e], "greet");
+        assert_eq!(symbols.names[&1000], "counter");
+        assert_eq!(
+            symbols.ranges[1],
+            SymbolRange {
+                start: 0x0f,
+                length: 6,
+                kind: RangeKind::Data(DataKind::String),
+            }
+        );
+        assert!(symbols.is_code(13));
+        assert!(!symbols.is_code(14));
+        assert_eq!(symbols.comments[&0].len(), 2);
+        assert_eq!(symbols.data_ranges().len(), 2);
+    }
+
+    #[test]
+    fn saved_symbols_load_back() {
+        let symbols: Symbols = SYMBOLS.parse().unwrap();
+
+        let saved = symbols.to_string();
+        assert!(saved.starts_with("name 0x0000 start\n"));
+        assert!(saved.contains("string 0x000f 6\n"));
+        assert_eq!(saved.parse::<Symbols>().unwrap(), symbols);
+    }
+
+    #[test]
+    fn generated_labels_are_saved_without_replacing_names() {
+        let mut symbols: Symbols = "name 0x000b greet".parse().unwrap();
+
+        let labels = HashMap::from([(0x0b, "label0".to_owned()), (0x20, "label1".to_owned())]);
+        symbols.add_labels(&labels);
+
+        assert_eq!(
+            symbols.to_string(),
+            "name 0x000b greet\nname 0x0020 label1\n"
+        );
+    }
+
+    #[test]
+    fn listing_uses_names_data_ranges_and_comments() {
+        let symbols: Symbols = SYMBOLS.parse().unwrap();
+        let output = listing(&symbols);
+
+        assert_eq!(
+            output,
+            "bits 16
+
+counter equ 0x03e8
+
+; sets up the counter
+; and greets twice
+start:
+mov [counter], word 2
+call greet
+call greet
+int 32
+greet:
+ret
+db 'Hello', '$'
+dw 0x1234
+"
+        );
+
+        let bytes = assembler::assemble(PROGRAM).unwrap();
+        assert_eq!(assembler::assemble(&output).unwrap(), bytes);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!("name 0x10".parse::<Symbols>().is_err());
+        assert!("label 0x10 start".parse::<Symbols>().is_err());
+        assert!("name 0x10 two words".parse::<Symbols>().is_err());
+        assert!("byte 0x10000 4".parse::<Symbols>().is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::clocks::*;
+    use crate::simulator::*;
+    use std::fs;
+
+    //* Drops line endings, trailing spaces and the `--- test\listing_00XX execution ---` header
+    fn normalize(trace: &str) -> Vec<&str> {
+        trace
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.starts_with("---"))
+            .collect()
+    }
+
+    fn compare_traces(expected: &str, actual: &str) -> Result<()> {
+        let (expected, actual) = (normalize(expected), normalize(actual));
+
+        for (line_num, (expected_line, actual_line)) in expected.iter().zip(&actual).enumerate() {
+            if expected_line != actual_line {
+                bail!(
+                    "line {}: expected `{}`, got `{}`",
+                    line_num + 1,
+                    expected_line,
+                    actual_line
+                );
+            }
+        }
+
+        if expected.len() != actual.len() {
+            bail!("expected {} lines, got {}", expected.len(), actual.len());
+        }
+
+        Ok(())
+    }
+
+    //* The cycle listings run once per `**** 8086 ****` / `**** 8088 ****` banner,
+    //* each run starting at its `--- ... execution ---` header
+    fn execution_sections(expected: &str) -> Vec<(Option<CpuModel>, String)> {
+        let mut sections: Vec<(Option<CpuModel>, String)> = Vec::new();
+        let mut model = None;
+        let mut in_section = false;
+
+        for line in expected.lines() {
+            if line.starts_with('*') {
+                if line.contains("8086") {
+                    model = Some(CpuModel::I8086);
+                } else if line.contains("8088") {
+                    model = Some(CpuModel::I8088);
+                }
+                in_section = false;
+            } else if line.starts_with("---") {
+                sections.push((model, String::new()));
+                in_section = true;
+            } else if let (true, Some((_, section))) = (in_section, sections.last_mut()) {
+                section.push_str(line);
+                section.push('\n');
+            }
+        }
+
+        sections
+    }
+
+    fn simulate_listing(listing: &str) -> Result<()> {
+        let filepath = format!("{}/{}", FILE_DIR, listing);
+
+        let source = fs::read_to_string(format!("{}.asm", filepath))?;
+        let expected = fs::read_to_string(format!("{}.txt", filepath))?;
+
+        //* ip shows up in the reference traces from listing 0048 on
+        let show_ip = expected.contains("ip:");
+
+        for (model, expected) in execution_sections(&expected) {
+            let mut cpu = Cpu::new(assemble(&source)?);
+            cpu.clock_model = model;
+
+            let actual = run_with_trace(&mut cpu, show_ip)?;
+
+            compare_traces(&expected, &actual)?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sections_per_cpu_model() {
+        let expected = "**************
+**** 8086 ****
+**************
+
+WARNING: Clocks reported by this utility are strictly from the 8086 manual.
+
+--- test\\listing_0056_estimating_cycles execution ---
+mov bx, 1000 ; Clocks: +4 = 4 | bx:0x0->0x3e8 ip:0x0->0x3
+
+**************
+**** 8088 ****
+**************
+
+--- test\\listing_0056_estimating_cycles execution ---
+mov bx, 1000 ; Clocks: +4 = 4 | bx:0x0->0x3e8 ip:0x0->0x3
+";
+
+        let sections = execution_sections(expected);
+
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].0, Some(CpuModel::I8086));
+        assert_eq!(sections[1].0, Some(CpuModel::I8088));
+        assert_eq!(
+            sections[1].1,
+            "mov bx, 1000 ; Clocks: +4 = 4 | bx:0x0->0x3e8 ip:0x0->0x3\n"
+        );
+        assert_eq!(
+            execution_sections("--- x execution ---\nmov ax, 1 ; ax:0x0->0x1\n").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn immediate_movs_trace() {
+        simulate_listing("listing_0043_immediate_movs").unwrap();
+    }
+
+    #[test]
+    fn register_movs_trace() {
+        simulate_listing("listing_0044_register_movs").unwrap();
+    }
+
+    #[test]
+    fn challenge_register_movs_trace() {
+        simulate_listing("listing_0045_challenge_register_movs").unwrap();
+    }
+
+    #[test]
+    fn add_sub_cmp_trace() {
+        simulate_listing("listing_0046_add_sub_cmp").unwrap();
+    }
+
+    #[test]
+    fn challenge_flags_trace() {
+        simulate_listing("listing_0047_challenge_flags").unwrap();
+    }
+
+    #[test]
+    fn ip_register_trace() {
+        simulate_listing("listing_0048_ip_register_sim").unwrap();
+    }
+
+    #[test]
+    fn conditional_jumps_trace() {
+        simulate_listing("listing_0049_conditional_jumps").unwrap();
+    }
+
+    #[test]
+    fn challenge_jumps_trace() {
+        simulate_listing("listing_0050_challenge_jumps").unwrap();
+    }
+
+    #[test]
+    fn memory_mov_trace() {
+        simulate_listing("listing_0051_memory_mov").unwrap();
+    }
+
+    #[test]
+    fn memory_add_loop_trace() {
+        simulate_listing("listing_0052_memory_add_loop").unwrap();
+    }
+
+    #[test]
+    fn add_loop_challenge_trace() {
+        simulate_listing("listing_0053_add_loop_challenge").unwrap();
+    }
+
+    #[test]
+    fn draw_rectangle_trace() {
+        simulate_listing("listing_0054_draw_rectangle").unwrap();
+    }
+
+    #[test]
+    fn challenge_rectangle_trace() {
+        simulate_listing("listing_0055_challenge_rectangle").unwrap();
+    }
+
+    #[test]
+    fn estimating_cycles_trace() {
+        simulate_listing("listing_0056_estimating_cycles").unwrap();
+    }
+
+    #[test]
+    fn challenge_cycles_trace() {
+        simulate_listing("listing_0057_challenge_cycles").unwrap();
+    }
+}
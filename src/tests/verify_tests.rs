@@ -0,0 +1,149 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::*;
+
+    use std::collections::HashMap;
+
+    fn verify_bytes(instructions: &[u8]) -> Vec<EncodingMismatch> {
+        let mut decoder = Decoder::new();
+        let decoded = decoder.decode_all(instructions).unwrap();
+
+        verify_reassembly(instructions, &decoded).unwrap()
+    }
+
+    #[test]
+    fn canonical_encodings_reassemble() {
+        let instructions = [
+            0x89, 0xd9, //* mov cx, bx
+            0x8b, 0x56, 0x00, //* mov dx, [bp]
+            0xc6, 0x03, 0x07, //* mov [bp + di], byte 7
+            0x83, 0xc6, 0xfe, //* add si, -2
+            0xa1, 0xfb, 0x09, //* mov ax, [2555]
+            0x8b, 0x41, 0xdb, //* mov ax, [bx + di + -37]
+            0x3c, 0x05, //* cmp al, 5
+            0x2d, 0xe8, 0x03, //* sub ax, 1000
+            0xc7, 0x85, 0x85, 0x03, 0x5b, 0x01, //* mov [di + 901], word 347
+            0x75, 0xe2, //* jne back to the start
+        ];
+
+        assert_eq!(verify_bytes(&instructions), vec![]);
+    }
+
+    const NON_CANONICAL_INSTRUCTIONS: [u8; 36] = [
+        0x82, 0xc0, 0x05, //* add al, 5 through the 0x82 alias
+        0x8b, 0x46, 0x00, //* mov ax, [bp] already has the 8 bit displacement nasm picks
+        0x8b, 0x87, 0x04, 0x00, //* mov ax, [bx + 4] with a 16 bit displacement
+        0x8b, 0x47, 0x00, //* mov ax, [bx] with a zero 8 bit displacement
+        0x8b, 0xd9, //* mov bx, cx with the direction bit set
+        0x81, 0xc3, 0x02, 0x00, //* add bx, 2 with a 16 bit immediate
+        0x05, 0x05, 0x00, //* add ax, 5 through the accumulator form
+        0x81, 0xc0, 0xe8, 0x03, //* add ax, 1000 without the accumulator form
+        0x83, 0xc0, 0x05, //* add ax, 5
+        0x81, 0x07, 0x05, 0x00, //* add [bx], word 5 with a 16 bit immediate
+        0xc6, 0x07, 0x05, //* mov [bx], byte 5
+    ];
+
+    #[test]
+    fn non_canonical_encodings_are_flagged() {
+        let mismatches = verify_bytes(&NON_CANONICAL_INSTRUCTIONS);
+        let reasons: Vec<_> = mismatches
+            .iter()
+            .map(|mismatch| (mismatch.offset, mismatch.reason.as_str()))
+            .collect();
+
+        assert_eq!(
+            reasons,
+            vec![
+                (0, "0x82 is an alias of 0x80"),
+                (13, "alternate opcode form"),
+                (22, "longer encoding than the canonical form"),
+            ]
+        );
+        assert_eq!(mismatches[0].canonical, vec![0x04, 0x05]);
+        assert_eq!(
+            format_mismatch(&mismatches[1]),
+            "; 0x000d: 8b d9 reassembles as 89 cb (alternate opcode form)"
+        );
+    }
+
+    //* `hlt` at 0, which the encoder doesn't know, then `mov bx, cx` with the direction bit set
+    const WITH_UNENCODABLE: [u8; 3] = [0xf4, 0x8b, 0xd9];
+
+    fn decode_with_unencodable() -> Vec<Instruction> {
+        let mut decoded = Decoder::new().decode_all(&WITH_UNENCODABLE[1..]).unwrap();
+        for ins in &mut decoded {
+            ins.offset += 1;
+        }
+        decoded.insert(
+            0,
+            Instruction {
+                size: 1,
+                mnemonic: "hlt",
+                ..Default::default()
+            },
+        );
+
+        decoded
+    }
+
+    #[test]
+    fn encoder_errors_are_reported_and_checking_goes_on() {
+        let mismatches = verify_reassembly(&WITH_UNENCODABLE, &decode_with_unencodable()).unwrap();
+
+        assert_eq!(mismatches.len(), 2);
+        assert!(mismatches[0].canonical.is_empty());
+        assert_eq!(
+            format_mismatch(&mismatches[0]),
+            "; 0x0000: f4 does not reassemble (`hlt` expects two operands)"
+        );
+        assert_eq!(mismatches[1].offset, 1);
+    }
+
+    #[test]
+    fn unencodable_instructions_are_listed_as_bytes() {
+        let listing = write_listing(
+            &WITH_UNENCODABLE,
+            &decode_with_unencodable(),
+            &HashMap::new(),
+            &HashMap::new(),
+            &PlainStyle,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            listing[1..],
+            ["db 0xf4 ; hlt\n", "db 0x8b, 0xd9 ; mov bx, cx\n"]
+        );
+    }
+
+    #[test]
+    fn size_hints_keep_exact_encodings() {
+        let outputs = decode_instructions(&NON_CANONICAL_INSTRUCTIONS).unwrap();
+
+        assert_eq!(
+            outputs[1..],
+            [
+                "db 0x82, 0xc0, 0x05 ; add al, 5\n",
+                "mov ax, [bp]\n",
+                "mov ax, [word bx + 4]\n",
+                "mov ax, [byte bx]\n",
+                "db 0x8b, 0xd9 ; mov bx, cx\n",
+                "add bx, strict word 2\n",
+                "add ax, strict word 5\n",
+                "db 0x81, 0xc0, 0xe8, 0x03 ; add ax, 1000\n",
+                "add ax, 5\n",
+                "add [bx], strict word 5\n",
+                "mov [bx], byte 5\n",
+            ]
+        );
+
+        assert_eq!(
+            assembler::assemble(&outputs.concat()).unwrap(),
+            NON_CANONICAL_INSTRUCTIONS
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use crate::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xref::*;
+
+    const PROGRAM: &str = "
+mov [1000], word 5
+top:
+add [1000], ax
+cmp [1000], byte 1
+mov bx, [1002]
+call helper
+jne top
+helper:
+ret
+";
+
+    fn build() -> (Vec<Instruction>, XrefIndex) {
+        let bytes = assembler::assemble(PROGRAM).unwrap();
+        let decoded = Decoder::new().decode_all(&bytes).unwrap();
+        let index = XrefIndex::build(&decoded);
+
+        (decoded, index)
+    }
+
+    #[test]
+    fn indexes_jumps_calls_and_memory_accesses() {
+        let (_, index) = build();
+
+        //* mov (6) | add (4), cmp (5), mov bx (4), call (3), jne (2) | ret
+        assert_eq!(index.addresses().collect::<Vec<_>>(), [6, 24, 1000, 1002]);
+        assert_eq!(
+            index.jumps_to(6).map(|xref| xref.from).collect::<Vec<_>>(),
+            [22]
+        );
+        assert_eq!(
+            index.calls_to(24).map(|xref| xref.from).collect::<Vec<_>>(),
+            [19]
+        );
+        assert_eq!(
+            index
+                .writes_to(1000)
+                .map(|xref| xref.from)
+                .collect::<Vec<_>>(),
+            [0, 6]
+        );
+        assert_eq!(
+            index
+                .reads_of(1000)
+                .map(|xref| xref.from)
+                .collect::<Vec<_>>(),
+            [6, 10]
+        );
+        assert_eq!(
+            index.references_to(1002),
+            [Xref {
+                from: 15,
+                mnemonic: "mov",
+                kind: XrefKind::Read
+            }]
+        );
+        assert!(index.references_to(3).is_empty());
+    }
+
+    #[test]
+    fn formats_listing_and_data_comments() {
+        let (decoded, index) = build();
+
+        let comments = index.listing_comments(&decoded);
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[&6], ["; xref: 0x0016 (jne)"]);
+        assert_eq!(comments[&24], ["; xref: 0x0013 (call)"]);
+
+        assert_eq!(
+            index.data_comments(&decoded),
+            [
+                "; 0x03e8 xref: 0x0000 (mov, write), 0x0006 (add, read/write), 0x000a (cmp, read)",
+                "; 0x03ea xref: 0x000f (mov, read)",
+            ]
+        );
+    }
+
+    #[test]
+    fn push_reads_and_pop_writes_its_operand() {
+        let address = |displacement| {
+            Some(Operand::Memory(EffectiveAddress {
+                rm: None,
+                displacement,
+                displacement_size: 2,
+                segment: None,
+            }))
+        };
+        //* push word [1000], pop word [1002]; the assembler has no pop to memory
+        let instructions = [
+            Instruction {
+                mnemonic: "push",
+                operands: [address(1000), None],
+                ..Default::default()
+            },
+            Instruction {
+                mnemonic: "pop",
+                offset: 4,
+                operands: [address(1002), None],
+                ..Default::default()
+            },
+        ];
+        let index = XrefIndex::build(&instructions);
+
+        assert_eq!(
+            index.references_to(1000),
+            [Xref {
+                from: 0,
+                mnemonic: "push",
+                kind: XrefKind::Read
+            }]
+        );
+        assert_eq!(
+            index.references_to(1002),
+            [Xref {
+                from: 4,
+                mnemonic: "pop",
+                kind: XrefKind::Write
+            }]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use crate::encoder::*;
+use crate::instruction::*;
+use crate::prelude::*;
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodingMismatch {
+    pub offset: usize,
+    pub original: Vec<u8>,
+    //* Empty when the encoder rejects the instruction
+    pub canonical: Vec<u8>,
+    pub reason: String,
+}
+
+fn explain_mismatch(original: &[u8], canonical: &[u8]) -> &'static str {
+    if original[0] == 0x82 {
+        return "0x82 is an alias of 0x80";
+    }
+
+    if original.len() == canonical.len() {
+        //* e.g. reg to reg with the direction bit set, or 0x8B 0x06 instead of 0xA1
+        return "alternate opcode form";
+    }
+
+    //* e.g. 0x81 on the accumulator, where nasm always picks 0x05
+    "longer encoding than the canonical form"
+}
+
+//* Re-encodes every decoded instruction and reports the ones that would not
+//* reassemble to the same bytes. Displacement and immediate sizes are kept through
+//* size hints, so what is left are encodings the listing has to emit as raw `db` bytes.
+//* Instructions the encoder rejects are reported with its error and the check goes on
+pub fn verify_reassembly(
+    instructions: &[u8],
+    decoded: &[Instruction],
+) -> Result<Vec<EncodingMismatch>> {
+    let mut mismatches = Vec::new();
+
+    for ins in decoded.iter().filter(|ins| ins.data.is_none()) {
+        let original = &instructions[ins.offset..ins.offset + ins.size];
+        let (canonical, reason) = match encode_instruction(ins) {
+            Ok(canonical) if canonical == original => continue,
+            Ok(canonical) => {
+                let reason = explain_mismatch(original, &canonical).to_owned();
+                (canonical, reason)
+            }
+            Err(error) => (Vec::new(), error.to_string()),
+        };
+
+        mismatches.push(EncodingMismatch {
+            offset: ins.offset,
+            original: original.to_vec(),
+            canonical,
+            reason,
+        });
+    }
+
+    Ok(mismatches)
+}
+
+pub fn format_mismatch(mismatch: &EncodingMismatch) -> String {
+    let mut output = String::new();
+    let _ = if mismatch.canonical.is_empty() {
+        write!(
+            output,
+            "; {:#06x}: {} does not reassemble ({})",
+            mismatch.offset,
+            format_bytes(&mismatch.original),
+            mismatch.reason
+        )
+    } else {
+        write!(
+            output,
+            "; {:#06x}: {} reassembles as {} ({})",
+            mismatch.offset,
+            format_bytes(&mismatch.original),
+            format_bytes(&mismatch.canonical),
+            mismatch.reason
+        )
+    };
+    output
+}
@@ -0,0 +1,163 @@
+use crate::instruction::*;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum XrefKind {
+    //* Conditional jumps, loops and jmp
+    Jump,
+    Call,
+    Read,
+    Write,
+    //* e.g. `add [1000], ax` reads and writes the same address
+    ReadWrite,
+}
+
+impl XrefKind {
+    pub fn reads(self) -> bool {
+        matches!(self, XrefKind::Read | XrefKind::ReadWrite)
+    }
+
+    pub fn writes(self) -> bool {
+        matches!(self, XrefKind::Write | XrefKind::ReadWrite)
+    }
+}
+
+//* A reference from the instruction at `from` to an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Xref {
+    pub from: usize,
+    pub mnemonic: &'static str,
+    pub kind: XrefKind,
+}
+
+//* Who jumps to, calls, reads or writes each address. Data addresses are the offsets of
+//* direct memory operands, e.g. `[1000]`, whatever segment they are used with
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XrefIndex {
+    references: BTreeMap<usize, Vec<Xref>>,
+}
+
+//* What an instruction does with its direct memory operand
+fn memory_access(instruction: &Instruction, is_destination: bool) -> XrefKind {
+    match instruction.mnemonic {
+        _ if !is_destination => XrefKind::Read,
+        "mov" | "pop" => XrefKind::Write,
+        //* Indirect transfers and push only read their operand
+        "cmp" | "call" | "jmp" | "push" => XrefKind::Read,
+        _ => XrefKind::ReadWrite,
+    }
+}
+
+impl XrefIndex {
+    pub fn build(instructions: &[Instruction]) -> Self {
+        let mut index = Self::default();
+
+        for instruction in instructions {
+            for (i, operand) in instruction.operands().enumerate() {
+                let (address, kind) = match operand {
+                    Operand::JumpTarget(target) if instruction.mnemonic == "call" => {
+                        (*target, XrefKind::Call)
+                    }
+                    Operand::JumpTarget(target) => (*target, XrefKind::Jump),
+                    Operand::Memory(address) if address.rm.is_none() => (
+                        address.displacement as u16 as usize,
+                        memory_access(instruction, i == 0),
+                    ),
+                    _ => continue,
+                };
+
+                index.references.entry(address).or_default().push(Xref {
+                    from: instruction.offset,
+                    mnemonic: instruction.mnemonic,
+                    kind,
+                });
+            }
+        }
+
+        index
+    }
+
+    //* Every reference to an address, in instruction order
+    pub fn references_to(&self, address: usize) -> &[Xref] {
+        self.references
+            .get(&address)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn jumps_to(&self, address: usize) -> impl Iterator<Item = &Xref> {
+        self.filtered(address, |kind| kind == XrefKind::Jump)
+    }
+
+    pub fn calls_to(&self, address: usize) -> impl Iterator<Item = &Xref> {
+        self.filtered(address, |kind| kind == XrefKind::Call)
+    }
+
+    pub fn reads_of(&self, address: usize) -> impl Iterator<Item = &Xref> {
+        self.filtered(address, XrefKind::reads)
+    }
+
+    pub fn writes_to(&self, address: usize) -> impl Iterator<Item = &Xref> {
+        self.filtered(address, XrefKind::writes)
+    }
+
+    fn filtered(
+        &self,
+        address: usize,
+        predicate: impl Fn(XrefKind) -> bool,
+    ) -> impl Iterator<Item = &Xref> {
+        self.references_to(address)
+            .iter()
+            .filter(move |xref| predicate(xref.kind))
+    }
+
+    //* Referenced addresses in ascending order
+    pub fn addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.references.keys().copied()
+    }
+
+    //* `; xref: ...` lines before each referenced instruction, for write_listing
+    pub fn listing_comments(&self, instructions: &[Instruction]) -> HashMap<usize, Vec<String>> {
+        instructions
+            .iter()
+            .filter(|instruction| self.references.contains_key(&instruction.offset))
+            .map(|instruction| {
+                let xrefs = self.references_to(instruction.offset);
+                (
+                    instruction.offset,
+                    vec![format!("; {}", format_xrefs(xrefs))],
+                )
+            })
+            .collect()
+    }
+
+    //* `; 0x03e8 xref: ...` lines for referenced addresses that don't start an instruction
+    pub fn data_comments(&self, instructions: &[Instruction]) -> Vec<String> {
+        let starts: BTreeSet<usize> = instructions.iter().map(|ins| ins.offset).collect();
+
+        self.references
+            .iter()
+            .filter(|(address, _)| !starts.contains(address))
+            .map(|(address, xrefs)| format!("; {:#06x} {}", address, format_xrefs(xrefs)))
+            .collect()
+    }
+}
+
+//* `xref: 0x0123 (jne), 0x0200 (call), 0x0210 (mov, write)`
+pub fn format_xrefs(xrefs: &[Xref]) -> String {
+    let entries: Vec<String> = xrefs
+        .iter()
+        .map(|xref| {
+            let access = match xref.kind {
+                XrefKind::Jump | XrefKind::Call => "",
+                XrefKind::Read => ", read",
+                XrefKind::Write => ", write",
+                XrefKind::ReadWrite => ", read/write",
+            };
+            format!("{:#06x} ({}{})", xref.from, xref.mnemonic, access)
+        })
+        .collect();
+
+    format!("xref: {}", entries.join(", "))
+}